@@ -1,81 +1,167 @@
-use air_rs::*;
-use std::collections::HashSet;
+use glam::Vec2;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, env, fs, path};
 
+use crate::spec::air::{self, Action, Clsn, ElementFlags};
+use crate::utils::warn_once;
+
+// A parsed AIR file.
+#[derive(Default)]
+pub struct Air {
+    actions: HashMap<u64, Action>,
+}
+
+// What the current element draws, relative to the char's position. The
+// sprite is None for a blank element (group -1), or when there's no action.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub sprite: Option<(u16, u16)>,
+    pub offset: Vec2,
+    pub flags: ElementFlags,
+}
+
+// The action map is parsed once and shared, so cloning an Animator for a
+// snapshot only copies the playback position. It isn't serialized either,
+// see `share_actions`.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Animator {
-    action_map: HashMap<u64, Action>,
-    time: u64,
+    #[serde(skip)]
+    air: Arc<Air>,
+    // the state owner's actions while in a custom state, for ChangeAnim2
+    #[serde(skip)]
+    owner_air: Option<Arc<Air>>,
+    showing_owner: bool,
+    time: i64, // ticks since the action started, the rest follows from it
     frame_time: i64,
     pub current_action: u64,
     current_element: usize,
     shown_animations: HashSet<String>,
-    loop_start: usize,
-    pub current_total_frames: i32,
 }
 
 impl Animator {
     pub fn new(air_file_path: &str) -> Self {
-        let action_map = Animator::load_air(air_file_path);
+        let air = Arc::new(Animator::load_air(air_file_path));
         Self {
             time: 0,
             current_action: 0,
             current_element: 0,
             frame_time: 0,
             shown_animations: HashSet::new(),
-            loop_start: 0,
-            action_map,
-            current_total_frames: 0,
+            air,
+            owner_air: None,
+            showing_owner: false,
         }
     }
 
-    fn load_air(file_path: &str) -> HashMap<u64, Action> {
+    fn load_air(file_path: &str) -> Air {
         let unparsed_file = fs::read_to_string(file_path).expect("cannot read file");
-        air_rs::parse(&unparsed_file).unwrap()
+        Air {
+            actions: air::parse(&unparsed_file),
+        }
+    }
+
+    fn air(&self) -> &Air {
+        match &self.owner_air {
+            Some(owner_air) if self.showing_owner => owner_air,
+            _ => &self.air,
+        }
+    }
+
+    fn actions(&self) -> &HashMap<u64, Action> {
+        &self.air().actions
+    }
+
+    // None when the AIR doesn't have the starting action 0, every other
+    // action is checked before it's changed to.
+    fn action(&self) -> Option<&Action> {
+        self.actions().get(&self.current_action)
     }
 
     pub fn update(&mut self) {
-        let action = self.action_map.get(&self.current_action).unwrap();
-        //self.current_total_frames = action.elements.iter().map(|e| e.time).sum::<i64>() as i32;
-        let current_frame = action.elements.get(self.current_element).unwrap();
+        self.time += 1;
+        self.seek();
+    }
 
-        self.loop_start = action.loop_start;
-        if (self.time as i32) < self.current_total_frames {
-            self.time += 1;
-        }
-        // dbg!(self.time);
-        self.frame_time += 1;
-        let x = current_frame.x;
-        let y = current_frame.y;
-        if self.frame_time > current_frame.time {
-            self.current_element = if self.current_element + 1 >= action.elements.len() {
-                self.loop_start
-            } else {
-                self.current_element + 1
-            };
-            self.frame_time = 0;
-        }
+    // Puts the current element where `time` says.
+    fn seek(&mut self) {
+        let (element, frame_time) = self
+            .action()
+            .and_then(|action| position(action, self.time))
+            .unwrap_or((0, 0));
+        self.current_element = element;
+        self.frame_time = frame_time;
     }
 
     pub fn set_action(&mut self, action_no: u64) {
+        self.start_action(action_no, false);
+    }
+
+    // ChangeAnim2, plays one of the state owner's actions.
+    pub fn set_owner_action(&mut self, action_no: u64) {
+        if self.owner_air.is_none() {
+            warn_once(format!("ChangeAnim2 to {} outside of a custom state", action_no));
+            return;
+        }
+        self.start_action(action_no, true);
+    }
+
+    // Like MUGEN, changing to an action that doesn't exist keeps the one
+    // that's playing.
+    fn start_action(&mut self, action_no: u64, owner: bool) {
+        let air = match &self.owner_air {
+            Some(owner_air) if owner => owner_air,
+            _ => &self.air,
+        };
+        if !air.actions.contains_key(&action_no) {
+            warn_once(format!(
+                "no action {}, staying in action {}",
+                action_no, self.current_action
+            ));
+            return;
+        }
+        self.showing_owner = owner;
         self.current_action = action_no;
-        self.current_element = 0;
         self.time = 0;
-        let action = self.action_map.get(&self.current_action).unwrap();
-        self.current_total_frames = action.elements.iter().map(|e| e.time).sum::<i64>() as i32;
-        //dbg!("current total frames");
-        //dbg!(self.current_total_frames);
-        //dbg!(action_no);
-        //dbg!(self.time);
-        self.frame_time = 0;
+        self.seek();
+    }
+
+    pub fn draw(&self) -> Option<(u16, u16)> {
+        self.frame().sprite
     }
 
-    pub fn draw(&self) -> (u16, u16) {
-        let action = self.action_map.get(&self.current_action).unwrap();
-        let current_frame = action.elements.get(self.current_element);
-        (
-            current_frame.unwrap().group.try_into().unwrap(),
-            current_frame.unwrap().image.try_into().unwrap(),
-        )
+    pub fn frame(&self) -> Frame {
+        let current_frame = match self
+            .action()
+            .and_then(|action| action.elements.get(self.current_element))
+        {
+            Some(current_frame) => current_frame,
+            None => return Frame::default(),
+        };
+        Frame {
+            sprite: match (
+                u16::try_from(current_frame.group),
+                u16::try_from(current_frame.image),
+            ) {
+                (Ok(group), Ok(image)) => Some((group, image)),
+                _ => None,
+            },
+            offset: Vec2::new(current_frame.x as f32, current_frame.y as f32),
+            flags: current_frame.flags,
+        }
+    }
+
+    // The current element's attack boxes and the boxes it can be hit in.
+    pub fn clsn(&self) -> (&[Clsn], &[Clsn]) {
+        match self
+            .action()
+            .and_then(|action| action.elements.get(self.current_element))
+        {
+            Some(element) => (&element.clsn1, &element.clsn2),
+            None => (&[], &[]),
+        }
     }
 
     // for the triggers
@@ -83,21 +169,106 @@ impl Animator {
         self.current_action
     }
 
+    // Counts up to 0 on the tick the action ends, which is also the tick it
+    // goes back to loopstart, then from minus the loop's length again. An
+    // action that holds on a -1 element ends when it gets there, and AnimTime
+    // goes on counting up from 0.
     pub fn get_anim_time(&self) -> i64 {
-        // dbg!((self.current_total_frames  - self.time as i32) as i64);
-        (self.current_total_frames as i64 - self.time as i64) as i64
+        let action = match self.action() {
+            Some(action) => action,
+            None => return 0,
+        };
+        let starts = start_times(action);
+        if let Some(hold) = action.elements.iter().position(|e| e.time < 0) {
+            return self.time - starts[hold];
+        }
+        let end = match (starts.last(), action.elements.last()) {
+            (Some(start), Some(last)) => start + last.time,
+            _ => return 0,
+        };
+        let loop_length = end - starts.get(action.loop_start).copied().unwrap_or(0);
+        if self.time < end || loop_length <= 0 {
+            return self.time - end;
+        }
+        match (self.time - end) % loop_length {
+            0 => 0,
+            past => past - loop_length,
+        }
     }
 
+    // The current element, from 0.
     pub fn get_anim_element(&self) -> usize {
         self.current_element
     }
-    
-    pub fn set_element(&mut self, animation_element: i32) {
-        self.current_element = animation_element as usize;
+
+    // AnimElemTime, the ticks since element `element_no` (from 1) started in
+    // this pass through the action, negative before it.
+    pub fn get_anim_elem_time(&self, element_no: i32) -> Option<i64> {
+        let starts = start_times(self.action()?);
+        let start = starts.get(usize::try_from(element_no - 1).ok()?)?;
+        Some(starts[self.current_element] + self.frame_time - start)
+    }
+
+    // AnimElemNo, the element (from 1) shown `offset` ticks from now.
+    pub fn get_anim_elem_no(&self, offset: i64) -> Option<i32> {
+        position(self.action()?, self.time + offset).map(|(element, _)| element as i32 + 1)
+    }
+
+    // ChangeAnim's elem, counted from 1. The action plays on from the start
+    // of that element.
+    pub fn set_element(&mut self, element_no: i32) {
+        let starts = match self.action() {
+            Some(action) => start_times(action),
+            None => return,
+        };
+        match usize::try_from(element_no - 1).ok().and_then(|i| starts.get(i)) {
+            Some(start) => {
+                self.time = *start;
+                self.seek();
+            }
+            None => warn_once(format!(
+                "action {} has no element {}",
+                self.current_action, element_no
+            )),
+        }
+    }
+
+    // Points a deserialized animator back at the loaded actions.
+    pub fn share_actions(&mut self, other: &Animator) {
+        self.air = other.air.clone();
+        self.owner_air = other.owner_air.clone();
+    }
+
+    // Makes the actions of the char whose states this one is put in
+    // available to ChangeAnim2.
+    pub fn lend_actions(&mut self, owner: &Animator) {
+        self.owner_air = Some(owner.air.clone());
+    }
+
+    pub fn has_action(&self, action_no: i32) -> bool {
+        match u64::try_from(action_no) {
+            Ok(action_no) => self.air.actions.contains_key(&action_no),
+            Err(_) => false,
+        }
+    }
+
+    // Every sprite the char's own actions show, for checking against the SFF.
+    pub fn sprites(&self) -> BTreeSet<(u16, u16)> {
+        self.air
+            .actions
+            .values()
+            .flat_map(|action| action.elements.iter())
+            .filter_map(|element| {
+                Some((
+                    u16::try_from(element.group).ok()?,
+                    u16::try_from(element.image).ok()?,
+                ))
+            })
+            .collect()
     }
 
     pub fn get_anim_action_no_set(&self) -> HashSet<u64> {
-        let vec = self.action_map.keys().cloned().collect::<Vec<u64>>(); 
+        let vec = self.air.actions.keys().cloned().collect::<Vec<u64>>(); 
         let mut set = HashSet::new(); 
         for i in vec {
             set.insert(i); 
@@ -105,3 +276,164 @@ impl Animator {
         set
     }
 }
+
+// When each element starts, in ticks from the start of the action. A -1
+// element never ends, the ones after it are never reached.
+fn start_times(action: &Action) -> Vec<i64> {
+    let mut start = 0;
+    action
+        .elements
+        .iter()
+        .map(|element| {
+            let element_start = start;
+            start += element.time.max(0);
+            element_start
+        })
+        .collect()
+}
+
+// The element `time` ticks into the action and how long it's been shown.
+// Past the end the action goes round again from loopstart.
+fn position(action: &Action, time: i64) -> Option<(usize, i64)> {
+    if time < 0 || action.elements.is_empty() {
+        return None;
+    }
+    let starts = start_times(action);
+    for (i, element) in action.elements.iter().enumerate() {
+        if element.time < 0 || time < starts[i] + element.time {
+            return Some((i, time - starts[i]));
+        }
+    }
+    let last = action.elements.len() - 1;
+    let end = starts[last] + action.elements[last].time;
+    let loop_time = starts.get(action.loop_start).copied().unwrap_or(0);
+    if end - loop_time <= 0 {
+        // nothing to loop through, the last element stays
+        return Some((last, time - starts[last]));
+    }
+    position(action, loop_time + (time - end) % (end - loop_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kfm_action(action_no: u64) -> Animator {
+        let mut animator = Animator::new("./resources/kfm720.air");
+        animator.set_action(action_no);
+        animator
+    }
+
+    fn run(animator: &mut Animator, ticks: i64) {
+        for _ in 0..ticks {
+            animator.update();
+        }
+    }
+
+    #[test]
+    fn test_frames_last_their_time() {
+        // standing, 10 ticks of the first element then 7 of the second
+        let mut animator = kfm_action(0);
+        assert_eq!(animator.get_anim_time(), -151);
+        run(&mut animator, 9);
+        assert_eq!(animator.get_anim_element(), 0);
+        run(&mut animator, 1);
+        assert_eq!(animator.get_anim_element(), 1);
+        run(&mut animator, 140);
+        assert_eq!(animator.get_anim_element(), 10);
+        assert_eq!(animator.get_anim_time(), -1);
+
+        // the end is 0 and the start of the next pass
+        run(&mut animator, 1);
+        assert_eq!(animator.get_anim_time(), 0);
+        assert_eq!(animator.get_anim_element(), 0);
+        run(&mut animator, 1);
+        assert_eq!(animator.get_anim_time(), -150);
+    }
+
+    #[test]
+    fn test_loopstart() {
+        // jumping up, 7 + 6 * 4 ticks, then the last two elements loop
+        let mut animator = kfm_action(41);
+        run(&mut animator, 39);
+        assert_eq!(animator.get_anim_time(), 0);
+        assert_eq!(animator.get_anim_element(), 7);
+        run(&mut animator, 4);
+        assert_eq!(animator.get_anim_element(), 8);
+        assert_eq!(animator.get_anim_time(), -4);
+        run(&mut animator, 4);
+        assert_eq!(animator.get_anim_element(), 7);
+        assert_eq!(animator.get_anim_time(), 0);
+    }
+
+    #[test]
+    fn test_infinite_frame_holds() {
+        // 8 + 4 + 4 + 4 ticks, then the last element for good
+        let mut animator = kfm_action(105);
+        run(&mut animator, 19);
+        assert_eq!(animator.get_anim_time(), -1);
+        run(&mut animator, 1);
+        assert_eq!(animator.get_anim_element(), 4);
+        assert_eq!(animator.get_anim_time(), 0);
+        run(&mut animator, 500);
+        assert_eq!(animator.get_anim_element(), 4);
+        assert_eq!(animator.get_anim_time(), 500);
+    }
+
+    #[test]
+    fn test_anim_elem_time_and_no() {
+        let mut animator = kfm_action(0);
+        run(&mut animator, 12);
+        assert_eq!(animator.get_anim_elem_time(1), Some(12));
+        assert_eq!(animator.get_anim_elem_time(2), Some(2));
+        assert_eq!(animator.get_anim_elem_time(3), Some(-5));
+        assert_eq!(animator.get_anim_elem_time(12), None);
+        assert_eq!(animator.get_anim_elem_no(0), Some(2));
+        assert_eq!(animator.get_anim_elem_no(5), Some(3));
+        assert_eq!(animator.get_anim_elem_no(-3), Some(1));
+        assert_eq!(animator.get_anim_elem_no(-13), None);
+
+        // relative to this pass through a looping action
+        let mut animator = kfm_action(41);
+        run(&mut animator, 45);
+        assert_eq!(animator.get_anim_elem_time(8), Some(6));
+        assert_eq!(animator.get_anim_elem_time(9), Some(2));
+        assert_eq!(animator.get_anim_elem_no(2), Some(8));
+    }
+
+    #[test]
+    fn test_set_element() {
+        let mut animator = kfm_action(0);
+        run(&mut animator, 5);
+        animator.set_element(3);
+        assert_eq!(animator.get_anim_element(), 2);
+        assert_eq!(animator.get_anim_elem_time(3), Some(0));
+        run(&mut animator, 6);
+        assert_eq!(animator.get_anim_element(), 2);
+        run(&mut animator, 1);
+        assert_eq!(animator.get_anim_element(), 3);
+    }
+
+    #[test]
+    fn test_missing_action_keeps_playing() {
+        let mut animator = kfm_action(0);
+        run(&mut animator, 12);
+        animator.set_action(99999);
+        assert_eq!(animator.get_anim_no(), 0);
+        assert_eq!(animator.get_anim_element(), 1);
+        assert!(!animator.has_action(99999));
+        assert!(!animator.has_action(-1));
+    }
+
+    #[test]
+    fn test_blank_element() {
+        // the third element of action 192 is group -1
+        let mut animator = kfm_action(192);
+        run(&mut animator, 13);
+        assert_eq!(animator.get_anim_element(), 2);
+        assert_eq!(animator.draw(), None);
+        run(&mut animator, 1);
+        assert_eq!(animator.draw(), Some((192, 1)));
+        assert!(animator.sprites().contains(&(192, 1)));
+    }
+}
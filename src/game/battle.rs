@@ -1,11 +1,673 @@
-use crate::spec::controllers::StateController;
-use std::collections::HashMap;
+use super::animation::Animator;
+use super::char::{CharBuilder, CharState};
+use super::env_shake::EnvShake;
+use super::fx::{EnvColor, Ghost, PalFx, PalParams, Render, ScreenEffect};
+use super::hit;
+use super::input::InputFrame;
+use super::pause::Pause;
+use super::push::{self, Bounds};
+use super::target;
+use super::random::Rng;
+use super::state_manager::StateManager;
+use serde::{Deserialize, Serialize};
+use crate::spec::{
+    cmd::{CmdFile, CommandList},
+    cns::CNSFile,
+    constants::char_constants::CharConstants,
+    def::char_def::CharDef,
+    triggers::ExpressionContext,
+};
+
+// There's no camera yet, so the stage is the screen less this much of its
+// width on either side (80 pixels of a 1280 wide one).
+const SCREEN_EDGE: f32 = 1.0 / 16.0;
+const DEFAULT_SCREEN_WIDTH: f32 = 1280.0;
+// Where p1 and p2 start, facing each other.
+const START_X: f32 = 280.0;
 
 // Non Game State
 pub struct BattleSystem {
-    state_map: HashMap<String, StateController>,
+    players: Vec<PlayerSystem>,
+    floor: f32,           // y position physics A chars land on
+    bounds: Bounds,
+}
+
+struct PlayerSystem {
+    def_file_path: String,
+    constants: CharConstants,
+    state_manager: StateManager,
+    expression_context: ExpressionContext,
+}
+
+// Game State. Everything that changes from tick to tick lives here; the
+// expression contexts are rebuilt from it at the start of every tick, so a
+// clone of this is a complete snapshot of the match.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BattleState {
+    pub frame: i32,
+    pub chars: Vec<CharState>,
+    pub rng: Rng,
+    pub pause: Option<(usize, Pause)>, // (the player that paused, pause)
+    pub env_shake: Option<EnvShake>,
+    pub env_color: Option<EnvColor>,
+    pub bg_pal_fx: Option<PalFx>,
+    pub all_pal_fx: Option<PalFx>, // the stage and every char
+}
+
+impl BattleSystem {
+    pub fn new() -> Self {
+        Self {
+            players: Vec::new(),
+            floor: 0.0,
+            bounds: Self::screen_bounds(DEFAULT_SCREEN_WIDTH),
+        }
+    }
+
+    fn screen_bounds(screen_width: f32) -> Bounds {
+        let bound = screen_width * (0.5 - SCREEN_EDGE);
+        Bounds {
+            left: -bound,
+            right: bound,
+        }
+    }
+
+    // Keeps the chars on a screen this wide.
+    pub fn set_screen_width(&mut self, screen_width: f32) {
+        self.bounds = Self::screen_bounds(screen_width);
+    }
+
+    // MUGEN's floor is y = 0, stages that sit higher or lower can move it.
+    pub fn set_floor(&mut self, floor: f32) {
+        self.floor = floor;
+    }
+
+    // How far left and right chars can go before they're cornered.
+    pub fn set_bounds(&mut self, left: f32, right: f32) {
+        self.bounds = Bounds { left, right };
+    }
+
+    // Loads everything the .def points at. Nothing here needs a ggez context,
+    // so a battle can run headless (replays, tests).
+    pub fn add_player(&mut self, def_file_path: &str, screen_width: f32) -> CharState {
+        let char_def = CharDef::new(def_file_path);
+        let files = char_def.get_filenames();
+        let path = |file: Option<String>| char_def.path(&file.expect("missing file in .def"));
+
+        let animator: Animator = Animator::new(&path(files.animations));
+        let cmd_file = CmdFile::new(&path(files.cmd));
+        let command_list = CommandList::new(&cmd_file);
+        let anim_no_set = animator.get_anim_action_no_set();
+        let mut expression_context = ExpressionContext::new(screen_width, anim_no_set);
+        let (char_cns, constants) =
+            CNSFile::new(&path(files.constants.clone())).get_char_constants();
+        expression_context.set_char_constants(&constants);
+        let state_manager = match self
+            .players
+            .iter()
+            .find(|player| player.def_file_path == def_file_path)
+        {
+            // same char again, the statedefs don't need parsing twice
+            Some(player) => player.state_manager.clone(),
+            None => {
+                let input_states = cmd_file.parse_states();
+                let command_states = CNSFile::new(&path(files.common_states)).get_states();
+                let mut char_states = char_cns.get_states();
+                if files.states.is_some() && files.states != files.constants {
+                    char_states.extend(CNSFile::new(&path(files.states)).get_states());
+                }
+                char_states.extend(input_states);
+                char_states.extend(command_states);
+                let state_manager = StateManager::new(char_states);
+                // changing to one of these keeps the anim that's playing
+                let missing_actions: Vec<i32> = state_manager
+                    .referenced_actions()
+                    .into_iter()
+                    .filter(|&action_no| !animator.has_action(action_no))
+                    .collect();
+                if !missing_actions.is_empty() {
+                    eprintln!(
+                        "{}: the states change to actions the AIR doesn't have: {:?}",
+                        def_file_path, missing_actions
+                    );
+                }
+                state_manager
+            }
+        };
+
+        self.players.push(PlayerSystem {
+            def_file_path: def_file_path.to_string(),
+            constants,
+            state_manager,
+            expression_context,
+        });
+
+        let air_juggle = self.players[self.players.len() - 1]
+            .constants
+            .get_float("data.airjuggle")
+            .unwrap_or(15.0) as i32;
+        let facing = if self.players.len() % 2 == 1 { 1.0 } else { -1.0 };
+        CharBuilder::new()
+            .animator(animator)
+            .command_list(command_list)
+            .start_x(-START_X * facing)
+            .facing(facing)
+            .air_juggle(air_juggle)
+            .build()
+    }
+
+    pub fn constants(&self, player: usize) -> &CharConstants {
+        &self.players[player].constants
+    }
+
+    // Swaps in other statedefs, so a test can tell one player's from another's.
+    #[cfg(test)]
+    pub fn set_state_manager(&mut self, player: usize, state_manager: StateManager) {
+        self.players[player].state_manager = state_manager;
+    }
+
+    // One simulation tick. `inputs` holds one frame per player.
+    pub fn update(&mut self, state: &mut BattleState, inputs: Vec<InputFrame>) {
+        let max_power: Vec<i32> = self
+            .players
+            .iter()
+            .map(|player| player.constants.get_float("data.power").unwrap_or(3000.0) as i32)
+            .collect();
+        let paused = state.pause;
+        state.env_shake = state.env_shake.and_then(EnvShake::tick);
+        state.env_color = state.env_color.and_then(EnvColor::tick);
+        state.bg_pal_fx = state.bg_pal_fx.and_then(PalFx::tick);
+        state.all_pal_fx = state.all_pal_fx.and_then(PalFx::tick);
+        for (i, frame) in inputs.into_iter().enumerate() {
+            let targets: Vec<(i32, &CharState)> = state.chars[i]
+                .targets
+                .iter()
+                .map(|target| (target.id, &state.chars[target.player]))
+                .collect();
+            self.players[i].expression_context.update_targets(&targets);
+            let char = &mut state.chars[i];
+            if let Some((owner, pause)) = paused {
+                if pause.freezes(owner, i) {
+                    char.update_input(state.frame, frame);
+                    continue;
+                }
+            }
+            // a thrown char runs the thrower's states
+            let owner = char
+                .state_owner
+                .map(|owner| self.players[owner].state_manager.clone());
+            let player = &mut self.players[i];
+            let state_manager = player.state_manager.clone();
+            char.update(state.frame, frame, &player.constants, self.floor);
+            player.expression_context.update(char);
+            state_manager.update(
+                char,
+                owner.as_ref(),
+                &mut player.expression_context,
+                &mut state.rng,
+                self.floor,
+            );
+            char.tick_hit_pause();
+            if let Some(pause) = char.pause_request.take() {
+                state.pause = Some((i, pause));
+            }
+            for effect in char.screen_effects.drain(..) {
+                match effect {
+                    ScreenEffect::EnvShake(env_shake) => state.env_shake = Some(env_shake),
+                    ScreenEffect::EnvColor(env_color) => state.env_color = Some(env_color),
+                    ScreenEffect::BgPalFx(pal_fx) => state.bg_pal_fx = Some(pal_fx),
+                    ScreenEffect::AllPalFx(pal_fx) => state.all_pal_fx = Some(pal_fx),
+                }
+            }
+            target::apply_effects(&mut state.chars, i, &max_power);
+        }
+        // a pause from this tick starts counting next tick
+        if let Some((owner, pause)) = paused {
+            if state.pause == paused {
+                state.pause = pause.tick().map(|pause| (owner, pause));
+            }
+        }
+        face_p2(&mut state.chars);
+        target::resolve_binds(&mut state.chars);
+        hit::resolve_hits(&mut state.chars, state.pause);
+        let constants: Vec<&CharConstants> =
+            self.players.iter().map(|player| &player.constants).collect();
+        push::resolve(&mut state.chars, &constants, self.bounds);
+        state.frame += 1;
+    }
 }
 
-// Game State
-#[derive(Copy, Clone)]
-pub struct BattleState {}
+// Turns the chars that entered a facep2 state towards the closest other char,
+// once they've all moved.
+fn face_p2(chars: &mut [CharState]) {
+    for i in 0..chars.len() {
+        if !chars[i].face_p2 {
+            continue;
+        }
+        chars[i].face_p2 = false;
+        let x = chars[i].position.x;
+        let closest = chars
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, other)| other.position.x - x)
+            .min_by(|a, b| a.abs().total_cmp(&b.abs()));
+        if let Some(distance) = closest {
+            if distance != 0.0 {
+                chars[i].facing = distance.signum();
+            }
+        }
+    }
+}
+
+impl BattleState {
+    pub fn new(chars: Vec<CharState>, seed: u64) -> Self {
+        Self {
+            frame: 0,
+            chars,
+            rng: Rng::new(seed),
+            pause: None,
+            env_shake: None,
+            env_color: None,
+            bg_pal_fx: None,
+            all_pal_fx: None,
+        }
+    }
+
+    // How to draw the `i`th char, AllPalFX on top of its own PalFX.
+    pub fn render(&self, i: usize) -> Render {
+        let render = self.chars[i].render();
+        Render {
+            pal: render.pal.then(&self.all_pal_params()),
+            ..render
+        }
+    }
+
+    // The `i`th char's after images, oldest last.
+    pub fn after_images(&self, i: usize) -> Vec<(Ghost, Render)> {
+        let after_image = match &self.chars[i].after_image {
+            Some(after_image) => after_image,
+            None => return Vec::new(),
+        };
+        after_image
+            .visible()
+            .into_iter()
+            .map(|(ghost, pal)| {
+                let render = Render {
+                    pal: pal.then(&self.all_pal_params()),
+                    trans: after_image.trans,
+                    rotation: ghost.rotation,
+                    scale: ghost.scale,
+                    offset: ghost.offset,
+                };
+                (ghost, render)
+            })
+            .collect()
+    }
+
+    // The stage's color with BGPalFX and AllPalFX.
+    pub fn background(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let bg = match self.bg_pal_fx {
+            Some(pal_fx) => pal_fx.params(),
+            None => PalParams::default(),
+        };
+        bg.then(&self.all_pal_params()).apply(rgb)
+    }
+
+    fn all_pal_params(&self) -> PalParams {
+        match self.all_pal_fx {
+            Some(pal_fx) => pal_fx.params(),
+            None => PalParams::default(),
+        }
+    }
+
+    pub fn save_state(&self) -> BattleState {
+        self.clone()
+    }
+
+    pub fn load_state(&mut self, snapshot: &BattleState) {
+        self.clone_from(snapshot);
+    }
+
+    // Like `load_state`, for a snapshot that went through serde and so is
+    // missing the data shared with the characters already loaded here.
+    pub fn load_serialized(&mut self, mut snapshot: BattleState) {
+        for (char, loaded) in snapshot.chars.iter_mut().zip(&self.chars) {
+            char.share_data(loaded);
+        }
+        *self = snapshot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::fx::Trans;
+    use crate::game::replay::state_hash;
+    use crate::game::test_utils::battle;
+    use crate::spec::cmd::{Button, ButtonKind, Direction, DirectionKind, Key};
+    use crate::spec::constants::char_constants::ConstantValue;
+    use crate::spec::state::{common_states, MoveType};
+    use glam::Vec2;
+
+    fn hashes(state: &BattleState) -> Vec<u64> {
+        state.chars.iter().map(state_hash).collect()
+    }
+
+    fn input(frame: usize) -> Vec<InputFrame> {
+        let key = Key::Direction(DirectionKind::Single(Direction::F));
+        // held for 20 of every 40 frames, reported the way InputSystem does
+        vec![match frame % 40 {
+            0 => InputFrame::Pressed(key),
+            1..=19 => InputFrame::Held(key),
+            20 => InputFrame::Released(key),
+            _ => InputFrame::NoInput,
+        }]
+    }
+
+    #[test]
+    fn test_walk_uses_velocity_constants() {
+        let (mut system, mut state) = battle(1, 0);
+        let walk_fwd = match system.constants(0).get("velocity.walk.fwd.x") {
+            Some(ConstantValue::Float(f)) => f,
+            _ => panic!("kfm720 has no walk speed"),
+        };
+
+        for frame in 0..10 {
+            system.update(&mut state, input(frame));
+        }
+        assert_eq!(state.chars[0].get_state_no(), common_states::WALK);
+        assert_eq!(state.chars[0].get_velocity().0, walk_fwd);
+
+        // input() lets go of F at frame 20
+        for frame in 10..30 {
+            system.update(&mut state, input(frame));
+        }
+        assert_eq!(state.chars[0].get_state_no(), common_states::STAND);
+        assert_eq!(state.chars[0].get_velocity().0, 0.0);
+    }
+
+    #[test]
+    fn test_left_walks_p2_towards_p1() {
+        let (mut system, mut state) = battle(2, 0);
+        state.chars[0].position.x = -100.0;
+        state.chars[1].position.x = 100.0;
+        let left = Key::Direction(DirectionKind::Single(Direction::B));
+        for frame in 0..10 {
+            let pressed = match frame {
+                0 => InputFrame::Pressed(left.clone()),
+                _ => InputFrame::Held(left.clone()),
+            };
+            system.update(&mut state, vec![InputFrame::NoInput, pressed]);
+        }
+        let p2 = &state.chars[1];
+        assert_eq!(p2.facing, -1.0);
+        assert_eq!(p2.get_state_no(), common_states::WALK);
+        assert!(p2.position.x < 100.0);
+    }
+
+    #[test]
+    fn test_anim_elem_past_the_last_element() {
+        let (mut system, mut state) = battle(1, 0);
+        // kfm720's Light Kung Fu Palm checks AnimElem = 13, and action 1000
+        // only has 12 elements
+        state.chars[0].set_state(1000);
+        system.update(&mut state, vec![InputFrame::NoInput]);
+        assert_eq!(state.chars[0].get_anim_no(), 1000);
+
+        for _ in 0..60 {
+            system.update(&mut state, vec![InputFrame::NoInput]);
+        }
+        assert_eq!(state.chars[0].get_state_no(), common_states::STAND);
+    }
+
+    #[test]
+    fn test_controller_persistency() {
+        let (_, mut state) = battle(1, 0);
+        let char = &mut state.chars[0];
+
+        let fired = |char: &mut CharState, index: usize, persistency: i32| {
+            (0..7)
+                .map(|_| char.persistent_fire(common_states::STAND, index, persistency))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fired(char, 0, 1), [true; 7]);
+        assert_eq!(
+            fired(char, 1, 0),
+            [true, false, false, false, false, false, false]
+        );
+        assert_eq!(
+            fired(char, 2, 3),
+            [true, false, false, true, false, false, true]
+        );
+
+        // entering a state starts over
+        char.set_state(common_states::STAND);
+        assert!(char.persistent_fire(common_states::STAND, 1, 0));
+    }
+
+    #[test]
+    fn test_change_state_runs_new_state_same_tick() {
+        let (mut system, mut state) = battle(1, 0);
+        let x = Key::Button(ButtonKind::Single(Button::x));
+
+        // state -1 changes to 200 on x, whose statedef applies right away
+        system.update(&mut state, vec![InputFrame::Pressed(x)]);
+        let char = &state.chars[0];
+        assert_eq!(char.get_state_no(), 200);
+        assert_eq!(char.get_state_time(), 0);
+        assert_eq!(char.get_anim_no(), 200);
+        assert_eq!(char.get_ctrl(), 0);
+        assert_eq!(char.get_move_type(), MoveType::A);
+
+        system.update(&mut state, vec![InputFrame::NoInput]);
+        assert_eq!(state.chars[0].get_state_time(), 1);
+    }
+
+    #[test]
+    fn test_statedef_parameters() {
+        let (mut system, mut state) = battle(1, 0);
+        let x = Key::Button(ButtonKind::Single(Button::x));
+        state.chars[0].move_hit = 1;
+
+        system.update(&mut state, vec![InputFrame::Pressed(x)]);
+        let char = &state.chars[0];
+        assert_eq!(char.get_state_no(), 200);
+        assert_eq!(char.power, 10);
+        assert_eq!(char.juggle, 1);
+        assert_eq!(char.spr_priority, 2);
+        // movehitpersist defaults to 0
+        assert_eq!(char.move_hit, 0);
+    }
+
+    #[test]
+    fn test_mirror_match_states_are_per_char() {
+        let (mut system, mut state) = battle(2, 0);
+        let x = Key::Button(ButtonKind::Single(Button::x));
+
+        // only p1 punches, and punches again once the first one is 7 ticks in
+        for tick in 0..9 {
+            let p1 = match tick {
+                0 | 8 => InputFrame::Pressed(x.clone()),
+                1 => InputFrame::Released(x.clone()),
+                _ => InputFrame::NoInput,
+            };
+            system.update(&mut state, vec![p1, InputFrame::NoInput]);
+            if tick == 7 {
+                assert_eq!(state.chars[0].get_state_time(), 7);
+            }
+        }
+
+        // changing to the state it's in starts it over
+        assert_eq!(state.chars[0].get_state_no(), 200);
+        assert_eq!(state.chars[0].get_state_time(), 0);
+        assert_eq!(state.chars[0].get_anim_element(), 0);
+        assert_eq!(state.chars[1].get_state_no(), common_states::STAND);
+        assert_eq!(state.chars[1].get_ctrl(), 1);
+    }
+
+    #[test]
+    fn test_hit_pause_freezes_char() {
+        let (mut system, mut state) = battle(1, 0);
+        for frame in 0..5 {
+            system.update(&mut state, input(frame));
+        }
+        assert_eq!(state.chars[0].get_state_no(), common_states::WALK);
+
+        state.chars[0].start_hit_pause(3, true);
+        let (x, time) = (state.chars[0].position.x, state.chars[0].get_state_time());
+        for frame in 5..8 {
+            system.update(&mut state, input(frame));
+            assert_eq!(state.chars[0].position.x, x);
+            assert_eq!(state.chars[0].get_state_time(), time);
+        }
+        assert!(!state.chars[0].in_hit_pause());
+        assert_eq!(state.chars[0].shake_offset(), 0.0);
+
+        system.update(&mut state, input(8));
+        assert!(state.chars[0].position.x > x);
+        assert_eq!(state.chars[0].get_state_time(), time + 1);
+    }
+
+    #[test]
+    fn test_render_effects() {
+        let (mut system, mut state) = battle(1, 0);
+        let half = PalFx {
+            time: 2,
+            mul: [128; 3],
+            ..PalFx::default()
+        };
+        state.chars[0].pal_fx = Some(half);
+        state.chars[0].trans = Trans::Add;
+        state.chars[0].angle = 90.0;
+        state.chars[0].angle_draw = Some(Vec2::new(2.0, 1.0));
+        state.all_pal_fx = Some(PalFx { time: 3, ..half });
+
+        let render = state.render(0);
+        assert_eq!(render.pal.apply([1.0; 3]), [0.25; 3]);
+        assert_eq!(render.trans, Trans::Add);
+        assert_eq!(render.scale, Vec2::new(2.0, 1.0));
+        assert!(render.rotation < 0.0);
+        assert_eq!(state.background([1.0; 3]), [0.5; 3]);
+
+        // Trans and AngleDraw are gone the tick after, the PalFX the one
+        // after that
+        system.update(&mut state, input(1));
+        let render = state.render(0);
+        assert_eq!(render.trans, Trans::Default);
+        assert_eq!(render.rotation, 0.0);
+        assert_eq!(render.pal.apply([1.0; 3]), [0.25; 3]);
+        system.update(&mut state, input(2));
+        assert_eq!(state.render(0).pal.apply([1.0; 3]), [0.5; 3]);
+        assert_eq!(state.chars[0].angle, 90.0);
+    }
+
+    #[test]
+    fn test_render_element_flags() {
+        let (_, mut state) = battle(1, 0);
+
+        // the first element of the turning action is flipped, which undoes
+        // facing left
+        state.chars[0].facing = -1.0;
+        state.chars[0].set_animation_no(5);
+        assert_eq!(state.render(0).scale, Vec2::new(1.0, 1.0));
+        state.chars[0].set_animation_no(0);
+        assert_eq!(state.render(0).scale, Vec2::new(-1.0, 1.0));
+
+        // action 5200 starts flipped both ways and moved up
+        state.chars[0].set_animation_no(5200);
+        let render = state.render(0);
+        assert_eq!(render.scale, Vec2::new(1.0, -1.0));
+        assert_eq!(render.offset, Vec2::new(0.0, -480.0));
+    }
+
+    #[test]
+    fn test_juggle_points_reset_after_get_hit() {
+        let (mut system, mut state) = battle(1, 0);
+        let char = &mut state.chars[0];
+        assert_eq!(char.juggle_points, 15);
+        assert!(char
+            .command_list
+            .commands
+            .iter()
+            .any(|command| command.name == "recovery"));
+
+        char.set_move_type(MoveType::H);
+        assert!(char.take_juggle(10));
+        assert!(!char.take_juggle(10));
+        assert_eq!(char.juggle_points, 5);
+
+        char.set_state(common_states::STAND);
+        system.update(&mut state, input(1));
+        assert_eq!(state.chars[0].get_move_type(), MoveType::I);
+        assert_eq!(state.chars[0].juggle_points, 15);
+    }
+
+    #[test]
+    fn test_jump_lands_on_floor() {
+        let (mut system, mut state) = battle(1, 0);
+        let up = Key::Direction(DirectionKind::Single(Direction::U));
+
+        let mut states = Vec::new();
+        for frame in 0..120 {
+            let input = match frame {
+                0 => InputFrame::Pressed(up.clone()),
+                1..=4 => InputFrame::Held(up.clone()),
+                5 => InputFrame::Released(up.clone()),
+                _ => InputFrame::NoInput,
+            };
+            system.update(&mut state, vec![input]);
+            states.push(state.chars[0].get_state_no());
+            // lands on the tick it reaches the floor, never below it
+            assert!(state.chars[0].get_position().1 <= 0.0, "below the floor");
+        }
+
+        for state_no in [
+            common_states::JUMP_START,
+            common_states::JUMP_UP,
+            common_states::JUMP_LAND,
+        ] {
+            assert!(states.contains(&state_no), "never entered {}", state_no);
+        }
+        assert_eq!(state.chars[0].get_state_no(), common_states::STAND);
+        assert_eq!(state.chars[0].get_position().1, 0.0);
+    }
+
+    #[test]
+    fn test_hop_back_lands_on_its_own() {
+        let (mut system, mut state) = battle(1, 0);
+        state.chars[0].set_state(105);
+
+        let mut states = Vec::new();
+        for _ in 0..30 {
+            system.update(&mut state, vec![InputFrame::NoInput]);
+            states.push(state.chars[0].get_state_no());
+        }
+        // 105 changes to 106 itself, the landing state never gets a look in
+        assert!(states.contains(&106));
+        assert!(!states.contains(&common_states::JUMP_LAND));
+        assert_eq!(state.chars[0].get_position().1, 0.0);
+    }
+
+    #[test]
+    fn test_rewind_and_resimulate() {
+        let (mut system, mut state) = battle(1, 0);
+
+        for frame in 0..30 {
+            system.update(&mut state, input(frame));
+        }
+        let snapshot = state.save_state();
+
+        let mut expected = Vec::new();
+        for frame in 30..90 {
+            system.update(&mut state, input(frame));
+            expected.push(hashes(&state));
+        }
+
+        state.load_state(&snapshot);
+        assert_eq!(state.frame, 30);
+        for (frame, expected) in (30..90).zip(expected) {
+            system.update(&mut state, input(frame));
+            assert_eq!(hashes(&state), expected);
+        }
+    }
+}
@@ -1,48 +1,64 @@
 use super::{
     animation::Animator,
+    fx::{self, AfterImage, Ghost, PalFx, PalParams, Render, ScreenEffect, Trans},
+    gamepad::GamepadRouter,
+    get_hit::GetHitVars,
+    hit::{HitBy, HitDef, HitOverride, ReversalDef, HIT_BY_SLOTS, HIT_OVERRIDE_SLOTS},
     input::{InputFrame, InputState, InputSystem},
+    pause::Pause,
+    target::{Bind, Target, TargetEffect},
 };
-use crate::{
-    cmd::{Direction, DirectionKind},
-    spec::state::Physics,
+use crate::spec::{
+    config::input_config::PlayerBindings,
+    state::{MoveType, Physics},
 };
 use crate::{
     spec::{
-        cmd::{CommandList, Key},
+        cmd::CommandList,
         constants::char_constants::*,
         state::common_states,
         state::StateType,
     },
-    utils::sprite_sheet::SpriteSheet,
+    utils::{sprite_sheet::SpriteSheet, warn_once},
 };
 use std::collections::hash_map::Iter;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::iter::Chain;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 
 use ggez::{
-    event,
+    event::{self, GamepadId},
     glam::*,
     graphics::{self, Color, ImageFormat, Text},
     Context, GameResult,
 };
 pub struct CharSystem {
     sprite_sheet: SpriteSheet,
-    pub constants: CharConstants,
-    input: InputSystem,
+    inputs: Vec<InputSystem>, // one per player at this machine
 }
 
 impl CharSystem {
-    pub fn new(sprite_sheet: SpriteSheet, constants: CharConstants) -> CharSystem {
+    pub fn new(sprite_sheet: SpriteSheet, bindings: Vec<PlayerBindings>) -> CharSystem {
         CharSystem {
             sprite_sheet,
-            constants,
-            input: InputSystem::new(),
+            inputs: bindings.into_iter().map(InputSystem::new).collect(),
         }
     }
 
-    pub fn update(&mut self, context: &mut Context) -> InputFrame {
-        self.input.update(context)
+    pub fn local_players(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn update(
+        &mut self,
+        player: usize,
+        context: &mut Context,
+        gamepads: &GamepadRouter<GamepadId>,
+    ) -> InputFrame {
+        self.inputs[player].update(context, gamepads)
     }
 
     pub fn draw(
@@ -51,29 +67,80 @@ impl CharSystem {
         canvas: &mut graphics::Canvas,
         draw_position: Vec2,
         position: Vec2,
-        group: u16,
-        image: u16,
+        priority: i32,
+        sprite: Option<(u16, u16)>,
+        render: &Render,
     ) {
-        let axis = self.sprite_sheet.get_axis(group, image);
-        let sprite_size = self.sprite_sheet.get_size(group, image);
+        // a blank element, or a sprite the SFF doesn't have, draws nothing
+        let (axis, sprite) = match sprite.and_then(|(group, image)| {
+            Some((
+                self.sprite_sheet.get_axis(group, image)?,
+                self.sprite_sheet.get(group, image)?,
+            ))
+        }) {
+            Some(found) => found,
+            None => return,
+        };
         let size_vec = Vec2::from(size);
 
-        let mut position = position + draw_position;
-        position.x += ((size_vec.x - 320.0) / 2.0) - axis.0 as f32;
-        position.y += (size_vec.y - 240.0) - axis.1 as f32;
-
-        //position -=  Vec2::new(axis.0 as f32, axis.1 as f32);
+        let mut anchor = position + draw_position + render.offset;
+        anchor.x += (size_vec.x - 320.0) / 2.0;
+        anchor.y += size_vec.y - 240.0;
+        // the sprite is scaled and rotated around its top left corner, dest
+        // is moved so the axis stays on the anchor. a mirrored or flipped
+        // sprite grows to the left of or above dest, so the axis goes the
+        // other way
+        let scale = render.scale;
+        let axis = Vec2::new(axis.0 as f32, axis.1 as f32) * scale;
+        let dest = anchor - Vec2::from_angle(render.rotation).rotate(axis);
+
+        // a tint can only take color away, so add, color and invert only
+        // show as far as they change white
+        let (blend_mode, color) = blend(render.trans, render.pal.apply([1.0; 3]));
+        canvas.set_blend_mode(blend_mode);
         canvas.draw(
-            self.sprite_sheet.get(group, image),
-            graphics::DrawParam::new().dest(position),
+            sprite,
+            graphics::DrawParam::new()
+                .dest(dest)
+                .rotation(render.rotation)
+                .scale(scale)
+                .color(color)
+                .z(priority),
         );
+        canvas.set_blend_mode(graphics::BlendMode::ALPHA);
     }
+}
 
-    pub fn constant(&self, name: &str) -> Option<ConstantValue> {
-        self.constants.get(name)
-    }
+// The blend mode and tint that draw a sprite with `trans`, out = source *
+// source alpha + dest * dest alpha. Alpha blending only scales dest by one
+// minus the tint's alpha, so the tint's color makes up the source alpha, and
+// a dest alpha of 256 is plain additive with the source alpha.
+fn blend(trans: Trans, [r, g, b]: [f32; 3]) -> (graphics::BlendMode, Color) {
+    let opaque = Color::new(r, g, b, 1.0);
+    let (source, dest) = match trans {
+        Trans::Add => return (graphics::BlendMode::ADD, opaque),
+        Trans::Sub => return (graphics::BlendMode::SUBTRACT, opaque),
+        Trans::Default | Trans::None => return (graphics::BlendMode::ALPHA, opaque),
+        Trans::Add1 => (256, 128),
+        Trans::AddAlpha(source, dest) => (source, dest),
+    };
+    let (source, dest) = (source as f32 / 256.0, dest as f32 / 256.0);
+    if dest >= 1.0 {
+        return (graphics::BlendMode::ADD, Color::new(r, g, b, source));
+    }
+    let mode = graphics::BlendMode {
+        color: graphics::BlendComponent {
+            src_factor: graphics::BlendFactor::SrcAlpha,
+            dst_factor: graphics::BlendFactor::OneMinusSrcAlpha,
+            operation: graphics::BlendOperation::Add,
+        },
+        ..graphics::BlendMode::ALPHA
+    };
+    let k = source / (1.0 - dest);
+    (mode, Color::new(r * k, g * k, b * k, 1.0 - dest))
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CharState {
     pub animator: Animator,
     pub position: Vec2,
@@ -85,155 +152,147 @@ pub struct CharState {
     pub draw_position: Vec2,
     pub draw_translation: Vec2,
     pub state_physics: Physics,
+    pub pos_freeze: bool, // skip the next move, see PosFreeze
+    pub facing: f32,      // 1.0 facing right, -1.0 facing left
+    // Width and PlayerPush, they only last the tick they're set in
+    pub player_width: Option<(f32, f32)>, // (front, back)
+    pub edge_width: (f32, f32),
+    pub player_push: bool,
     alive: i32,
+    life: i32,
     state_type: StateType,
-    sys_var: [i32; 6],
-    var: [i32; 60],
-    fvar: [f32; 60],
+    move_type: MoveType,
+    sys_var: [i32; NUM_SYS_VARS],
+    sys_fvar: [f32; NUM_SYS_VARS],
+    #[serde(with = "crate::utils::serde_array")]
+    var: [i32; NUM_VARS],
+    #[serde(with = "crate::utils::serde_array")]
+    fvar: [f32; NUM_FVARS],
     input: InputState,
-    pub command_list: CommandList,
-    last_command: Option<String>,
+    #[serde(skip)]
+    pub command_list: Arc<CommandList>,
     pub prev_state_no: i32,
-    pub persistent: i32,
-    pub persistent_counter: i32,
+    // for each state, how many more times each controller's triggers have to
+    // be true before it fires again. empty until the controller first triggers
+    persistence: HashMap<i32, Vec<i32>>,
+    pub hit_pause: i32, // ticks of hitpause left
+    pub hit_shake: bool, // shakes during the hitpause, the one that got hit
+    pub pause_request: Option<Pause>, // from Pause or SuperPause, for the battle
+    // state whose statedef params were applied, NO_STATE after set_state
+    // until the state manager enters the new state
+    pub last_state: i32,
+    // the player whose states this char runs, set by TargetState (a throw)
+    // until SelfState gives it back its own
+    pub state_owner: Option<usize>,
+    pub targets: Vec<Target>,
+    pub target_effects: Vec<(i32, TargetEffect)>, // (target id, effect)
+    pub bind: Option<Bind>,
+    pub power: i32,
+    pub juggle: i32,       // juggle points the attacks of the current state cost
+    pub spr_priority: i32, // chars with a higher one are drawn in front
+    pub face_p2: bool,     // turn towards p2 once every char has moved
+    // the last HitDef and what became of it, kept or cleared by the statedef
+    // persist flags. the HitDef is None once it's hit
+    pub hit_def: Option<HitDef>,
+    pub move_contact: i32,
+    pub move_hit: i32,
+    pub move_guarded: i32,
+    pub hit_count: i32,
+    pub get_hit: GetHitVars, // set by the HitDef that hit the char
+    // juggle points left while the char is being juggled, every air hit takes
+    // the HitDef's state's juggle from them. back to air_juggle once the
+    // char's out of its get-hit states
+    pub juggle_points: i32,
+    pub air_juggle: i32,
+    pub screen_effects: Vec<ScreenEffect>, // for the battle
+    pub hit_by: [Option<HitBy>; HIT_BY_SLOTS],
+    pub hit_overrides: [Option<HitOverride>; HIT_OVERRIDE_SLOTS],
+    pub reversal_def: Option<ReversalDef>,
+    pub pal_fx: Option<PalFx>,
+    pub after_image: Option<AfterImage>,
+    pub angle: f32, // degrees counterclockwise, only drawn with AngleDraw
+    // Trans and AngleDraw only last the tick they're set in
+    pub trans: Trans,
+    pub angle_draw: Option<Vec2>, // scale
 }
 
 impl CharState {
-    pub fn update(&mut self, frame_no: i32, frame: InputFrame, constants: &CharConstants) {
-        self.input.update(frame_no, frame, &self.command_list);
-
-        self.movement(); 
-        self.physics(constants);
-        self.position = self.position + self.velocity;
-        self.animator.update();
-    }
-    /*
-        if self.state_type == StateType::S && self.input.buffered_key(Direction::U) {
-            if self.state_no != 40 {
-                self.set_state(40);
-            }
-        } else if self.state_type == StateType::S
-            && self.state_no != common_states::CROUCHING
-            && self
-                .input
-                .buffered(Key::Direction(DirectionKind::Single(Direction::D)))
-        {
-            if self.state_no == common_states::RUN_FWD {
-                self.set_velocity((0, 0));
-            }
-            // self.set_velocity((0, 25));
-            //self.set_draw_translation(0.0, 10.0);
-            self.set_state(10);
-        } else if self.state_no == common_states::CROUCHING {
-            //self.set_velocity((0, 0));
-            self.set_draw_translation(0.0, 0.0);
-
-            if !self.input.command("holddown") {
-                self.set_state(12);
-            }
-        } else if self.state_no == common_states::RUN_FWD {
-            self.set_velocity((18, 0));
-            self.last_command = Some("FF".to_string());
-        } else if self.state_no == common_states::HOP_BACKWARDS {
-            self.last_command = Some("BB".to_string())
-        } else if self.input.buffered_key((Direction::F)) && !self.is_jumping() {
-            //&& self.state_no != 100{
-            self.set_state(20);
-            let vel_x = match constants.get("velocity.walk.fwd.x") {
-                Some(ConstantValue::Int(vel_x)) => vel_x,
-                Some(ConstantValue::Float(vel_x)) => vel_x as i32,
-                _ => 0,
-            };
-            self.set_velocity((10, 0));
-            self.last_command = Some("F".to_string());
-        } else if self.input.buffered_key(Direction::B) && !self.is_jumping() {
-            //&& self.state_no != 100 {
-            self.set_state(20);
-            self.set_velocity((-9, 0));
-            self.last_command = Some("B".to_string());
-        } else {
-            if let Some(cmd) = &self.last_command {
-                if cmd == "F" || cmd == "B" || cmd == "FF" || cmd == "BB" {
-                    //dbg!("resetting!");
-                    self.set_state(0);
-                    self.set_velocity((0, 0));
-                    self.last_command = None;
-                }
-            }
+    pub fn update(
+        &mut self,
+        frame_no: i32,
+        frame: InputFrame,
+        constants: &CharConstants,
+        floor: f32,
+    ) {
+        self.update_input(frame_no, frame);
+        self.pal_fx = self.pal_fx.and_then(PalFx::tick);
+        // frozen by a hit, only the ignorehitpause controllers run
+        if self.in_hit_pause() {
+            return;
         }
-     */
-    fn is_jumping(&self) -> bool {
-        dbg!(self.state_no); 
-        // !(self.state_type != StateType::A && self.state_physics != Physics::A)
-             !(self.state_no < common_states::JUMP_START
-                || self.state_no > common_states::JUMP_DOWN)
-    }
 
-    fn movement(&mut self) {
-        self.vertical_movement();
-        self.Horizontal_movement(); 
-        self.diagonal_movement(); 
-    }
+        self.trans = Trans::Default;
+        self.angle_draw = None;
 
-    fn jump(&mut self) {
-        if self.state_no != common_states::JUMP_START {
-            self.set_state(common_states::JUMP_START);
+        for hit_by in self.hit_by.iter_mut() {
+            *hit_by = hit_by.take().and_then(HitBy::tick);
         }
-    }
-
-    fn vertical_movement(&mut self) {
-        if self.state_type == StateType::S {
-            if self.input.buffered_key(Direction::U) {
-                self.jump(); 
-            } else if self.state_no != common_states::CROUCHING
-                && self.input.buffered_key(Direction::D)
-            {
-                if self.state_no == common_states::RUN_FWD {
-                    self.set_velocity((0, 0));
-                }
-                self.set_state(common_states::STAND_TO_CROUCH);
-            }
-        } else if self.state_no == common_states::CROUCHING {
-            self.set_draw_translation(0.0, 0.0);
-
-            if !self.input.command("holddown") {
-                self.set_state(common_states::CROUCH_TO_STAND);
-            }
+        for hit_override in self.hit_overrides.iter_mut() {
+            *hit_override = hit_override.take().and_then(HitOverride::tick);
         }
-    }
-
-    fn Horizontal_movement(&mut self) {
-        if self.state_no == common_states::RUN_FWD {
-            self.set_velocity((18, 0));
-            self.last_command = Some("FF".to_string());
-        } else if self.state_no == common_states::HOP_BACKWARDS {
-            self.last_command = Some("BB".to_string())
-        } else if self.input.buffered_key((Direction::F)) && !self.is_jumping() {
-            self.set_state(common_states::WALK);
-            self.set_velocity((10, 0));
-            self.last_command = Some("F".to_string());
-        } else if self.input.buffered_key(Direction::B) && !self.is_jumping() {
-            self.set_state(common_states::WALK);
-            self.set_velocity((-9, 0));
-            self.last_command = Some("B".to_string());
+        self.internal_transitions();
+        if self.pos_freeze {
+            self.pos_freeze = false;
         } else {
-            self.reset_to_neutral(); 
-        }        
-    }
-    
-    fn diagonal_movement(&mut self) {
-        if self.input.buffered_key(Direction::UF) || self.input.buffered_key(Direction::UB){
-            self.jump(); 
+            // x velocities are relative to the way the char faces
+            self.position.x += self.velocity.x * self.facing;
+            self.position.y += self.velocity.y;
+        }
+        self.physics(constants, floor);
+        self.animator.update();
+        if let Some(after_image) = self.after_image.take() {
+            let render = self.render();
+            let ghost = Ghost {
+                position: self.position,
+                sprite: self.animator.draw(),
+                offset: render.offset,
+                rotation: render.rotation,
+                scale: render.scale,
+            };
+            self.after_image = after_image.tick(ghost);
+        }
+        if self.move_type == MoveType::H {
+            self.get_hit.tick();
         }
     }
-
-    fn reset_to_neutral(&mut self) {
-        if let Some(cmd) = &self.last_command {
-            if cmd == "F" || cmd == "B" || cmd == "FF" || cmd == "BB" {
-                self.set_state(common_states::STAND);
-                self.set_velocity((0, 0));
-                self.last_command = None;
+    // The transitions MUGEN does itself instead of through common1.cns. With
+    // ctrl a standing char walks, crouches and jumps, and walking and crouching
+    // end when the direction is let go. The velocities are set by the states,
+    // from the char's [Velocity] constants.
+    fn internal_transitions(&mut self) {
+        if self.ctrl_flag == 0 {
+            return;
+        }
+        match self.state_type {
+            StateType::S => {
+                if self.input.command("holdup") {
+                    self.set_state(common_states::JUMP_START);
+                } else if self.input.command("holddown") {
+                    self.set_state(common_states::STAND_TO_CROUCH);
+                } else if self.input.command("holdfwd") || self.input.command("holdback") {
+                    if self.state_no != common_states::WALK {
+                        self.set_state(common_states::WALK);
+                    }
+                } else if self.state_no == common_states::WALK {
+                    self.set_state(common_states::STAND);
+                }
+            }
+            StateType::C => {
+                if self.state_no == common_states::CROUCHING && !self.input.command("holddown") {
+                    self.set_state(common_states::CROUCH_TO_STAND);
+                }
             }
+            _ => {}
         }
     }
 
@@ -276,58 +335,148 @@ impl CharState {
     */
 
     pub fn get_int_var(&self, idx: usize) -> i32 {
-        self.var[idx]
+        self.var.get(idx).copied().unwrap_or(0)
     }
 
     pub fn set_int_var(&mut self, idx: usize, val: i32) {
-        self.var[idx] = val;
+        set_var(&mut self.var, "var", idx, val);
     }
 
     pub fn get_flaot_var(&self, idx: usize) -> f32 {
-        self.fvar[idx]
+        self.fvar.get(idx).copied().unwrap_or(0.0)
     }
 
     pub fn set_float_var(&mut self, idx: usize, val: f32) {
-        self.fvar[idx] = val;
+        set_var(&mut self.fvar, "fvar", idx, val);
     }
 
     pub fn set_state_physics(&mut self, physics: Physics) {
         self.state_physics = physics;
     }
 
-    pub fn physics(&mut self, constants: &CharConstants) {
-        if self.state_physics == Physics::A {
-            self.gravity(constants);
-            self.land();
+    // Like MUGEN, physics is applied after the char has moved. A state that
+    // was changed to this tick (walking, say) hasn't had its statedef applied
+    // yet, so it gets the physics of the one it came from.
+    pub fn physics(&mut self, constants: &CharConstants, floor: f32) {
+        match self.state_physics {
+            Physics::S => self.friction(
+                constants.get_float("movement.stand.friction").unwrap_or(0.85),
+                constants
+                    .get_float("movement.stand.friction.threshold")
+                    .unwrap_or(8.0),
+            ),
+            Physics::C => self.friction(
+                constants.get_float("movement.crouch.friction").unwrap_or(0.82),
+                constants
+                    .get_float("movement.crouch.friction.threshold")
+                    .unwrap_or(0.2),
+            ),
+            Physics::A => {
+                self.gravity(constants);
+                if self.move_type == MoveType::H {
+                    // falling get-hit states land themselves once they reach
+                    // air.gethit.groundlevel, this only keeps them from
+                    // dropping through the stage if they don't
+                    let ground_level = floor
+                        + constants
+                            .get_float("movement.air.gethit.groundlevel")
+                            .unwrap_or(100.0);
+                    if self.position.y > ground_level {
+                        self.position.y = ground_level;
+                        self.velocity.y = 0.0;
+                    }
+                }
+            }
+            Physics::N | Physics::U => {}
         }
     }
 
-    pub fn land(&mut self) {
-        if self.get_velocity().1 > 28.0 && self.get_state_no() != 105 && self.get_state_no() != 52 {
-            self.set_state(52);
+    fn friction(&mut self, friction: f32, threshold: f32) {
+        self.velocity.x *= friction;
+        if self.velocity.x.abs() < threshold {
+            self.velocity.x = 0.0;
         }
     }
 
-    pub fn gravity(&mut self, constants: &CharConstants) {
-        self.velocity.y += match constants
-            .get("movement.yaccel")
-            .unwrap_or(ConstantValue::Float(1.76))
+    // Physics A chars that are falling and reached the floor go to the
+    // landing state, returns whether this one did. The state manager checks
+    // once the move is done and the state's controllers have had a look, so
+    // states that land on their own (hop back into 106) can.
+    pub fn land(&mut self, floor: f32) -> bool {
+        if self.state_physics != Physics::A
+            || self.move_type == MoveType::H
+            || self.in_hit_pause()
         {
-            ConstantValue::Float(f) => f,
-            ConstantValue::Int(i) => i as f32,
-        };
+            return false;
+        }
+        if self.velocity.y > 0.0 && self.position.y >= floor {
+            self.position.y = floor;
+            self.velocity.y = 0.0;
+            self.set_state(common_states::JUMP_LAND);
+            return true;
+        }
+        false
+    }
+
+    pub fn gravity(&mut self, constants: &CharConstants) {
+        self.velocity.y += constants.get_float("movement.yaccel").unwrap_or(1.76);
+    }
+
+    // Called when the triggers of the `index`th controller of `state_no` are
+    // true, says whether it fires. persistent = 0 fires once per state entry,
+    // persistent = n the first time and then every nth time after that.
+    pub fn persistent_fire(&mut self, state_no: i32, index: usize, persistency: i32) -> bool {
+        let counters = self.persistence.entry(state_no).or_default();
+        if counters.len() <= index {
+            counters.resize(index + 1, 0);
+        }
+        let left = &mut counters[index];
+        match *left {
+            0 => {
+                *left = if persistency > 0 { persistency - 1 } else { -1 };
+                true
+            }
+            -1 => false,
+            _ => {
+                *left -= 1;
+                false
+            }
+        }
+    }
+
+    // Commands keep buffering while the char is frozen.
+    pub fn update_input(&mut self, frame_no: i32, frame: InputFrame) {
+        let frame = frame.facing(self.facing);
+        self.input.update(frame_no, frame, &self.command_list);
     }
 
-    pub fn get_persistent_value(&self) -> i32 {
-        self.persistent
+    pub fn in_hit_pause(&self) -> bool {
+        self.hit_pause > 0
     }
 
-    pub fn increment_persistent_counter(&mut self) {
-        self.persistent_counter += 1;
+    // HitDef's pausetime, the attacker freezes and whoever got hit shakes.
+    pub fn start_hit_pause(&mut self, time: i32, shake: bool) {
+        self.hit_pause = time;
+        self.hit_shake = shake && time > 0;
     }
 
-    pub fn get_persistent_counter(&self) -> i32 {
-        self.persistent_counter
+    // Called once the char's states have run.
+    pub fn tick_hit_pause(&mut self) {
+        if self.hit_pause > 0 {
+            self.hit_pause -= 1;
+        }
+        if self.hit_pause == 0 {
+            self.hit_shake = false;
+        }
+    }
+
+    // How far to draw a shaking char to the side.
+    pub fn shake_offset(&self) -> f32 {
+        match (self.hit_shake, self.hit_pause % 2) {
+            (false, _) => 0.0,
+            (true, 0) => -2.0,
+            (true, _) => 2.0,
+        }
     }
 
     pub fn increment_state_time(&mut self) {
@@ -338,6 +487,13 @@ impl CharState {
         self.state_type = state_type;
     }
 
+    pub fn set_move_type(&mut self, move_type: MoveType) {
+        if self.move_type == MoveType::H && move_type != MoveType::H {
+            self.juggle_points = self.air_juggle;
+        }
+        self.move_type = move_type;
+    }
+
     pub fn set_velocity(&mut self, vel: (i32, i32)) {
         self.velocity.x = vel.0 as f32;
         self.velocity.y = vel.1 as f32;
@@ -349,7 +505,10 @@ impl CharState {
     }
 
     pub fn set_state(&mut self, state_no: i32) {
-        self.persistent_counter = 0;
+        self.persistence.clear();
+        self.reversal_def = None;
+        // changing to the state it's already in starts it over too
+        self.last_state = NO_STATE;
         self.state_time = 0;
         self.prev_state_no = self.state_no;
         self.state_no = state_no;
@@ -377,6 +536,10 @@ impl CharState {
         self.animator.set_action(anim_no as u64);
     }
 
+    pub fn set_owner_animation_no(&mut self, anim_no: i32) {
+        self.animator.set_owner_action(anim_no as u64);
+    }
+
     pub fn set_animation_element(&mut self, element_no: i32) {
         self.animator.set_element(element_no);
     }
@@ -385,7 +548,44 @@ impl CharState {
         self.ctrl_flag = ctrl_flag;
     }
 
-    pub fn draw(&mut self) -> (u16, u16) {
+    // Serialized states leave out the loaded animations and commands, this
+    // takes them from a character built from the same files.
+    pub fn share_data(&mut self, other: &CharState) {
+        self.animator.share_actions(&other.animator);
+        self.command_list = other.command_list.clone();
+    }
+
+    // The palette, blending and geometry the char is drawn with. The current
+    // AIR element's flips, scale and angle go on top of the facing and
+    // AngleDraw, and Trans replaces the element's blending.
+    pub fn render(&self) -> Render {
+        let frame = self.animator.frame();
+        let flags = frame.flags;
+        let flip = Vec2::new(
+            if flags.flip_h { -1.0 } else { 1.0 },
+            if flags.flip_v { -1.0 } else { 1.0 },
+        );
+        let (angle, scale) = match self.angle_draw {
+            Some(scale) => (self.angle, scale),
+            None => (0.0, Vec2::ONE),
+        };
+        Render {
+            pal: match self.pal_fx {
+                Some(pal_fx) => pal_fx.params(),
+                None => PalParams::default(),
+            },
+            trans: match self.trans {
+                Trans::Default => flags.trans.unwrap_or(Trans::Default),
+                trans => trans,
+            },
+            rotation: fx::rotation(flags.angle + angle, self.facing),
+            scale: Vec2::new(self.facing, 1.0) * flip * flags.scale * scale,
+            offset: Vec2::new(frame.offset.x * self.facing, frame.offset.y),
+        }
+    }
+
+    // None while the anim shows no sprite.
+    pub fn draw(&mut self) -> Option<(u16, u16)> {
         self.draw_position = self.draw_position + self.draw_translation;
         self.animator.draw()
     }
@@ -416,6 +616,10 @@ impl CharState {
         self.alive
     }
 
+    pub fn get_life(&self) -> i32 {
+        self.life
+    }
+
     pub fn get_state_no(&self) -> i32 {
         self.state_no
     }
@@ -428,33 +632,119 @@ impl CharState {
         self.state_type
     }
 
+    pub fn get_move_type(&self) -> MoveType {
+        self.move_type
+    }
+
     // this will come from inputstate or wherever
     pub fn command(&self, name: &str) -> bool {
         self.input.command(name)
     }
 
     pub fn sys_var(&self, idx: usize) -> i32 {
-        self.sys_var[idx]
+        self.sys_var.get(idx).copied().unwrap_or(0)
     }
 
     pub fn set_sys_var(&mut self, idx: usize, val: i32) {
-        self.sys_var[idx] = val;
+        set_var(&mut self.sys_var, "sysvar", idx, val);
+    }
+
+    pub fn sys_fvar(&self, idx: usize) -> f32 {
+        self.sys_fvar.get(idx).copied().unwrap_or(0.0)
+    }
+
+    pub fn set_sys_fvar(&mut self, idx: usize, val: f32) {
+        set_var(&mut self.sys_fvar, "sysfvar", idx, val);
     }
 
     pub fn get_move_contact(&self) -> i32 {
-        0 
+        self.move_contact
+    }
+
+    pub fn reset_move_hit(&mut self) {
+        self.move_contact = 0;
+        self.move_hit = 0;
+        self.move_guarded = 0;
+    }
+
+    // Without kill, life can't go below 1.
+    pub fn add_life(&mut self, life: i32, kill: bool) {
+        let min = if kill || self.life <= 0 { 0 } else { 1 };
+        self.life = (self.life + life).max(min);
+    }
+
+    pub fn add_target(&mut self, player: usize, id: i32) {
+        let target = Target { player, id };
+        if !self.targets.contains(&target) {
+            self.targets.push(target);
+        }
+    }
+
+    // id -1 is every target.
+    pub fn queue_target_effect(&mut self, id: i32, effect: TargetEffect) {
+        self.target_effects.push((id, effect));
+    }
+
+    pub fn in_custom_state(&self) -> bool {
+        self.state_owner.is_some()
+    }
+
+    // Whether a hit costing `cost` juggle points can still juggle the char,
+    // taking them if it can.
+    pub fn take_juggle(&mut self, cost: i32) -> bool {
+        if self.juggle_points < cost {
+            return false;
+        }
+        self.juggle_points -= cost;
+        true
+    }
+
+    pub fn can_recover(&self) -> bool {
+        self.get_hit.can_recover()
+    }
+
+    pub fn add_power(&mut self, power: i32, max: i32) {
+        self.power = (self.power + power).clamp(0, max);
+    }
+
+    pub fn reset_push_overrides(&mut self) {
+        self.player_width = None;
+        self.edge_width = (0.0, 0.0);
+        self.player_push = true;
+    }
+}
+
+// last_state of a char whose new state hasn't been entered yet
+pub const NO_STATE: i32 = i32::MIN;
+
+// MUGEN's variable counts
+pub const NUM_VARS: usize = 60;
+pub const NUM_FVARS: usize = 40;
+pub const NUM_SYS_VARS: usize = 5;
+
+// An index out of range is a mistake in the char's files, it shouldn't take
+// the game down.
+fn set_var<T>(vars: &mut [T], name: &str, idx: usize, val: T) {
+    let len = vars.len();
+    match vars.get_mut(idx) {
+        Some(var) => *var = val,
+        None => warn_once(format!("{}({}) is out of range, there are {}", name, idx, len)),
     }
 }
 
 pub struct CharBuilder {
     animator: Option<Animator>,
     position: Vec2,
+    start_x: f32,
+    facing: f32,
     direction: Vec2,
     velocity: Vec2,
     ctrl_flag: i32,
     state_no: i32,
     state_time: i32,
     alive: i32,
+    life: i32,
+    air_juggle: i32,
     command_list: Option<CommandList>,
 }
 
@@ -463,12 +753,16 @@ impl CharBuilder {
         Self {
             animator: None,
             position: Vec2::new(80.0, 150.0),
+            start_x: 0.0,
+            facing: 1.0,
             direction: Vec2::new(0.0, 0.0),
             velocity: Vec2::new(0.0, 0.0),
             ctrl_flag: 1,
             state_no: 0,
             state_time: 0,
             alive: 1,
+            life: 1000,
+            air_juggle: 15,
             command_list: None,
         }
     }
@@ -482,10 +776,30 @@ impl CharBuilder {
         self
     }
 
+    pub fn life(mut self, life: i32) -> Self {
+        self.life = life;
+        self
+    }
+
+    pub fn air_juggle(mut self, air_juggle: i32) -> Self {
+        self.air_juggle = air_juggle;
+        self
+    }
+
+    pub fn start_x(mut self, x: f32) -> Self {
+        self.start_x = x;
+        self
+    }
+
+    pub fn facing(mut self, facing: f32) -> Self {
+        self.facing = facing;
+        self
+    }
+
     pub fn build(self) -> CharState {
         CharState {
             animator: self.animator.unwrap(),
-            position: Vec2::new(0.0, 0.0),
+            position: Vec2::new(self.start_x, 0.0),
             direction: self.direction,
             velocity: self.velocity,
             ctrl_flag: self.ctrl_flag,
@@ -493,18 +807,53 @@ impl CharBuilder {
             prev_state_no: -1, // remember that you did this.
             state_time: self.state_time,
             alive: self.alive,
+            life: self.life,
             state_type: StateType::default(),
-            sys_var: [0; 6],
-            var: [0; 60],
-            fvar: [0.0; 60],
+            move_type: MoveType::default(),
+            sys_var: [0; NUM_SYS_VARS],
+            sys_fvar: [0.0; NUM_SYS_VARS],
+            var: [0; NUM_VARS],
+            fvar: [0.0; NUM_FVARS],
             input: InputState::new(),
-            command_list: self.command_list.unwrap(),
-            last_command: None,
+            command_list: Arc::new(self.command_list.unwrap()),
             draw_position: self.position,
             draw_translation: Vec2::new(0.0, 0.0),
             state_physics: Physics::S,
-            persistent: 1,
-            persistent_counter: 0,
+            pos_freeze: false,
+            facing: self.facing,
+            player_width: None,
+            edge_width: (0.0, 0.0),
+            player_push: true,
+            persistence: HashMap::new(),
+            hit_pause: 0,
+            hit_shake: false,
+            pause_request: None,
+            last_state: self.state_no,
+            state_owner: None,
+            targets: Vec::new(),
+            target_effects: Vec::new(),
+            bind: None,
+            power: 0,
+            juggle: 0,
+            spr_priority: 0,
+            face_p2: false,
+            hit_def: None,
+            move_contact: 0,
+            move_hit: 0,
+            move_guarded: 0,
+            hit_count: 0,
+            get_hit: GetHitVars::default(),
+            juggle_points: self.air_juggle,
+            air_juggle: self.air_juggle,
+            screen_effects: Vec::new(),
+            hit_by: Default::default(),
+            hit_overrides: Default::default(),
+            reversal_def: None,
+            pal_fx: None,
+            after_image: None,
+            angle: 0.0,
+            trans: Trans::Default,
+            angle_draw: None,
         }
     }
 }
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+// Shakes the screen up and down for `time` ticks. freq and phase are in
+// degrees, ampl in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnvShake {
+    pub time: i32,
+    pub freq: f32,
+    pub ampl: f32,
+    pub phase: f32,
+}
+
+impl Default for EnvShake {
+    fn default() -> Self {
+        Self {
+            time: 0,
+            freq: 60.0,
+            ampl: -4.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl EnvShake {
+    // How far everything is moved down this tick.
+    pub fn offset(&self) -> f32 {
+        self.ampl * self.phase.to_radians().sin()
+    }
+
+    // Counts down a tick, None once it's over.
+    pub fn tick(self) -> Option<EnvShake> {
+        if self.time <= 1 {
+            return None;
+        }
+        Some(EnvShake {
+            time: self.time - 1,
+            phase: (self.phase + self.freq) % 360.0,
+            ..self
+        })
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+use std::str::FromStr;
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::env_shake::EnvShake;
+
+// PalFX, BGPalFX and AllPalFX. Colors become
+// (gray(color) inverted + add + sinadd) * mul / 256 for `time` ticks, -1 is
+// until it's replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PalFx {
+    pub time: i32,
+    pub add: [i32; 3],
+    pub mul: [i32; 3],
+    pub sin_add: [i32; 3],
+    pub sin_period: i32,
+    pub invert: bool,
+    pub color: i32, // 256 is full color, 0 gray
+    pub elapsed: i32,
+}
+
+impl Default for PalFx {
+    fn default() -> Self {
+        Self {
+            time: 0,
+            add: [0; 3],
+            mul: [256; 3],
+            sin_add: [0; 3],
+            sin_period: 0,
+            invert: false,
+            color: 256,
+            elapsed: 0,
+        }
+    }
+}
+
+impl PalFx {
+    pub fn tick(self) -> Option<PalFx> {
+        if self.time >= 0 && self.time <= 1 {
+            return None;
+        }
+        Some(PalFx {
+            time: if self.time < 0 { self.time } else { self.time - 1 },
+            elapsed: self.elapsed + 1,
+            ..self
+        })
+    }
+
+    pub fn params(&self) -> PalParams {
+        let sin = if self.sin_period > 0 {
+            (TAU * self.elapsed as f32 / self.sin_period as f32).sin()
+        } else {
+            0.0
+        };
+        PalParams {
+            add: [0, 1, 2].map(|i| (self.add[i] as f32 + self.sin_add[i] as f32 * sin) / 255.0),
+            mul: self.mul.map(|mul| mul as f32 / 256.0),
+            color: self.color as f32 / 256.0,
+            invert: self.invert,
+        }
+    }
+}
+
+// What a palette effect does to a color, with colors from 0 to 1:
+// (gray(color) inverted + add) * mul.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PalParams {
+    pub add: [f32; 3],
+    pub mul: [f32; 3],
+    pub color: f32,
+    pub invert: bool,
+}
+
+impl Default for PalParams {
+    fn default() -> Self {
+        Self {
+            add: [0.0; 3],
+            mul: [1.0; 3],
+            color: 1.0,
+            invert: false,
+        }
+    }
+}
+
+impl PalParams {
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let gray = (rgb[0] + rgb[1] + rgb[2]) / 3.0;
+        [0, 1, 2].map(|i| {
+            let c = gray + (rgb[i] - gray) * self.color;
+            let c = if self.invert { 1.0 - c } else { c };
+            ((c + self.add[i]) * self.mul[i]).clamp(0.0, 1.0)
+        })
+    }
+
+    // This effect, then `next` on top of it. Each one is a line through the
+    // color, inverting makes its slope negative, so the two stages
+    // ((c + a1) * m1 + a2) * m2, or (1 - (c + a1) * m1 + a2) * m2 when `next`
+    // inverts, come out as one. A channel multiplied by 0 stays 0.
+    pub fn then(&self, next: &PalParams) -> PalParams {
+        let invert = self.invert != next.invert;
+        let line = |params: &PalParams, i: usize| {
+            let sign = if params.invert { -1.0 } else { 1.0 };
+            let offset = if params.invert { 1.0 } else { 0.0 };
+            (sign * params.mul[i], (offset + params.add[i]) * params.mul[i])
+        };
+        let channels = [0, 1, 2].map(|i| {
+            let (slope, offset) = line(self, i);
+            let (next_slope, next_offset) = line(next, i);
+            let (slope, offset) = (slope * next_slope, offset * next_slope + next_offset);
+            let mul = slope.abs();
+            if mul == 0.0 {
+                (0.0, 0.0)
+            } else {
+                (offset / mul - if invert { 1.0 } else { 0.0 }, mul)
+            }
+        });
+        PalParams {
+            add: channels.map(|(add, _)| add),
+            mul: channels.map(|(_, mul)| mul),
+            color: self.color * next.color,
+            invert,
+        }
+    }
+}
+
+// Trans, how the char is blended with what's behind it. Only lasts the tick
+// it's set in, Default leaves it to the anim.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Trans {
+    #[default]
+    Default,
+    None,
+    Add,
+    Add1,
+    Sub,
+    AddAlpha(i32, i32), // source and destination alpha, out of 256
+}
+
+impl FromStr for Trans {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "default" => Ok(Trans::Default),
+            "none" => Ok(Trans::None),
+            "add" => Ok(Trans::Add),
+            "add1" => Ok(Trans::Add1),
+            "sub" => Ok(Trans::Sub),
+            "addalpha" => Ok(Trans::AddAlpha(256, 0)),
+            _ => Err("trans is default, none, add, add1, sub or addalpha"),
+        }
+    }
+}
+
+// EnvColor, fills the screen with `color` for `time` ticks, behind the chars
+// with `under`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnvColor {
+    pub color: [u8; 3],
+    pub time: i32,
+    pub under: bool,
+}
+
+impl EnvColor {
+    pub fn tick(self) -> Option<EnvColor> {
+        if self.time >= 0 && self.time <= 1 {
+            return None;
+        }
+        Some(EnvColor {
+            time: if self.time < 0 { self.time } else { self.time - 1 },
+            ..self
+        })
+    }
+}
+
+// The effects a char's controllers have on the whole screen. They're queued
+// on the char and the battle takes them after its states have run.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScreenEffect {
+    EnvShake(EnvShake),
+    EnvColor(EnvColor),
+    BgPalFx(PalFx),
+    AllPalFx(PalFx),
+}
+
+// Where a char was and what it showed, for its after images. The geometry
+// is the char's Render at the time.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ghost {
+    pub position: Vec2,
+    pub sprite: Option<(u16, u16)>,
+    pub offset: Vec2,
+    pub rotation: f32,
+    pub scale: Vec2,
+}
+
+// AfterImage. Every time_gap ticks the char is recorded, and every
+// frame_gap-th record is drawn behind it. The first gets bright, contrast
+// and post_bright, each one after that add and mul on top.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AfterImage {
+    pub time: i32, // -1 is until AfterImageTime turns it off
+    pub length: usize,
+    pub time_gap: i32,
+    pub frame_gap: usize,
+    pub color: i32,
+    pub invert: bool,
+    pub bright: [i32; 3],
+    pub contrast: [i32; 3],
+    pub post_bright: [i32; 3],
+    pub add: [i32; 3],
+    pub mul: [f32; 3],
+    pub trans: Trans,
+    pub ghosts: VecDeque<Ghost>, // newest first
+    pub elapsed: i32,
+}
+
+// MUGEN's cap on length
+pub const MAX_AFTER_IMAGE_LENGTH: usize = 60;
+
+impl Default for AfterImage {
+    fn default() -> Self {
+        Self {
+            time: 1,
+            length: 20,
+            time_gap: 1,
+            frame_gap: 4,
+            color: 256,
+            invert: false,
+            bright: [30; 3],
+            contrast: [120, 120, 220],
+            post_bright: [0; 3],
+            add: [10, 10, 25],
+            mul: [0.65, 0.65, 0.75],
+            trans: Trans::None,
+            ghosts: VecDeque::new(),
+            elapsed: 0,
+        }
+    }
+}
+
+impl AfterImage {
+    // Called once a tick with where the char is now, None once it's over.
+    pub fn tick(mut self, ghost: Ghost) -> Option<AfterImage> {
+        if self.time == 0 {
+            return None;
+        }
+        if self.elapsed % self.time_gap.max(1) == 0 {
+            self.ghosts.push_front(ghost);
+            self.ghosts.truncate(self.length.min(MAX_AFTER_IMAGE_LENGTH));
+        }
+        self.elapsed += 1;
+        if self.time > 0 {
+            self.time -= 1;
+        }
+        Some(self)
+    }
+
+    // The ghosts to draw, newest first, and the palette of each.
+    pub fn visible(&self) -> Vec<(Ghost, PalParams)> {
+        let first = PalParams {
+            add: self.bright.map(|add| add as f32 / 255.0),
+            mul: self.contrast.map(|mul| mul as f32 / 256.0),
+            color: self.color as f32 / 256.0,
+            invert: self.invert,
+        }
+        .then(&PalParams {
+            add: self.post_bright.map(|add| add as f32 / 255.0),
+            ..PalParams::default()
+        });
+        let step = PalParams {
+            add: self.add.map(|add| add as f32 / 255.0),
+            mul: self.mul,
+            ..PalParams::default()
+        };
+
+        let mut pal = first;
+        let frame_gap = self.frame_gap.max(1);
+        self.ghosts
+            .iter()
+            .skip(frame_gap)
+            .step_by(frame_gap)
+            .map(|ghost| {
+                let visible = (*ghost, pal);
+                pal = pal.then(&step);
+                visible
+            })
+            .collect()
+    }
+}
+
+// How to draw a char this tick, worked out from its state so it can be
+// checked without a window. The scale has the facing and the element's flips
+// in it, the offset is the element's, already mirrored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Render {
+    pub pal: PalParams,
+    pub trans: Trans,
+    pub rotation: f32, // radians, clockwise on screen
+    pub scale: Vec2,
+    pub offset: Vec2,
+}
+
+impl Default for Render {
+    fn default() -> Self {
+        Self {
+            pal: PalParams::default(),
+            trans: Trans::Default,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+// MUGEN's angles go counterclockwise, and a char facing left is mirrored.
+pub fn rotation(angle: f32, facing: f32) -> f32 {
+    -angle.to_radians() * facing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn test_pal_fx() {
+        let pal_fx = PalFx {
+            time: 2,
+            add: [255, 0, 0],
+            mul: [256, 128, 256],
+            ..PalFx::default()
+        };
+        let params = pal_fx.params();
+        assert!(close(params.apply([0.0, 1.0, 0.5]), [1.0, 0.5, 0.5]));
+
+        let pal_fx = pal_fx.tick().unwrap();
+        assert_eq!((pal_fx.time, pal_fx.elapsed), (1, 1));
+        assert_eq!(pal_fx.tick(), None);
+
+        let forever = PalFx {
+            time: -1,
+            ..PalFx::default()
+        };
+        assert_eq!(forever.tick().map(|pal_fx| pal_fx.time), Some(-1));
+    }
+
+    #[test]
+    fn test_pal_fx_sin_add_and_color() {
+        let pal_fx = PalFx {
+            time: -1,
+            sin_add: [255, 0, 0],
+            sin_period: 4,
+            elapsed: 1,
+            color: 0,
+            invert: true,
+            ..PalFx::default()
+        };
+        // a quarter of the way through the period sin is 1
+        let params = pal_fx.params();
+        assert!((params.add[0] - 1.0).abs() < 1e-4);
+        // gray, then inverted
+        assert!(close(params.apply([1.0, 0.5, 0.0]), [1.0, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_pal_params_then() {
+        let a = PalParams {
+            add: [0.1; 3],
+            mul: [0.5; 3],
+            ..PalParams::default()
+        };
+        let b = PalParams {
+            add: [0.2; 3],
+            mul: [0.5; 3],
+            ..PalParams::default()
+        };
+        let rgb = [0.3, 0.6, 0.9];
+        assert!(close(a.then(&b).apply(rgb), b.apply(a.apply(rgb))));
+
+        // (1 - ((c + a1) * m1) + a2) * m2
+        let invert = PalParams {
+            invert: true,
+            ..b
+        };
+        assert!(close(a.then(&invert).apply(rgb), invert.apply(a.apply(rgb))));
+        assert!(close(a.then(&invert).apply(rgb), [0.5, 0.425, 0.35]));
+        assert!(close(invert.then(&a).apply(rgb), a.apply(invert.apply(rgb))));
+        assert!(close(invert.then(&invert).apply(rgb), invert.apply(invert.apply(rgb))));
+    }
+
+    #[test]
+    fn test_after_image_ghosts() {
+        let ghost = |x: f32| Ghost {
+            position: Vec2::new(x, 0.0),
+            sprite: Some((0, 0)),
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+        };
+        let mut after_image = AfterImage {
+            time: 6,
+            length: 5,
+            frame_gap: 2,
+            ..AfterImage::default()
+        };
+        for x in 0..6 {
+            after_image = after_image.tick(ghost(x as f32)).unwrap();
+        }
+        assert_eq!(after_image.ghosts.len(), 5);
+        assert_eq!(after_image.tick(ghost(6.0)), None);
+
+        let mut after_image = AfterImage {
+            time: -1,
+            length: 5,
+            frame_gap: 2,
+            ..AfterImage::default()
+        };
+        for x in 0..6 {
+            after_image = after_image.tick(ghost(x as f32)).unwrap();
+        }
+        // the newest one is where the char is now, they're drawn from
+        // frame_gap ticks back
+        let visible = after_image.visible();
+        let xs: Vec<f32> = visible.iter().map(|(ghost, _)| ghost.position.x).collect();
+        assert_eq!(xs, [3.0, 1.0]);
+        // and the older one is darker
+        let (_, newer) = visible[0];
+        let (_, older) = visible[1];
+        assert!(older.apply([1.0; 3])[0] < newer.apply([1.0; 3])[0]);
+    }
+
+    #[test]
+    fn test_trans() {
+        assert_eq!(Trans::from_str("AddAlpha"), Ok(Trans::AddAlpha(256, 0)));
+        assert_eq!(Trans::from_str(" sub"), Ok(Trans::Sub));
+        assert!(Trans::from_str("mul").is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let quarter = std::f32::consts::FRAC_PI_2;
+        assert!((rotation(90.0, 1.0) + quarter).abs() < 1e-6);
+        assert!((rotation(90.0, -1.0) - quarter).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+use ggez::input::gamepad::gilrs::{Axis, Button as PadButton};
+use ggez::{event::GamepadId, Context};
+
+use crate::spec::cmd::{Button, Direction};
+use crate::spec::config::input_config::{AxisDirection, InputBinding};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GamepadEvent<Id> {
+    Connected(Id),
+    Disconnected(Id),
+    ButtonDown(Id, PadButton),
+    ButtonUp(Id, PadButton),
+    Axis(Id, Axis, f32),
+}
+
+// Anything that can feed gamepad events to the router. ggez hands us events
+// through the EventHandler callbacks, tests just queue them up by hand.
+pub trait GamepadEventStream {
+    type Id: Copy + Eq + Hash;
+    fn next_event(&mut self) -> Option<GamepadEvent<Self::Id>>;
+}
+
+pub struct GamepadEventQueue<Id> {
+    events: VecDeque<GamepadEvent<Id>>,
+    connected: HashSet<Id>,
+}
+
+impl<Id: Copy + Eq + Hash> GamepadEventQueue<Id> {
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            connected: HashSet::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: GamepadEvent<Id>) {
+        self.events.push_back(event);
+    }
+}
+
+impl GamepadEventQueue<GamepadId> {
+    // ggez only forwards button and axis events, so connections are found by
+    // diffing the connected pads every tick.
+    pub fn poll_connections(&mut self, context: &Context) {
+        let connected: HashSet<GamepadId> = context.gamepad.gamepads().map(|(id, _)| id).collect();
+        for &id in connected.difference(&self.connected) {
+            self.events.push_back(GamepadEvent::Connected(id));
+        }
+        for &id in self.connected.difference(&connected) {
+            self.events.push_back(GamepadEvent::Disconnected(id));
+        }
+        self.connected = connected;
+    }
+}
+
+impl<Id: Copy + Eq + Hash> GamepadEventStream for GamepadEventQueue<Id> {
+    type Id = Id;
+    fn next_event(&mut self) -> Option<GamepadEvent<Id>> {
+        self.events.pop_front()
+    }
+}
+
+struct PadState {
+    buttons: HashSet<PadButton>,
+    axes: HashMap<Axis, f32>,
+}
+
+impl PadState {
+    fn new() -> Self {
+        Self {
+            buttons: HashSet::new(),
+            axes: HashMap::new(),
+        }
+    }
+
+    fn axis(&self, axis: Axis) -> f32 {
+        *self.axes.get(&axis).unwrap_or(&0.0)
+    }
+}
+
+// Tracks every pad and which joystick slot it occupies. Slots are what
+// `PlayerBindings::joystick` refers to, so with the default config the first
+// pad plugged in drives P1 and the second drives P2. A pad that is unplugged
+// frees its slot for the next pad to be connected.
+pub struct GamepadRouter<Id> {
+    slots: Vec<Option<Id>>,
+    pads: HashMap<Id, PadState>,
+}
+
+impl<Id: Copy + Eq + Hash> GamepadRouter<Id> {
+    pub const MAX_PADS: usize = 4;
+
+    pub fn new() -> Self {
+        Self {
+            slots: vec![None; Self::MAX_PADS],
+            pads: HashMap::new(),
+        }
+    }
+
+    pub fn pump<S: GamepadEventStream<Id = Id>>(&mut self, stream: &mut S) {
+        while let Some(event) = stream.next_event() {
+            self.handle(event);
+        }
+    }
+
+    pub fn handle(&mut self, event: GamepadEvent<Id>) {
+        match event {
+            GamepadEvent::Connected(id) => self.connect(id),
+            GamepadEvent::Disconnected(id) => self.disconnect(id),
+            GamepadEvent::ButtonDown(id, button) => {
+                self.connect(id);
+                self.pads.get_mut(&id).unwrap().buttons.insert(button);
+            }
+            GamepadEvent::ButtonUp(id, button) => {
+                if let Some(pad) = self.pads.get_mut(&id) {
+                    pad.buttons.remove(&button);
+                }
+            }
+            GamepadEvent::Axis(id, axis, value) => {
+                self.connect(id);
+                self.pads.get_mut(&id).unwrap().axes.insert(axis, value);
+            }
+        }
+    }
+
+    fn connect(&mut self, id: Id) {
+        if self.pads.contains_key(&id) {
+            return;
+        }
+        self.pads.insert(id, PadState::new());
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(id);
+        }
+    }
+
+    fn disconnect(&mut self, id: Id) {
+        self.pads.remove(&id);
+        for slot in self.slots.iter_mut() {
+            if *slot == Some(id) {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn slot_of(&self, id: Id) -> Option<usize> {
+        self.slots.iter().position(|slot| *slot == Some(id))
+    }
+
+    fn pad(&self, joystick: usize) -> Option<&PadState> {
+        let id = (*self.slots.get(joystick)?)?;
+        self.pads.get(&id)
+    }
+
+    pub fn buttons(
+        &self,
+        joystick: Option<usize>,
+        bindings: &[(InputBinding, Button)],
+        deadzone: f32,
+    ) -> HashSet<Button> {
+        let pad = match joystick.and_then(|joystick| self.pad(joystick)) {
+            Some(pad) => pad,
+            None => return HashSet::new(),
+        };
+        bindings
+            .iter()
+            .filter(|(binding, _)| match binding {
+                InputBinding::PadButton(button) => pad.buttons.contains(button),
+                InputBinding::PadAxis(axis, axis_direction) => {
+                    axis_direction.active(pad.axis(*axis), deadzone)
+                }
+                InputBinding::Key(_) => false,
+            })
+            .map(|(_, button)| *button)
+            .collect()
+    }
+
+    pub fn directions(
+        &self,
+        joystick: Option<usize>,
+        bindings: &[(InputBinding, Direction)],
+        deadzone: f32,
+    ) -> HashSet<Direction> {
+        let pad = match joystick.and_then(|joystick| self.pad(joystick)) {
+            Some(pad) => pad,
+            None => return HashSet::new(),
+        };
+        bindings
+            .iter()
+            .filter(|(binding, _)| match binding {
+                InputBinding::PadButton(button) => pad.buttons.contains(button),
+                InputBinding::PadAxis(axis, axis_direction) => {
+                    gated_axis_active(pad, *axis, *axis_direction, deadzone)
+                }
+                InputBinding::Key(_) => false,
+            })
+            .map(|(_, direction)| *direction)
+            .collect()
+    }
+}
+
+fn stick_pair(axis: Axis) -> Option<(Axis, Axis)> {
+    match axis {
+        Axis::LeftStickX | Axis::LeftStickY => Some((Axis::LeftStickX, Axis::LeftStickY)),
+        Axis::RightStickX | Axis::RightStickY => Some((Axis::RightStickX, Axis::RightStickY)),
+        Axis::DPadX | Axis::DPadY => Some((Axis::DPadX, Axis::DPadY)),
+        _ => None,
+    }
+}
+
+// Stick axes are read as a pair so the stick resolves to exactly one of
+// eight directions, instead of each axis crossing its own threshold.
+fn gated_axis_active(
+    pad: &PadState,
+    axis: Axis,
+    axis_direction: AxisDirection,
+    deadzone: f32,
+) -> bool {
+    let (x_axis, y_axis) = match stick_pair(axis) {
+        Some(pair) => pair,
+        None => return axis_direction.active(pad.axis(axis), deadzone),
+    };
+    let (x, y) = eight_way(pad.axis(x_axis), pad.axis(y_axis), deadzone);
+    let component = if axis == x_axis { x } else { y };
+    match axis_direction {
+        AxisDirection::Positive => component > 0,
+        AxisDirection::Negative => component < 0,
+    }
+}
+
+// Snaps a stick position to one of 8 directions (or neutral) using a radial
+// deadzone and 45 degree sectors. Returns the sign of each component.
+pub fn eight_way(x: f32, y: f32, deadzone: f32) -> (i32, i32) {
+    if (x * x + y * y).sqrt() <= deadzone {
+        return (0, 0);
+    }
+    let sector = (y.atan2(x) / std::f32::consts::FRAC_PI_4).round() as i32;
+    match sector.rem_euclid(8) {
+        0 => (1, 0),
+        1 => (1, 1),
+        2 => (0, 1),
+        3 => (-1, 1),
+        4 => (-1, 0),
+        5 => (-1, -1),
+        6 => (0, -1),
+        _ => (1, -1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stick_bindings() -> Vec<(InputBinding, Direction)> {
+        vec![
+            (
+                InputBinding::PadAxis(Axis::LeftStickY, AxisDirection::Positive),
+                Direction::U,
+            ),
+            (
+                InputBinding::PadAxis(Axis::LeftStickY, AxisDirection::Negative),
+                Direction::D,
+            ),
+            (
+                InputBinding::PadAxis(Axis::LeftStickX, AxisDirection::Negative),
+                Direction::B,
+            ),
+            (
+                InputBinding::PadAxis(Axis::LeftStickX, AxisDirection::Positive),
+                Direction::F,
+            ),
+            (InputBinding::PadButton(PadButton::DPadUp), Direction::U),
+        ]
+    }
+
+    #[test]
+    fn test_eight_way_gating() {
+        assert_eq!(eight_way(0.1, 0.1, 0.3), (0, 0));
+        assert_eq!(eight_way(0.9, 0.2, 0.3), (1, 0));
+        assert_eq!(eight_way(0.7, 0.7, 0.3), (1, 1));
+        assert_eq!(eight_way(-0.2, -0.9, 0.3), (0, -1));
+    }
+
+    #[test]
+    fn test_stick_to_directions() {
+        let mut stream: GamepadEventQueue<u32> = GamepadEventQueue::new();
+        stream.push(GamepadEvent::Connected(7));
+        stream.push(GamepadEvent::Axis(7, Axis::LeftStickX, 0.9));
+        stream.push(GamepadEvent::Axis(7, Axis::LeftStickY, 0.25));
+
+        let mut router = GamepadRouter::new();
+        router.pump(&mut stream);
+        // mostly right, the little bit of up is gated away
+        let dirs = router.directions(Some(0), &stick_bindings(), 0.3);
+        assert_eq!(dirs, HashSet::from([Direction::F]));
+
+        router.handle(GamepadEvent::Axis(7, Axis::LeftStickY, 0.9));
+        let dirs = router.directions(Some(0), &stick_bindings(), 0.3);
+        assert_eq!(dirs, HashSet::from([Direction::F, Direction::U]));
+    }
+
+    #[test]
+    fn test_face_buttons() {
+        let bindings = vec![
+            (InputBinding::PadButton(PadButton::South), Button::a),
+            (InputBinding::PadButton(PadButton::Start), Button::s),
+        ];
+        let mut router = GamepadRouter::new();
+        router.handle(GamepadEvent::ButtonDown(1u32, PadButton::South));
+        assert_eq!(
+            router.buttons(Some(0), &bindings, 0.3),
+            HashSet::from([Button::a])
+        );
+        router.handle(GamepadEvent::ButtonUp(1u32, PadButton::South));
+        assert!(router.buttons(Some(0), &bindings, 0.3).is_empty());
+    }
+
+    #[test]
+    fn test_hot_plug_slots() {
+        let mut router = GamepadRouter::new();
+        router.handle(GamepadEvent::Connected(10u32));
+        router.handle(GamepadEvent::Connected(20u32));
+        assert_eq!(router.slot_of(10), Some(0));
+        assert_eq!(router.slot_of(20), Some(1));
+
+        // P1's pad is unplugged, the next pad takes over P1
+        router.handle(GamepadEvent::Disconnected(10));
+        router.handle(GamepadEvent::Connected(30));
+        assert_eq!(router.slot_of(30), Some(0));
+        assert_eq!(router.slot_of(20), Some(1));
+    }
+}
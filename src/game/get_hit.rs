@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+
+use super::env_shake::EnvShake;
+use crate::spec::constants::char_constants::ConstantValue;
+
+// What the last HitDef that hit the char said should happen to it. It's what
+// GetHitVar reads and the get-hit states in common1.cns act on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GetHitVars {
+    pub anim_type: i32,
+    pub air_type: i32,
+    pub ground_type: i32,
+    pub damage: i32,
+    pub hit_count: i32,
+    pub fall_count: i32,
+    pub hit_shake_time: i32,
+    pub hit_time: i32, // counts down, HitOver once it's out
+    pub slide_time: i32,
+    pub ctrl_time: i32,
+    pub recover_time: i32,
+    pub x_off: f32,
+    pub y_off: f32,
+    pub x_vel: f32,
+    pub y_vel: f32,
+    pub y_accel: f32,
+    pub hit_id: i32,
+    pub chain_id: i32,
+    pub guarded: bool,
+    pub is_bound: bool,
+    pub fall: Fall,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fall {
+    pub fall: bool,
+    pub damage: i32,
+    pub x_vel: Option<f32>, // None keeps the x velocity
+    pub y_vel: f32,
+    pub recover: bool,
+    pub recover_time: i32, // counts down while falling, CanRecover once it's out
+    pub time: i32,         // ticks spent falling
+    pub kill: bool,
+    pub env_shake: EnvShake,
+}
+
+// HitDef's defaults
+impl Default for Fall {
+    fn default() -> Self {
+        Self {
+            fall: false,
+            damage: 0,
+            x_vel: None,
+            y_vel: -4.5,
+            recover: true,
+            recover_time: 4,
+            time: 0,
+            kill: true,
+            env_shake: EnvShake::default(),
+        }
+    }
+}
+
+// Everything GetHitVar can ask for.
+pub const GET_HIT_VAR_NAMES: [&str; 33] = [
+    "animtype",
+    "airtype",
+    "groundtype",
+    "damage",
+    "hitcount",
+    "fallcount",
+    "hitshaketime",
+    "hittime",
+    "slidetime",
+    "ctrltime",
+    "recovertime",
+    "xoff",
+    "yoff",
+    "xvel",
+    "yvel",
+    "yaccel",
+    "hitid",
+    "chainid",
+    "guarded",
+    "isbound",
+    "fall",
+    "fall.damage",
+    "fall.xvel",
+    "fall.yvel",
+    "fall.recover",
+    "fall.recovertime",
+    "fall.time",
+    "fall.kill",
+    "fall.envshake.time",
+    "fall.envshake.freq",
+    "fall.envshake.ampl",
+    "fall.envshake.phase",
+    "type",
+];
+
+impl GetHitVars {
+    pub fn get(&self, name: &str) -> Option<ConstantValue> {
+        let int = |val: i32| Some(ConstantValue::Int(val));
+        let float = |val: f32| Some(ConstantValue::Float(val));
+        let fall = &self.fall;
+        match name {
+            // type is what animtype was called before MUGEN 1.0
+            "animtype" | "type" => int(self.anim_type),
+            "airtype" => int(self.air_type),
+            "groundtype" => int(self.ground_type),
+            "damage" => int(self.damage),
+            "hitcount" => int(self.hit_count),
+            "fallcount" => int(self.fall_count),
+            "hitshaketime" => int(self.hit_shake_time),
+            "hittime" => int(self.hit_time),
+            "slidetime" => int(self.slide_time),
+            "ctrltime" => int(self.ctrl_time),
+            "recovertime" => int(self.recover_time),
+            "xoff" => float(self.x_off),
+            "yoff" => float(self.y_off),
+            "xvel" => float(self.x_vel),
+            "yvel" => float(self.y_vel),
+            "yaccel" => float(self.y_accel),
+            "hitid" => int(self.hit_id),
+            "chainid" => int(self.chain_id),
+            "guarded" => int(self.guarded as i32),
+            "isbound" => int(self.is_bound as i32),
+            "fall" => int(fall.fall as i32),
+            "fall.damage" => int(fall.damage),
+            "fall.xvel" => float(fall.x_vel.unwrap_or(0.0)),
+            "fall.yvel" => float(fall.y_vel),
+            "fall.recover" => int(fall.recover as i32),
+            "fall.recovertime" => int(fall.recover_time),
+            "fall.time" => int(fall.time),
+            "fall.kill" => int(fall.kill as i32),
+            "fall.envshake.time" => int(fall.env_shake.time),
+            "fall.envshake.freq" => float(fall.env_shake.freq),
+            "fall.envshake.ampl" => float(fall.env_shake.ampl),
+            "fall.envshake.phase" => float(fall.env_shake.phase),
+            _ => None,
+        }
+    }
+
+    // Runs while the char is in a get-hit state and not in hitpause.
+    pub fn tick(&mut self) {
+        for time in [
+            &mut self.hit_time,
+            &mut self.slide_time,
+            &mut self.ctrl_time,
+        ] {
+            if *time > 0 {
+                *time -= 1;
+            }
+        }
+        if self.fall.fall {
+            self.fall.time += 1;
+            if self.fall.recover_time > 0 {
+                self.fall.recover_time -= 1;
+            }
+        }
+    }
+
+    // CanRecover, a falling char can use the recovery command once
+    // fall.recovertime has run out.
+    pub fn can_recover(&self) -> bool {
+        self.fall.fall && self.fall.recover && self.fall.recover_time <= 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_recover_after_recover_time() {
+        let mut vars = GetHitVars {
+            hit_time: 2,
+            fall: Fall {
+                fall: true,
+                recover_time: 2,
+                ..Fall::default()
+            },
+            ..GetHitVars::default()
+        };
+        assert!(!vars.can_recover());
+        vars.tick();
+        assert!(!vars.can_recover());
+        vars.tick();
+        assert!(vars.can_recover());
+        assert_eq!(vars.hit_time, 0);
+        assert_eq!(vars.fall.time, 2);
+
+        vars.fall.recover = false;
+        assert!(!vars.can_recover());
+    }
+
+    #[test]
+    fn test_every_name_has_a_value() {
+        let vars = GetHitVars::default();
+        for name in GET_HIT_VAR_NAMES {
+            assert!(vars.get(name).is_some(), "no value for {}", name);
+        }
+    }
+}
@@ -0,0 +1,938 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::char::CharState;
+use super::get_hit::{Fall, GetHitVars};
+use super::pause::Pause;
+use super::target::{self, pair_mut};
+use crate::spec::air::Clsn;
+use crate::spec::hit_attr::{AnimType, AttackAttr, AttrSet, HitFlags, HitType};
+use crate::spec::state::{common_states, MoveType, StateType};
+
+pub const HIT_BY_SLOTS: usize = 2;
+pub const HIT_OVERRIDE_SLOTS: usize = 8;
+
+// The HitDef a char's attack is looking to hit with. Once it's hit the
+// attack needs another HitDef. The guard parameters are kept for when
+// there's guarding.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HitDef {
+    pub attr: AttackAttr,
+    pub hit_flag: HitFlags,
+    pub guard_flag: HitFlags,
+    pub id: i32,
+    pub chain_id: i32,          // -1 hits anyone, otherwise only who it last hit with that id
+    pub no_chain_id: [i32; 2],  // can't hit who was last hit with these, -1 is none
+    pub hit_once: bool,         // hits only one of the chars it meets
+    pub damage: i32,
+    pub guard_damage: i32,
+    pub kill: bool,
+    pub guard_kill: bool,
+    pub num_hits: i32,
+    pub pause_time: (i32, i32), // (p1, p2)
+    pub guard_pause_time: (i32, i32),
+    pub anim_type: AnimType,
+    pub air_anim_type: AnimType,
+    pub fall_anim_type: AnimType,
+    pub ground_type: HitType,
+    pub air_type: HitType,
+    pub ground_slide_time: i32,
+    pub guard_slide_time: i32,
+    pub ground_hit_time: i32,
+    pub guard_hit_time: i32,
+    pub air_hit_time: i32,
+    pub guard_ctrl_time: i32,
+    pub air_guard_ctrl_time: i32,
+    pub y_accel: f32,
+    pub ground_velocity: Vec2,
+    pub guard_velocity: f32,
+    pub air_velocity: Vec2,
+    pub air_guard_velocity: Vec2,
+    pub down_velocity: Vec2,
+    pub down_hit_time: i32,
+    pub down_bounce: bool,
+    pub air_juggle: Option<i32>, // None costs the attacker's state's juggle
+    pub p1_state: Option<i32>,
+    pub p2_state: Option<i32>,
+    pub p2_get_p1_state: bool, // p2_state is one of the attacker's, like TargetState
+    pub force_stand: bool,
+    pub fall: Fall,     // fall.*, fall.fall is `fall`
+    pub air_fall: bool, // fall for a defender in the air
+    pub force_no_fall: bool,
+}
+
+// HitBy or NotHitBy, for `time` ticks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HitBy {
+    pub attr: AttrSet,
+    pub time: i32,
+    pub only: bool, // HitBy, only these attrs can hit. NotHitBy, these can't
+}
+
+impl HitBy {
+    // Counts down a tick, None once it's over.
+    pub fn tick(self) -> Option<HitBy> {
+        if self.time <= 1 {
+            return None;
+        }
+        Some(HitBy {
+            time: self.time - 1,
+            ..self
+        })
+    }
+}
+
+// HitOverride, attacks with these attrs send the char to `state_no` instead
+// of its get-hit states. A time of -1 lasts until it's overridden.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HitOverride {
+    pub attr: AttrSet,
+    pub state_no: i32,
+    pub time: i32,
+    pub force_air: bool, // get-hit vars as if the char was in the air
+}
+
+impl HitOverride {
+    pub fn tick(self) -> Option<HitOverride> {
+        match self.time {
+            time if time < 0 => Some(self),
+            time if time <= 1 => None,
+            time => Some(HitOverride {
+                time: time - 1,
+                ..self
+            }),
+        }
+    }
+}
+
+// ReversalDef, counters attacks with these attrs when their Clsn1 meets the
+// char's Clsn1. Lasts until the char changes state or reverses something.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReversalDef {
+    pub attr: AttrSet,
+    pub pause_time: (i32, i32), // (the reverser, the one reversed)
+    pub p1_state: Option<i32>,
+    pub p2_state: Option<i32>, // runs the reverser's state, like TargetState
+}
+
+// Runs once every char has moved. Every active HitDef whose Clsn1 meets
+// another char's Clsn1 is reversed if that char has a ReversalDef for it,
+// otherwise it hits if it meets the char's Clsn2. Every contact is found
+// before any is applied, so two chars can trade hits. While there's a
+// `pause`, its owner can be unhittable and everyone else's defence is
+// multiplied.
+pub fn resolve_hits(chars: &mut [CharState], pause: Option<(usize, Pause)>) {
+    let mut reversals = Vec::new();
+    let mut hits: Vec<(usize, usize, HitDef, HitResult)> = Vec::new();
+    for (attacker, attacking) in chars.iter().enumerate() {
+        let hit_def = match &attacking.hit_def {
+            Some(hit_def) => hit_def,
+            None => continue,
+        };
+        let attack = boxes(attacking, true);
+        for (defender, defending) in chars.iter().enumerate() {
+            if defender == attacker || !reaches(hit_def, defending) {
+                continue;
+            }
+            if hit_def.hit_once && hits.iter().any(|&(hitter, ..)| hitter == attacker) {
+                continue;
+            }
+            let mut hit_def = hit_def.clone();
+            match pause {
+                Some((owner, pause)) if owner == defender && pause.unhittable => continue,
+                Some((owner, pause)) if owner != defender => {
+                    hit_def.damage = (hit_def.damage as f32 / pause.p2_def_mul) as i32;
+                }
+                _ => {}
+            }
+            if reverses(&hit_def.attr, defending) && overlap(&attack, &boxes(defending, true)) {
+                reversals.push((defender, attacker));
+            } else if overlap(&attack, &boxes(defending, false)) {
+                match resolve(&hit_def.attr, defending) {
+                    HitResult::Miss => {}
+                    result => hits.push((attacker, defender, hit_def, result)),
+                }
+            }
+        }
+    }
+
+    for &(reverser, attacker) in &reversals {
+        apply_reversal(chars, reverser, attacker);
+    }
+    for (attacker, defender, hit_def, result) in hits {
+        if !reversals.iter().any(|&(_, reversed)| reversed == attacker) {
+            apply_hit(chars, attacker, defender, &hit_def, &result);
+        }
+    }
+}
+
+// The HitDef's hitflag and chain ids let it hit `defender` as it stands.
+fn reaches(hit_def: &HitDef, defender: &CharState) -> bool {
+    let get_hit = defender.get_move_type() == MoveType::H;
+    let last_hit_id = if get_hit { defender.get_hit.hit_id } else { -1 };
+    let falling = defender.get_hit.fall.fall;
+    hit_def
+        .hit_flag
+        .reaches(defender.get_state_type(), get_hit, falling)
+        && (hit_def.chain_id == -1 || hit_def.chain_id == last_hit_id)
+        && (last_hit_id == -1 || !hit_def.no_chain_id.contains(&last_hit_id))
+}
+
+// The char's attack boxes, or the boxes it can be hit in, where it stands.
+fn boxes(char: &CharState, attack: bool) -> Vec<Clsn> {
+    let (clsn1, clsn2) = char.animator.clsn();
+    let boxes = if attack { clsn1 } else { clsn2 };
+    boxes
+        .iter()
+        .map(|clsn| clsn.at(char.position, char.facing))
+        .collect()
+}
+
+fn overlap(a: &[Clsn], b: &[Clsn]) -> bool {
+    a.iter().any(|a| b.iter().any(|b| a.overlaps(b)))
+}
+
+// `attacker`'s HitDef hit `defender`, who goes to its get-hit state for the
+// state type it's in, the HitOverride's state or the HitDef's p2stateno.
+fn apply_hit(
+    chars: &mut [CharState],
+    attacker: usize,
+    defender: usize,
+    hit_def: &HitDef,
+    result: &HitResult,
+) {
+    let (attacking, defending) = pair_mut(chars, attacker, defender);
+    let being_juggled =
+        defending.get_state_type() == StateType::A && defending.get_move_type() == MoveType::H;
+    let juggle = hit_def.air_juggle.unwrap_or(attacking.juggle);
+    if being_juggled && !defending.take_juggle(juggle) {
+        return;
+    }
+
+    attacking.hit_def = None;
+    attacking.move_contact = 1;
+    attacking.move_hit = 1;
+    attacking.hit_count += hit_def.num_hits;
+    attacking.add_target(defender, hit_def.id);
+    attacking.start_hit_pause(hit_def.pause_time.0, false);
+    defending.start_hit_pause(hit_def.pause_time.1, true);
+
+    let force_air = matches!(result, HitResult::Override(hit_override) if hit_override.force_air);
+    let in_air = defending.get_state_type() == StateType::A || force_air;
+    let lying = defending.get_state_type() == StateType::L && !force_air;
+    let (velocity, hit_time) = if in_air {
+        (hit_def.air_velocity, hit_def.air_hit_time)
+    } else if lying {
+        (hit_def.down_velocity, hit_def.down_hit_time)
+    } else {
+        (hit_def.ground_velocity, hit_def.ground_hit_time)
+    };
+    let (hit_count, fall_count) = match defending.get_move_type() {
+        MoveType::H => (
+            defending.get_hit.hit_count + hit_def.num_hits,
+            defending.get_hit.fall_count,
+        ),
+        _ => (hit_def.num_hits, 0),
+    };
+    // a char that's falling already keeps falling, unless it's forcenofall
+    let falling = defending.get_move_type() == MoveType::H && defending.get_hit.fall.fall;
+    let falls = if in_air {
+        hit_def.air_fall
+    } else {
+        hit_def.fall.fall
+    };
+    let fall = (falling || falls) && !hit_def.force_no_fall;
+    let anim_type = match (in_air, fall) {
+        (true, true) => hit_def.fall_anim_type,
+        (true, false) => hit_def.air_anim_type,
+        _ => hit_def.anim_type,
+    };
+    defending.get_hit = GetHitVars {
+        anim_type: anim_type as i32,
+        air_type: hit_def.air_type as i32,
+        ground_type: hit_def.ground_type as i32,
+        damage: hit_def.damage,
+        hit_count,
+        fall_count,
+        hit_shake_time: hit_def.pause_time.1,
+        hit_time,
+        slide_time: hit_def.ground_slide_time,
+        // MUGEN gives back control once a hit that isn't guarded is over
+        ctrl_time: hit_time,
+        recover_time: hit_def.fall.recover_time,
+        x_vel: velocity.x,
+        y_vel: velocity.y,
+        y_accel: hit_def.y_accel,
+        hit_id: hit_def.id,
+        chain_id: hit_def.chain_id,
+        fall: Fall { fall, ..hit_def.fall },
+        ..GetHitVars::default()
+    };
+    defending.add_life(-hit_def.damage, hit_def.kill);
+    defending.set_ctrl_flag(0);
+    match (result, hit_def.p2_state) {
+        (HitResult::Override(hit_override), _) => apply_override(defending, hit_override),
+        (_, Some(state_no)) if hit_def.p2_get_p1_state => {
+            target::enter_custom_state(attacking, attacker, defending, state_no)
+        }
+        (_, Some(state_no)) => defending.set_state(state_no),
+        (_, None) => defending.set_state(match defending.get_state_type() {
+            StateType::A => common_states::AIR_GET_HIT_SHAKING,
+            StateType::L => common_states::DOWNED_GET_HIT_SHAKING,
+            _ if hit_def.ground_type == HitType::Trip => common_states::TRIPPED_GET_HIT_SHAKING,
+            StateType::C if !hit_def.force_stand => common_states::CROUCH_GET_HIT_SHAKING,
+            _ => common_states::STAND_GET_HIT_SHAKING,
+        }),
+    }
+    if let Some(state_no) = hit_def.p1_state {
+        attacking.set_state(state_no);
+    }
+}
+
+// What becomes of an attack whose Clsn1 met the defender's Clsn2.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HitResult {
+    Miss,
+    Hit,
+    Override(HitOverride),
+}
+
+// Every HitBy and NotHitBy slot has to let the attack through, then the
+// first HitOverride slot that takes it wins.
+pub fn resolve(attr: &AttackAttr, defender: &CharState) -> HitResult {
+    let hittable = defender
+        .hit_by
+        .iter()
+        .flatten()
+        .all(|hit_by| hit_by.attr.contains(attr) == hit_by.only);
+    if !hittable {
+        return HitResult::Miss;
+    }
+    match defender
+        .hit_overrides
+        .iter()
+        .flatten()
+        .find(|hit_override| hit_override.attr.contains(attr))
+    {
+        Some(hit_override) => HitResult::Override(hit_override.clone()),
+        None => HitResult::Hit,
+    }
+}
+
+// Whether an attack whose Clsn1 met the defender's Clsn1 is reversed. The
+// reversal goes first, a reversed attack doesn't hit.
+pub fn reverses(attr: &AttackAttr, defender: &CharState) -> bool {
+    match &defender.reversal_def {
+        Some(reversal_def) => reversal_def.attr.contains(attr),
+        None => false,
+    }
+}
+
+// `reverser`'s ReversalDef caught `attacker`'s attack.
+pub fn apply_reversal(chars: &mut [CharState], reverser: usize, attacker: usize) {
+    let reversal_def = match chars[reverser].reversal_def.take() {
+        Some(reversal_def) => reversal_def,
+        None => return,
+    };
+    let (p1, p2) = pair_mut(chars, reverser, attacker);
+    p1.start_hit_pause(reversal_def.pause_time.0, false);
+    p2.start_hit_pause(reversal_def.pause_time.1, true);
+    p2.hit_def = None;
+    p1.add_target(attacker, 0);
+    if let Some(state_no) = reversal_def.p1_state {
+        p1.set_state(state_no);
+    }
+    if let Some(state_no) = reversal_def.p2_state {
+        target::enter_custom_state(p1, reverser, p2, state_no);
+    }
+}
+
+pub fn apply_override(defender: &mut CharState, hit_override: &HitOverride) {
+    defender.set_state(hit_override.state_no);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::battle::{BattleState, BattleSystem};
+    use crate::game::input::InputFrame;
+    use crate::game::state_manager::StateManager;
+    use crate::game::target::Target;
+    use crate::game::get_hit::GET_HIT_VAR_NAMES;
+    use crate::game::test_utils::battle;
+    use crate::spec::constants::char_constants::ConstantValue;
+    use crate::spec::cns::CNSFile;
+    use crate::utils::ini::parse_ini;
+    use std::str::FromStr;
+
+    fn attr(s: &str) -> AttackAttr {
+        AttackAttr::from_str(s).unwrap()
+    }
+
+    fn attr_set(s: &str) -> AttrSet {
+        AttrSet::from_str(s).unwrap()
+    }
+
+    // A HitDef with `attr` and the rest left at its defaults.
+    fn hit_def(attr: AttackAttr) -> HitDef {
+        HitDef {
+            attr,
+            hit_flag: HitFlags::from_str("MAF").unwrap(),
+            guard_flag: HitFlags::default(),
+            id: 0,
+            chain_id: -1,
+            no_chain_id: [-1, -1],
+            hit_once: false,
+            damage: 0,
+            guard_damage: 0,
+            kill: true,
+            guard_kill: true,
+            num_hits: 1,
+            pause_time: (0, 0),
+            guard_pause_time: (0, 0),
+            anim_type: AnimType::Light,
+            air_anim_type: AnimType::Light,
+            fall_anim_type: AnimType::Back,
+            ground_type: HitType::High,
+            air_type: HitType::High,
+            ground_slide_time: 0,
+            guard_slide_time: 0,
+            ground_hit_time: 0,
+            guard_hit_time: 0,
+            air_hit_time: 20,
+            guard_ctrl_time: 0,
+            air_guard_ctrl_time: 0,
+            y_accel: 0.35,
+            ground_velocity: Vec2::ZERO,
+            guard_velocity: 0.0,
+            air_velocity: Vec2::ZERO,
+            air_guard_velocity: Vec2::ZERO,
+            down_velocity: Vec2::ZERO,
+            down_hit_time: 0,
+            down_bounce: false,
+            air_juggle: None,
+            p1_state: None,
+            p2_state: None,
+            p2_get_p1_state: true,
+            force_stand: false,
+            fall: Fall::default(),
+            air_fall: false,
+            force_no_fall: false,
+        }
+    }
+
+    fn tick(system: &mut BattleSystem, state: &mut BattleState) {
+        system.update(state, vec![InputFrame::NoInput, InputFrame::NoInput]);
+    }
+
+    // p1 throws KFM's light punch with `hit_def`'s parameters, close enough
+    // for its Clsn1 to reach p2.
+    fn punch(hit_def: &str) -> (BattleSystem, BattleState) {
+        attack(200, hit_def)
+    }
+
+    fn attack(anim: i32, hit_def: &str) -> (BattleSystem, BattleState) {
+        let (mut system, mut state) = battle(2, 0);
+        let ini = parse_ini(&format!(
+            "[Statedef 200]\n\
+             anim = {}\n\
+             movetype = A\n\
+             ctrl = 0\n\
+             [State 200, attack]\n\
+             type = HitDef\n\
+             trigger1 = Time = 0\n\
+             {}",
+            anim, hit_def
+        ));
+        system.set_state_manager(0, StateManager::new(CNSFile::parse_states(&ini)));
+        state.chars[0].position.x = -100.0;
+        state.chars[1].position.x = 50.0;
+        state.chars[0].set_state(200);
+        (system, state)
+    }
+
+    #[test]
+    fn test_hit_by_slots() {
+        let (_, mut state) = battle(2, 0);
+        let mut char = state.chars.remove(0);
+        assert_eq!(resolve(&attr("S, NA"), &char), HitResult::Hit);
+
+        char.hit_by[0] = Some(HitBy {
+            attr: attr_set("SCA, AP"),
+            time: 2,
+            only: false,
+        });
+        assert_eq!(resolve(&attr("S, SP"), &char), HitResult::Miss);
+        assert_eq!(resolve(&attr("S, NA"), &char), HitResult::Hit);
+
+        char.hit_by[1] = Some(HitBy {
+            attr: attr_set("A, NA"),
+            time: 1,
+            only: true,
+        });
+        assert_eq!(resolve(&attr("S, NA"), &char), HitResult::Miss);
+        assert_eq!(resolve(&attr("A, NA"), &char), HitResult::Hit);
+
+        let hit_by = char.hit_by[0].clone().unwrap();
+        assert_eq!(hit_by.clone().tick().map(|hit_by| hit_by.time), Some(1));
+        assert_eq!(hit_by.tick().and_then(HitBy::tick), None);
+    }
+
+    #[test]
+    fn test_hit_override() {
+        let (_, mut state) = battle(2, 0);
+        let mut char = state.chars.remove(0);
+        let hit_override = HitOverride {
+            attr: attr_set("SCA, AT"),
+            state_no: 200,
+            time: -1,
+            force_air: false,
+        };
+        char.hit_overrides[3] = Some(hit_override.clone());
+        assert_eq!(
+            resolve(&attr("S, NT"), &char),
+            HitResult::Override(hit_override.clone())
+        );
+        assert_eq!(resolve(&attr("S, NA"), &char), HitResult::Hit);
+        assert_eq!(hit_override.clone().tick(), Some(hit_override));
+    }
+
+    #[test]
+    fn test_reversal() {
+        let (_, state) = battle(2, 0);
+        let mut chars = state.chars;
+        chars[0].reversal_def = Some(ReversalDef {
+            attr: attr_set("SA, NA"),
+            pause_time: (10, 12),
+            p1_state: Some(200),
+            p2_state: Some(210),
+        });
+        assert!(reverses(&attr("S, NA"), &chars[0]));
+        assert!(!reverses(&attr("C, NA"), &chars[0]));
+        assert!(!reverses(&attr("S, NA"), &chars[1]));
+
+        chars[1].hit_def = Some(hit_def(attr("S, NA")));
+        apply_reversal(&mut chars, 0, 1);
+        assert!(chars[0].reversal_def.is_none());
+        assert_eq!((chars[0].hit_pause, chars[1].hit_pause), (10, 12));
+        assert!(chars[1].hit_shake);
+        assert!(chars[1].hit_def.is_none());
+        assert_eq!(chars[0].get_state_no(), 200);
+        assert_eq!(chars[1].get_state_no(), 210);
+        assert_eq!(chars[1].state_owner, Some(0));
+        assert_eq!(chars[0].targets.len(), 1);
+    }
+
+    #[test]
+    fn test_hit_def_hits() {
+        let (mut system, mut state) = punch(
+            "attr = S, NA\ndamage = 23\nid = 4\npausetime = 0, 10\nground.velocity = -4, 0\n",
+        );
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        let (p1, p2) = (&state.chars[0], &state.chars[1]);
+        assert_eq!(p2.get_life(), life - 23);
+        assert_eq!(p2.get_state_no(), common_states::STAND_GET_HIT_SHAKING);
+        assert_eq!((p2.get_hit.damage, p2.get_hit.x_vel), (23, -4.0));
+        assert_eq!(p1.targets, [Target { player: 1, id: 4 }]);
+        assert_eq!((p1.move_hit, p1.hit_count), (1, 1));
+        assert!(p1.hit_def.is_none());
+    }
+
+    #[test]
+    fn test_hit_def_defaults() {
+        let (mut system, mut state) = punch(
+            "attr = S, NA\n\
+             pausetime = 8, 9\n\
+             animtype = Up\n\
+             ground.slidetime = 6\n\
+             ground.velocity = -4, -2\n\
+             air.velocity = -2, -6\n",
+        );
+        tick(&mut system, &mut state);
+        let hit_def = state.chars[0].hit_def.clone().unwrap();
+        assert_eq!(hit_def.guard_pause_time, (8, 9));
+        assert_eq!(hit_def.air_anim_type, AnimType::Up);
+        assert_eq!(hit_def.fall_anim_type, AnimType::Up);
+        assert_eq!(
+            (hit_def.guard_slide_time, hit_def.guard_hit_time, hit_def.air_guard_ctrl_time),
+            (6, 6, 6)
+        );
+        assert_eq!(hit_def.guard_velocity, -4.0);
+        assert_eq!(hit_def.air_guard_velocity, Vec2::new(-3.0, -3.0));
+        assert_eq!(hit_def.down_velocity, Vec2::new(-2.0, -6.0));
+        assert!(hit_def.force_stand);
+        assert!(!hit_def.hit_once);
+    }
+
+    #[test]
+    fn test_hit_flag() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\nhitflag = A\n");
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life);
+        assert!(state.chars[0].hit_def.is_some());
+
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\nhitflag = H\n");
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life - 23);
+
+        // + only hits a char that's being hit already
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\nhitflag = MAF+\n");
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life);
+    }
+
+    #[test]
+    fn test_hit_def_fills_get_hit_vars() {
+        let (mut system, mut state) = punch(
+            "attr = S, NA\n\
+             damage = 23, 5\n\
+             id = 4\n\
+             pausetime = 0, 12\n\
+             animtype = Medium\n\
+             ground.type = Low\n\
+             air.type = Trip\n\
+             ground.slidetime = 6\n\
+             ground.hittime = 14\n\
+             ground.velocity = -4, 0\n\
+             yaccel = .5\n\
+             fall = 1\n\
+             fall.xvelocity = -1\n\
+             fall.yvelocity = -6\n\
+             fall.recover = 0\n\
+             fall.recovertime = 9\n\
+             fall.damage = 7\n\
+             fall.kill = 0\n\
+             fall.envshake.time = 5\n\
+             fall.envshake.freq = 120\n\
+             fall.envshake.ampl = 3\n",
+        );
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+            if state.chars[0].move_hit == 1 {
+                break;
+            }
+        }
+        let get_hit = state.chars[1].get_hit;
+        let var = |name: &str| match get_hit.get(name) {
+            Some(ConstantValue::Int(value)) => value as f32,
+            Some(ConstantValue::Float(value)) => value,
+            None => panic!("no value for {}", name),
+        };
+        let expected = [
+            ("animtype", 1.0),
+            ("airtype", 3.0),
+            ("groundtype", 2.0),
+            ("damage", 23.0),
+            ("hitcount", 1.0),
+            ("fallcount", 0.0),
+            ("hitshaketime", 12.0),
+            ("hittime", 14.0),
+            ("slidetime", 6.0),
+            ("ctrltime", 14.0),
+            ("recovertime", 9.0),
+            ("xoff", 0.0),
+            ("yoff", 0.0),
+            ("xvel", -4.0),
+            ("yvel", 0.0),
+            ("yaccel", 0.5),
+            ("hitid", 4.0),
+            ("chainid", -1.0),
+            ("guarded", 0.0),
+            ("isbound", 0.0),
+            ("fall", 1.0),
+            ("fall.damage", 7.0),
+            ("fall.xvel", -1.0),
+            ("fall.yvel", -6.0),
+            ("fall.recover", 0.0),
+            ("fall.recovertime", 9.0),
+            ("fall.time", 0.0),
+            ("fall.kill", 0.0),
+            ("fall.envshake.time", 5.0),
+            ("fall.envshake.freq", 120.0),
+            ("fall.envshake.ampl", 3.0),
+            ("fall.envshake.phase", 90.0),
+            ("type", 1.0),
+        ];
+        assert_eq!(expected.len(), GET_HIT_VAR_NAMES.len());
+        for (name, value) in expected {
+            assert_eq!(var(name), value, "{}", name);
+        }
+    }
+
+    // Ticks until p2 is back standing with ctrl, returns the states it went
+    // through.
+    fn recover(system: &mut BattleSystem, state: &mut BattleState, ticks: usize) -> Vec<i32> {
+        let mut states = Vec::new();
+        for _ in 0..ticks {
+            tick(system, state);
+            let p2 = &state.chars[1];
+            if states.last() != Some(&p2.get_state_no()) {
+                states.push(p2.get_state_no());
+            }
+            if states.len() > 1 && p2.get_state_no() == common_states::STAND && p2.get_ctrl() != 0
+            {
+                break;
+            }
+        }
+        states
+    }
+
+    #[test]
+    fn test_get_hit_states_recover() {
+        let (mut system, mut state) = punch(
+            "attr = S, NA\n\
+             pausetime = 0, 6\n\
+             ground.slidetime = 4\n\
+             ground.hittime = 10\n\
+             ground.velocity = -4, 0\n",
+        );
+        let states = recover(&mut system, &mut state, 60);
+        assert_eq!(
+            states,
+            [
+                common_states::STAND,
+                common_states::STAND_GET_HIT_SHAKING,
+                common_states::STAND_GET_HIT_KNOCKED_BACK,
+                common_states::STAND,
+            ]
+        );
+        assert_ne!(state.chars[1].get_ctrl(), 0);
+        assert_eq!(state.chars[1].get_move_type(), MoveType::I);
+    }
+
+    #[test]
+    fn test_get_hit_states_fall_and_get_up() {
+        let (mut system, mut state) = punch(
+            "attr = S, NA\n\
+             pausetime = 0, 6\n\
+             ground.velocity = -4, -20\n\
+             fall = 1\n",
+        );
+        let states = recover(&mut system, &mut state, 300);
+        assert_eq!(
+            states,
+            [
+                common_states::STAND,
+                common_states::STAND_GET_HIT_SHAKING,
+                // knocked away is over at once, ground.hittime is 0
+                common_states::AIR_GET_HIT_FALLING,
+                common_states::DOWNED_GET_HIT_HIT_GROUND,
+                common_states::DOWNED_GET_HIT_BOUNCE,
+                common_states::DOWNED_GET_HIT_LYING,
+                common_states::DOWNED_GET_HIT_GETTING_UP,
+                common_states::STAND,
+            ]
+        );
+        assert_eq!(state.chars[1].position.y, 0.0);
+        assert_ne!(state.chars[1].get_ctrl(), 0);
+    }
+
+    #[test]
+    fn test_kfm_throw() {
+        let (mut system, mut state) = battle(2, 0);
+        state.chars[0].position.x = -100.0;
+        state.chars[1].position.x = 50.0;
+        state.chars[0].set_state(800);
+        let life = state.chars[1].get_life();
+
+        let mut p1_states = vec![state.chars[0].get_state_no()];
+        let mut p2_states = Vec::new();
+        let mut owner_anims = false;
+        for _ in 0..200 {
+            tick(&mut system, &mut state);
+            let (p1, p2) = (&state.chars[0], &state.chars[1]);
+            if p1_states.last() != Some(&p1.get_state_no()) {
+                p1_states.push(p1.get_state_no());
+            }
+            if p2_states.last() != Some(&p2.get_state_no()) {
+                p2_states.push(p2.get_state_no());
+            }
+            owner_anims |= p2.in_custom_state() && p2.get_anim_no() == 820;
+            if p2.get_state_no() == common_states::STAND && p2.get_ctrl() != 0 {
+                break;
+            }
+        }
+        assert_eq!(p1_states[..3], [800, 810, common_states::STAND]);
+        assert_eq!(
+            p2_states,
+            [
+                820,
+                821,
+                common_states::DOWNED_GET_HIT_HIT_GROUND,
+                common_states::DOWNED_GET_HIT_BOUNCE,
+                common_states::DOWNED_GET_HIT_LYING,
+                common_states::DOWNED_GET_HIT_GETTING_UP,
+                common_states::STAND,
+            ]
+        );
+        assert!(owner_anims);
+        let p2 = &state.chars[1];
+        assert!(!p2.in_custom_state());
+        assert_eq!(p2.get_life(), life - 60);
+    }
+
+    #[test]
+    fn test_hit_def_misses_out_of_reach() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\n");
+        state.chars[1].position.x = 400.0;
+        state.chars[1].player_push = false;
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life);
+        assert!(state.chars[0].hit_def.is_some());
+    }
+
+    #[test]
+    fn test_not_hit_by_stops_hit_def() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\n");
+        state.chars[1].hit_by[0] = Some(HitBy {
+            attr: attr_set("S, NA"),
+            time: 20,
+            only: false,
+        });
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life);
+        assert_ne!(state.chars[1].get_state_no(), common_states::STAND_GET_HIT_SHAKING);
+    }
+
+    #[test]
+    fn test_hit_override_takes_hit() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\n");
+        state.chars[1].hit_overrides[0] = Some(HitOverride {
+            attr: attr_set("S, NA"),
+            state_no: 700,
+            time: 20,
+            force_air: false,
+        });
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life - 23);
+        assert_eq!(state.chars[1].get_state_no(), 700);
+    }
+
+    #[test]
+    fn test_reversal_def_meets_clsn1() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\n");
+        // p2 punches back, looking to reverse p1's punch
+        let ini = parse_ini(
+            "[Statedef 200]\n\
+             anim = 200\n\
+             ctrl = 0\n\
+             [State 200, reverse]\n\
+             type = ReversalDef\n\
+             trigger1 = 1\n\
+             reversal.attr = S, NA\n\
+             p1stateno = 800\n",
+        );
+        system.set_state_manager(1, StateManager::new(CNSFile::parse_states(&ini)));
+        state.chars[1].set_state(200);
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        let (p1, p2) = (&state.chars[0], &state.chars[1]);
+        assert_eq!(p2.get_life(), life);
+        assert_eq!(p2.get_state_no(), 800);
+        assert_eq!(p2.targets, [Target { player: 0, id: 0 }]);
+        assert!(p1.hit_def.is_none());
+    }
+
+    #[test]
+    fn test_hit_def_pause_time() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 23\npausetime = 5, 7\n");
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+            if state.chars[1].get_life() < life {
+                break;
+            }
+        }
+        let (p1, p2) = (&state.chars[0], &state.chars[1]);
+        assert_eq!((p1.hit_pause, p2.hit_pause), (5, 7));
+        assert!(!p1.hit_shake && p2.hit_shake);
+        assert_eq!(p2.get_hit.hit_shake_time, 7);
+    }
+
+    fn pause(unhittable: bool, p2_def_mul: f32) -> Pause {
+        Pause {
+            time: 20,
+            move_time: 20,
+            super_pause: true,
+            darken: false,
+            p2_def_mul,
+            unhittable,
+        }
+    }
+
+    #[test]
+    fn test_pause_multiplies_defence() {
+        let (mut system, mut state) = punch("attr = S, NA\ndamage = 24\n");
+        state.pause = Some((0, pause(false, 2.0)));
+        let life = state.chars[1].get_life();
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        assert_eq!(state.chars[1].get_life(), life - 12);
+        assert_eq!(state.chars[1].get_hit.damage, 12);
+    }
+
+    #[test]
+    fn test_pause_owner_unhittable() {
+        for unhittable in [true, false] {
+            let (mut system, mut state) = attack(800, "attr = S, NA\ndamage = 24\n");
+            // p1 is frozen while its throw's first element still has its
+            // Clsn1 out, and p2 steps into it
+            state.chars[1].position.x = 400.0;
+            tick(&mut system, &mut state);
+            state.chars[1].position.x = 50.0;
+            state.pause = Some((1, pause(unhittable, 1.0)));
+            let life = state.chars[1].get_life();
+            tick(&mut system, &mut state);
+            assert_eq!(state.chars[1].get_life() == life, unhittable);
+        }
+    }
+
+    #[test]
+    fn test_hit_def_state_nos() {
+        // p2 goes to p1's 300, which marks var(5), and p1 to its 250
+        let (mut system, mut state) = punch(
+            "attr = S, NA\n\
+             p1stateno = 250\n\
+             p2stateno = 300\n\
+             [Statedef 250]\n\
+             [Statedef 300]\n\
+             anim = 0\n\
+             [State 300, mark]\n\
+             type = VarSet\n\
+             trigger1 = 1\n\
+             v = 5\n\
+             value = 1\n",
+        );
+        for _ in 0..8 {
+            tick(&mut system, &mut state);
+        }
+        let (p1, p2) = (&state.chars[0], &state.chars[1]);
+        assert_eq!(p1.get_state_no(), 250);
+        assert_eq!(p2.get_state_no(), 300);
+        assert_eq!(p2.state_owner, Some(0));
+        assert_eq!(p2.get_int_var(5), 1);
+        assert_eq!(p1.targets, [Target { player: 1, id: 0 }]);
+    }
+}
@@ -6,67 +6,134 @@ use ggez::{
 };
 use std::{
     collections::{HashMap, HashSet},
+    hash::Hash,
     ops::Index,
 };
 
-use ggez::winit::event::VirtualKeyCode;
+use ggez::event::GamepadId;
+use serde::{Deserialize, Serialize};
+
+use super::gamepad::GamepadRouter;
+use crate::spec::config::input_config::{InputBinding, PlayerBindings, SocdPolicy};
 
 use crate::spec::cmd::{
     combine_buttons, get_ordinals, matches_sequence_exact, remove_inbetweens, matches_sequence, get_ordinal_pair,  Button, ButtonKind, Command,
     CommandList, Direction, DirectionKind, Element, Key,
 };
 
-pub struct InputSystem {
-    pub button_map: HashMap<VirtualKeyCode, Button>,
-    pub direction_map: HashMap<VirtualKeyCode, Direction>,
+// Turns opposing directions held together into what the player's
+// SocdPolicy says, before they reach the command buffer.
+pub struct SocdCleaner {
+    policy: SocdPolicy,
+    press_order: Vec<Direction>, // held cardinals, oldest first
 }
 
-impl InputSystem {
-    pub fn new() -> InputSystem {
-        let dir_map = [
-            // directions
-            (VirtualKeyCode::Up, Direction::U),
-            (VirtualKeyCode::Down, Direction::D),
-            (VirtualKeyCode::Left, Direction::B),
-            (VirtualKeyCode::Right, Direction::F),
-        ];
-
-        let btn_map = [
-            // Buttons
-            (VirtualKeyCode::A, Button::a),
-            (VirtualKeyCode::S, Button::b),
-            (VirtualKeyCode::D, Button::c),
-            (VirtualKeyCode::Z, Button::x),
-            (VirtualKeyCode::X, Button::y),
-            (VirtualKeyCode::C, Button::z),
-            (VirtualKeyCode::Space, Button::s),
-        ];
+impl SocdCleaner {
+    // Directions pressed on the same tick are ordered by this, not by the set.
+    const CARDINALS: [Direction; 4] = [Direction::U, Direction::D, Direction::B, Direction::F];
 
-        InputSystem {
-            button_map: HashMap::from(btn_map),
-            direction_map: HashMap::from(dir_map),
+    pub fn new(policy: SocdPolicy) -> Self {
+        Self {
+            policy,
+            press_order: Vec::new(),
         }
     }
 
-    fn update_buttons(&self, context: &mut Context) -> InputFrame {
-        let mut pressed_buttons = HashSet::new();
-        // let mut held_buttons: HashSet<_> = HashSet::new();
-        let mut released_buttons = HashSet::new();
+    pub fn clean(&mut self, raw: HashSet<Direction>) -> HashSet<Direction> {
+        self.press_order.retain(|dir| raw.contains(dir));
+        for dir in Self::CARDINALS {
+            if raw.contains(&dir) && !self.press_order.contains(&dir) {
+                self.press_order.push(dir);
+            }
+        }
 
-        for (&virtual_key, game_key) in &self.button_map {
-            if context.keyboard.is_key_just_pressed(virtual_key) {
-                pressed_buttons.insert(game_key.clone());
-            // } else if context.keyboard.is_key_pressed(virtual_key) {
-            //     held_buttons.insert(game_key.clone());
-            } else if context.keyboard.is_key_just_released(virtual_key) {
-                released_buttons.insert(game_key.clone());
+        let mut cleaned = HashSet::new();
+        for (neg, pos) in [(Direction::D, Direction::U), (Direction::B, Direction::F)] {
+            if let Some(dir) = self.resolve_axis(neg, pos) {
+                cleaned.insert(dir);
             }
         }
+        cleaned
+    }
+
+    fn resolve_axis(&self, neg: Direction, pos: Direction) -> Option<Direction> {
+        let neg_idx = self.press_order.iter().position(|&dir| dir == neg);
+        let pos_idx = self.press_order.iter().position(|&dir| dir == pos);
+        match (neg_idx, pos_idx) {
+            (None, None) => None,
+            (Some(_), None) => Some(neg),
+            (None, Some(_)) => Some(pos),
+            (Some(neg_idx), Some(pos_idx)) => match self.policy {
+                SocdPolicy::Neutral => None,
+                SocdPolicy::LastInputWins => Some(if neg_idx > pos_idx { neg } else { pos }),
+                SocdPolicy::FirstInputWins => Some(if neg_idx < pos_idx { neg } else { pos }),
+                SocdPolicy::UpPriority => {
+                    if pos == Direction::U {
+                        Some(Direction::U)
+                    } else {
+                        None
+                    }
+                }
+            },
+        }
+    }
+}
+
+pub struct InputSystem {
+    bindings: PlayerBindings,
+    socd: SocdCleaner,
+    held_buttons: HashSet<Button>,
+    held_directions: HashSet<Direction>,
+}
+
+impl InputSystem {
+    pub fn new(bindings: PlayerBindings) -> InputSystem {
+        InputSystem {
+            socd: SocdCleaner::new(bindings.socd),
+            bindings,
+            held_buttons: HashSet::new(),
+            held_directions: HashSet::new(),
+        }
+    }
+
+    fn active<T: Copy + Eq + Hash>(
+        context: &Context,
+        bindings: &[(InputBinding, T)],
+    ) -> HashSet<T> {
+        bindings
+            .iter()
+            .filter(|(binding, _)| match binding {
+                InputBinding::Key(key) => context.keyboard.is_key_pressed(*key),
+                _ => false,
+            })
+            .map(|(_, game_key)| *game_key)
+            .collect()
+    }
+
+    fn update_buttons(
+        &mut self,
+        context: &Context,
+        gamepads: &GamepadRouter<GamepadId>,
+    ) -> InputFrame {
+        let mut current_buttons = Self::active(context, &self.bindings.buttons);
+        current_buttons.extend(gamepads.buttons(
+            self.bindings.joystick,
+            &self.bindings.buttons,
+            self.bindings.deadzone,
+        ));
+        let pressed_buttons: HashSet<Button> = current_buttons
+            .difference(&self.held_buttons)
+            .cloned()
+            .collect();
+        let released_buttons: HashSet<Button> = self
+            .held_buttons
+            .difference(&current_buttons)
+            .cloned()
+            .collect();
+        self.held_buttons = current_buttons;
 
         if !pressed_buttons.is_empty() {
             InputFrame::Pressed(combine_buttons(pressed_buttons))
-        // } else if pressed_buttons.is_empty() && !held_buttons.is_empty() {
-        //     InputFrame::Held(combine_buttons(held_buttons))
         } else if !released_buttons.is_empty() {
             InputFrame::Released(combine_buttons(released_buttons))
         } else {
@@ -74,26 +141,39 @@ impl InputSystem {
         }
     }
 
-    fn update_directions(&self, context: &mut Context) -> InputFrame {
-        let mut pressed_dirs = HashSet::new();
-        let mut held_dirs = HashSet::new();
-        let mut released_dirs = HashSet::new();
-        for (&virtual_key, game_key) in &self.direction_map {
-            if context.keyboard.is_key_just_pressed(virtual_key) {
-                pressed_dirs.insert(game_key.clone());
-            } else if context.keyboard.is_key_just_released(virtual_key) {
-                released_dirs.insert(game_key.clone());
-            } else if context.keyboard.is_key_pressed(virtual_key) {
-                held_dirs.insert(game_key.clone());
-            }
-        }
-
-        if !pressed_dirs.is_empty() {
-            InputFrame::Pressed(get_ordinals(pressed_dirs))
-        } else if !released_dirs.is_empty() {
-            InputFrame::Released(get_ordinals(released_dirs))
-        } else if pressed_dirs.is_empty() && !held_dirs.is_empty() {
-            InputFrame::Held(get_ordinals(held_dirs))
+    fn update_directions(
+        &mut self,
+        context: &Context,
+        gamepads: &GamepadRouter<GamepadId>,
+    ) -> InputFrame {
+        let mut current_dirs = Self::active(context, &self.bindings.directions);
+        current_dirs.extend(gamepads.directions(
+            self.bindings.joystick,
+            &self.bindings.directions,
+            self.bindings.deadzone,
+        ));
+        let current_dirs = self.socd.clean(current_dirs);
+        let pressed_dirs: HashSet<Direction> = current_dirs
+            .difference(&self.held_directions)
+            .cloned()
+            .collect();
+        let released_dirs: HashSet<Direction> = self
+            .held_directions
+            .difference(&current_dirs)
+            .cloned()
+            .collect();
+        let held_dirs: HashSet<Direction> = current_dirs
+            .intersection(&self.held_directions)
+            .cloned()
+            .collect();
+        self.held_directions = current_dirs;
+
+        if let Some(key) = get_ordinals(pressed_dirs) {
+            InputFrame::Pressed(key)
+        } else if let Some(key) = get_ordinals(released_dirs) {
+            InputFrame::Released(key)
+        } else if let Some(key) = get_ordinals(held_dirs) {
+            InputFrame::Held(key)
         } else {
             InputFrame::NoInput
         }
@@ -102,14 +182,20 @@ impl InputSystem {
     // Here's the rules.
     // Pressed takes priority over held, which takes priority over released
     // Buttons take priority over directions
-    // If more than one direction is pressed, held or released, then
-    // the first one seen (in order of the Direction Map (Up Down Left Right )) is
-    // returned
-    pub fn update(&self, context: &mut Context) -> InputFrame {
-        let cur_button = self.update_buttons(context);
+    // Opposing directions are resolved by the player's SOCD policy first, so
+    // at most one direction per axis makes it into the frame
+    // Left is B and right is F, the char mirrors them once it knows which
+    // way it faces, see InputFrame::facing
+    pub fn update(
+        &mut self,
+        context: &Context,
+        gamepads: &GamepadRouter<GamepadId>,
+    ) -> InputFrame {
+        let cur_button = self.update_buttons(context, gamepads);
+        // Directions are always sampled so held/released tracking stays in sync.
+        let cur_direction = self.update_directions(context, gamepads);
         // M.U.G.E.N.
         if cur_button.no_input() {
-            let cur_direction = self.update_directions(context);
             return cur_direction;
         } else {
             return cur_button;
@@ -117,7 +203,7 @@ impl InputSystem {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum InputFrame {
     Pressed(Key),
     Released(Key),
@@ -126,6 +212,29 @@ pub enum InputFrame {
 }
 
 impl InputFrame {
+    // Frames come in as pressed, left is B and right is F. A char facing
+    // left gets them mirrored, so B is always away from where it faces.
+    pub fn facing(self, facing: f32) -> InputFrame {
+        if facing >= 0.0 {
+            return self;
+        }
+        let mirror = |key: Key| match key {
+            Key::Direction(DirectionKind::Single(dir)) => {
+                Key::Direction(DirectionKind::Single(dir.mirrored()))
+            }
+            Key::Direction(DirectionKind::FourWay(dir)) => {
+                Key::Direction(DirectionKind::FourWay(dir.mirrored()))
+            }
+            key => key,
+        };
+        match self {
+            InputFrame::Pressed(key) => InputFrame::Pressed(mirror(key)),
+            InputFrame::Released(key) => InputFrame::Released(mirror(key)),
+            InputFrame::Held(key) => InputFrame::Held(mirror(key)),
+            InputFrame::NoInput => InputFrame::NoInput,
+        }
+    }
+
     pub fn no_input(&self) -> bool {
         match self {
             InputFrame::NoInput => true,
@@ -134,11 +243,14 @@ impl InputFrame {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct InputState {
     input_buffer: Vec<Element>,
     seccess_buffer: SuccessBuffer,
+    frame_count: u64,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 struct SuccessBuffer {
     buffer: Vec<Command>,
     insert_times: Vec<u64>,
@@ -214,10 +326,13 @@ impl SuccessBuffer {
 }
 
 impl InputState {
+    const MAX_BUFFER_LEN: usize = 120;
+
     pub fn new() -> Self {
         Self {
             input_buffer: Vec::new(),
             seccess_buffer: SuccessBuffer::new(),
+            frame_count: 0,
         }
     }
 
@@ -276,7 +391,14 @@ impl InputState {
 
     pub fn update(&mut self, frame_no: i32, frame: InputFrame, command_list: &CommandList) {
         self.update_input_buffer(frame);
-        self.update_success_buffer(self.input_buffer.len() as i32, command_list);
+        self.frame_count += 1;
+        // commands only look back `time` frames, so older input can go. This
+        // keeps the buffer (and every snapshot of it) a fixed size.
+        if self.input_buffer.len() > Self::MAX_BUFFER_LEN {
+            let excess = self.input_buffer.len() - Self::MAX_BUFFER_LEN;
+            self.input_buffer.drain(..excess);
+        }
+        self.update_success_buffer(self.frame_count as i32, command_list);
     }
 
     pub fn command(&self, name: &str) -> bool {
@@ -459,4 +581,66 @@ impl InputState {
         }
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(dirs: &[Direction]) -> HashSet<Direction> {
+        dirs.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_socd_neutral() {
+        let mut socd = SocdCleaner::new(SocdPolicy::Neutral);
+        socd.clean(held(&[Direction::B]));
+        let cleaned = socd.clean(held(&[Direction::B, Direction::F]));
+        assert_eq!(cleaned, held(&[]));
+        // and the cancelled out directions aren't sent on as any direction
+        assert_eq!(get_ordinals(cleaned), None);
+    }
+
+    #[test]
+    fn test_socd_last_input_wins() {
+        let mut socd = SocdCleaner::new(SocdPolicy::LastInputWins);
+        socd.clean(held(&[Direction::B]));
+        assert_eq!(
+            socd.clean(held(&[Direction::B, Direction::F])),
+            held(&[Direction::F])
+        );
+        // letting go of the newer direction goes back to the older one
+        assert_eq!(socd.clean(held(&[Direction::B])), held(&[Direction::B]));
+    }
+
+    #[test]
+    fn test_socd_first_input_wins() {
+        let mut socd = SocdCleaner::new(SocdPolicy::FirstInputWins);
+        socd.clean(held(&[Direction::D]));
+        assert_eq!(
+            socd.clean(held(&[Direction::D, Direction::U, Direction::F])),
+            held(&[Direction::D, Direction::F])
+        );
+    }
+
+    #[test]
+    fn test_socd_up_priority() {
+        let mut socd = SocdCleaner::new(SocdPolicy::UpPriority);
+        socd.clean(held(&[Direction::U]));
+        assert_eq!(
+            socd.clean(held(&[Direction::U, Direction::D, Direction::B, Direction::F])),
+            held(&[Direction::U])
+        );
+    }
+
+    #[test]
+    fn test_socd_same_tick_is_deterministic() {
+        for _ in 0..16 {
+            let mut socd = SocdCleaner::new(SocdPolicy::LastInputWins);
+            assert_eq!(
+                socd.clean(held(&[Direction::F, Direction::B])),
+                held(&[Direction::F])
+            );
+        }
+    }
+}
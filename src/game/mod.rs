@@ -3,14 +3,36 @@ use self::battle::{BattleState, BattleSystem};
 pub mod animation;
 pub mod battle;
 pub mod char;
+pub mod env_shake;
+pub mod fx;
+pub mod gamepad;
+pub mod get_hit;
+pub mod hit;
 pub mod input;
+pub mod pause;
+pub mod push;
+pub mod random;
+pub mod replay;
 pub mod state_manager;
+pub mod target;
+#[cfg(test)]
+pub mod test_utils;
 
 pub struct GameSystem {
     pub battle: BattleSystem,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct GameState {
     pub battle: BattleState,
 }
+
+impl GameState {
+    pub fn save_state(&self) -> GameState {
+        self.clone()
+    }
+
+    pub fn load_state(&mut self, snapshot: &GameState) {
+        self.clone_from(snapshot);
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+// A Pause or SuperPause. Everyone but the char that paused is frozen for
+// `time` ticks, and that char too once its `move_time` runs out.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pause {
+    pub time: i32,
+    pub move_time: i32,
+    pub super_pause: bool,
+    pub darken: bool,       // SuperPause dims the stage
+    pub p2_def_mul: f32,    // defence multiplier of everyone else while it lasts
+    pub unhittable: bool,   // the char that paused can't be hit while it lasts
+}
+
+impl Pause {
+    // Whether `player` sits this tick out. `owner` paused.
+    pub fn freezes(&self, owner: usize, player: usize) -> bool {
+        player != owner || self.move_time <= 0
+    }
+
+    // Counts down a tick, None once it's over.
+    pub fn tick(self) -> Option<Pause> {
+        if self.time <= 1 {
+            return None;
+        }
+        Some(Pause {
+            time: self.time - 1,
+            move_time: (self.move_time - 1).max(0),
+            ..self
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::input::InputFrame;
+    use crate::game::test_utils::battle;
+    use crate::spec::cmd::{Direction, DirectionKind, Key};
+
+    #[test]
+    fn test_pause_freezes_everyone_else() {
+        let (mut system, mut state) = battle(2, 0);
+        let fwd = Key::Direction(DirectionKind::Single(Direction::F));
+        state.pause = Some((
+            0,
+            Pause {
+                time: 3,
+                move_time: 2,
+                super_pause: false,
+                darken: false,
+                p2_def_mul: 1.0,
+                unhittable: false,
+            },
+        ));
+
+        let p2_start = state.chars[1].position;
+        let mut p1_positions = Vec::new();
+        for tick in 0..4 {
+            let pressed = match tick {
+                0 => InputFrame::Pressed(fwd.clone()),
+                _ => InputFrame::Held(fwd.clone()),
+            };
+            system.update(&mut state, vec![pressed.clone(), pressed]);
+            p1_positions.push(state.chars[0].position.x);
+            if tick < 3 {
+                assert_eq!(state.chars[1].position, p2_start);
+                assert_eq!(state.chars[1].get_state_time(), 0);
+            }
+        }
+
+        // p1 walks during its 2 move ticks, then waits out the last one
+        assert!(p1_positions[1] > p1_positions[0]);
+        assert_eq!(p1_positions[2], p1_positions[1]);
+        assert!(state.pause.is_none());
+        // and p2 only started walking once it was over
+        assert_eq!(state.chars[1].get_state_no(), 20);
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+
+use super::char::CharState;
+use crate::spec::{constants::char_constants::CharConstants, state::StateType};
+
+// Left and right edges of the stage, in char positions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub left: f32,
+    pub right: f32,
+}
+
+// A char's body, from its [Size] constants or a Width override. y grows
+// downwards like positions do, so top < bottom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PushBox {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+impl PushBox {
+    pub fn new(char: &CharState, constants: &CharConstants) -> PushBox {
+        let size = |name: &str, default: f32| constants.get_float(name).unwrap_or(default);
+        let (front, back) = char.player_width.unwrap_or_else(|| {
+            if char.get_state_type() == StateType::A {
+                (size("size.air.front", 12.0), size("size.air.back", 12.0))
+            } else {
+                (size("size.ground.front", 16.0), size("size.ground.back", 15.0))
+            }
+        });
+        let (x, y) = char.get_position();
+        let (left, right) = if char.facing < 0.0 {
+            (x - front, x + back)
+        } else {
+            (x - back, x + front)
+        };
+        PushBox {
+            left,
+            right,
+            top: y - size("size.height", 60.0),
+            bottom: y,
+        }
+    }
+
+    // How far the boxes overlap horizontally. A char that's entirely above
+    // the other's head is jumping over it, so that's no overlap.
+    pub fn overlap(&self, other: &PushBox) -> f32 {
+        if self.bottom <= other.top || other.bottom <= self.top {
+            return 0.0;
+        }
+        (self.right.min(other.right) - self.left.max(other.left)).max(0.0)
+    }
+}
+
+// Keeps every char on the stage and pushes overlapping players apart. A char
+// that's cornered can't give way, so the other one takes the whole push.
+pub fn resolve(chars: &mut [CharState], constants: &[&CharConstants], bounds: Bounds) {
+    for (char, constants) in chars.iter_mut().zip(constants) {
+        keep_in_bounds(char, constants, bounds);
+    }
+    for j in 1..chars.len() {
+        let (head, tail) = chars.split_at_mut(j);
+        for (i, a) in head.iter_mut().enumerate() {
+            push_apart(a, &mut tail[0], constants[i], constants[j], bounds);
+        }
+    }
+    for char in chars.iter_mut() {
+        char.reset_push_overrides();
+    }
+}
+
+fn push_apart(
+    a: &mut CharState,
+    b: &mut CharState,
+    a_constants: &CharConstants,
+    b_constants: &CharConstants,
+    bounds: Bounds,
+) {
+    if !a.player_push || !b.player_push {
+        return;
+    }
+    let (a_box, b_box) = (PushBox::new(a, a_constants), PushBox::new(b, b_constants));
+    let overlap = a_box.overlap(&b_box);
+    if overlap <= 0.0 {
+        return;
+    }
+
+    // whoever is further left goes left, chars on the same spot go the way
+    // their backs are facing
+    let a_is_left = match (a_box.left + a_box.right).partial_cmp(&(b_box.left + b_box.right)) {
+        Some(Ordering::Less) => true,
+        Some(Ordering::Greater) => false,
+        _ => a.facing > 0.0,
+    };
+    let (left, right, left_constants, right_constants) = if a_is_left {
+        (a, b, a_constants, b_constants)
+    } else {
+        (b, a, b_constants, a_constants)
+    };
+
+    left.position.x -= overlap / 2.0;
+    right.position.x += overlap / 2.0;
+    keep_in_bounds(left, left_constants, bounds);
+    keep_in_bounds(right, right_constants, bounds);
+
+    let overlap = |left: &CharState, right: &CharState| {
+        PushBox::new(left, left_constants).overlap(&PushBox::new(right, right_constants))
+    };
+    let rest = overlap(left, right);
+    if rest > 0.0 {
+        right.position.x += rest;
+        keep_in_bounds(right, right_constants, bounds);
+        let rest = overlap(left, right);
+        left.position.x -= rest;
+        keep_in_bounds(left, left_constants, bounds);
+    }
+}
+
+// The edge width from Width keeps the body that much further from the edge.
+fn keep_in_bounds(char: &mut CharState, constants: &CharConstants, bounds: Bounds) {
+    let body = PushBox::new(char, constants);
+    let (front, back) = char.edge_width;
+    let (edge_left, edge_right) = if char.facing < 0.0 {
+        (front, back)
+    } else {
+        (back, front)
+    };
+    let min = bounds.left + edge_left;
+    let max = bounds.right - edge_right;
+    if body.left < min {
+        char.position.x += min - body.left;
+    } else if body.right > max {
+        char.position.x -= body.right - max;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::battle::{BattleState, BattleSystem};
+    use crate::game::input::InputFrame;
+    use crate::game::test_utils::battle;
+    use crate::spec::cmd::{Direction, DirectionKind, Key};
+
+    // Both players hold forward for the given number of ticks (p2 only if
+    // `both_walk`), checking they never overlap.
+    fn walk(system: &mut BattleSystem, state: &mut BattleState, both_walk: bool, ticks: usize) {
+        let hold = |dir: Direction, tick: usize| {
+            let key = Key::Direction(DirectionKind::Single(dir));
+            match tick {
+                0 => InputFrame::Pressed(key),
+                _ => InputFrame::Held(key),
+            }
+        };
+
+        for tick in 0..ticks {
+            // p2 faces p1 from the right, so its forward is left
+            let p2 = if both_walk {
+                hold(Direction::B, tick)
+            } else {
+                InputFrame::NoInput
+            };
+            system.update(state, vec![hold(Direction::F, tick), p2]);
+
+            let p1 = PushBox::new(&state.chars[0], system.constants(0));
+            let p2 = PushBox::new(&state.chars[1], system.constants(1));
+            assert!(p1.overlap(&p2) < 0.001, "overlapping at tick {}", tick);
+        }
+    }
+
+    #[test]
+    fn test_players_dont_walk_through_each_other() {
+        let (mut system, mut state) = battle(2, 0);
+        walk(&mut system, &mut state, true, 120);
+
+        let p1 = PushBox::new(&state.chars[0], system.constants(0));
+        let p2 = PushBox::new(&state.chars[1], system.constants(1));
+        assert!(p1.right <= p2.left + 0.001);
+        // they meet in the middle
+        assert!((p1.right + p2.left).abs() < 10.0);
+    }
+
+    #[test]
+    fn test_cornered_player_passes_push_on() {
+        let (mut system, mut state) = battle(2, 0);
+        system.set_bounds(-400.0, 400.0);
+        walk(&mut system, &mut state, false, 120);
+
+        let p1 = PushBox::new(&state.chars[0], system.constants(0));
+        let p2 = PushBox::new(&state.chars[1], system.constants(1));
+        assert!((p2.right - 400.0).abs() < 0.001);
+        assert!(p1.right <= p2.left + 0.001);
+    }
+
+    #[test]
+    fn test_bounds_follow_screen_width() {
+        let (mut system, mut state) = battle(2, 0);
+        system.set_screen_width(960.0);
+        walk(&mut system, &mut state, false, 120);
+
+        // 60 pixels in from the edge of a 960 wide screen
+        let p2 = PushBox::new(&state.chars[1], system.constants(1));
+        assert!((p2.right - 420.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_jumping_over_doesnt_push() {
+        let standing = PushBox {
+            left: -60.0,
+            right: 64.0,
+            top: -240.0,
+            bottom: 0.0,
+        };
+        let above = PushBox {
+            left: 0.0,
+            right: 96.0,
+            top: -500.0,
+            bottom: -250.0,
+        };
+        assert_eq!(standing.overlap(&above), 0.0);
+
+        let coming_down = PushBox {
+            top: -440.0,
+            bottom: -200.0,
+            ..above
+        };
+        assert_eq!(standing.overlap(&coming_down), 64.0);
+    }
+}
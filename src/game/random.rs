@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+// Park-Miller "minimal standard" generator, same as IKEMEN. It lives in the
+// BattleState, so snapshots, replays and netplay all agree on every roll.
+// Simulation code must only ever roll through this.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rng {
+    seed: i32,
+}
+
+impl Rng {
+    const MODULUS: i64 = 2147483647;
+    const MULTIPLIER: i64 = 48271;
+
+    pub fn new(seed: u64) -> Rng {
+        // zero would get stuck, so keep the seed in 1..MODULUS
+        let seed = (seed % (Self::MODULUS as u64 - 1)) as i32 + 1;
+        Rng { seed }
+    }
+
+    pub fn seed(&self) -> i32 {
+        self.seed
+    }
+
+    pub fn next(&mut self) -> i32 {
+        self.seed = ((self.seed as i64 * Self::MULTIPLIER) % Self::MODULUS) as i32;
+        self.seed
+    }
+
+    // Inclusive on both ends, like MUGEN's ranges.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        // i64 so a range as wide as i32 itself doesn't overflow
+        let span = max as i64 - min as i64 + 1;
+        (min as i64 + self.next() as i64 % span) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequence() {
+        let mut a = Rng::new(1234);
+        let mut b = Rng::new(1234);
+        let rolls: Vec<i32> = (0..100).map(|_| a.range(0, 999)).collect();
+        assert!(rolls.iter().all(|roll| (0..=999).contains(roll)));
+        assert_eq!(rolls, (0..100).map(|_| b.range(0, 999)).collect::<Vec<_>>());
+
+        let mut c = Rng::new(4321);
+        assert_ne!(rolls, (0..100).map(|_| c.range(0, 999)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let mut rng = Rng::new(0);
+        assert_eq!(rng.range(5, 5), 5);
+        for _ in 0..1000 {
+            assert!((-3..=3).contains(&rng.range(-3, 3)));
+        }
+        for _ in 0..1000 {
+            rng.range(i32::MIN, i32::MAX);
+            assert!(rng.range(-2, i32::MAX) >= -2);
+        }
+    }
+}
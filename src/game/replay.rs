@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use super::battle::{BattleState, BattleSystem};
+use super::char::CharState;
+use super::input::InputFrame;
+
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Everything needed to rebuild the match before the first tick.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub engine_version: String,
+    pub seed: u64,
+    pub screen_width: f32,
+    pub stage: String,
+    pub chars: Vec<String>, // .def file per player
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub inputs: Vec<InputFrame>, // one per player
+    pub hashes: Vec<u64>,        // state_hash of each player after the tick
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub frames: Vec<ReplayFrame>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    // re-simulating `frame` gave `player` a different state than was recorded
+    Desync {
+        frame: usize,
+        player: usize,
+        expected: u64,
+        actual: u64,
+    },
+    // `frame` doesn't have an input and a hash per player, so the file is
+    // truncated or corrupt, or the header doesn't list the players it was
+    // recorded with
+    PlayerCount {
+        frame: usize,
+        players: usize,
+        inputs: usize,
+        hashes: usize,
+    },
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Desync {
+                frame,
+                player,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "desync at frame {} on player {} (expected {:x}, got {:x})",
+                frame, player, expected, actual
+            ),
+            ReplayError::PlayerCount {
+                frame,
+                players,
+                inputs,
+                hashes,
+            } => write!(
+                f,
+                "frame {} has {} inputs and {} hashes for {} players",
+                frame, inputs, hashes, players
+            ),
+        }
+    }
+}
+
+impl Replay {
+    pub fn new(header: ReplayHeader) -> Self {
+        Self {
+            header,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(replay_file_path: &str) -> Result<Replay, String> {
+        let data = fs::read_to_string(replay_file_path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, replay_file_path: &str) -> Result<(), String> {
+        let data = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(replay_file_path, data).map_err(|e| e.to_string())
+    }
+
+    // Called after every tick with the inputs that drove it.
+    pub fn record(&mut self, inputs: Vec<InputFrame>, state: &BattleState) {
+        self.frames.push(ReplayFrame {
+            inputs,
+            hashes: state.chars.iter().map(state_hash).collect(),
+        });
+    }
+
+    // Re-simulates the whole match from the recorded inputs, checking the
+    // state hash of every player on every frame.
+    pub fn verify(
+        &self,
+        system: &mut BattleSystem,
+        state: &mut BattleState,
+    ) -> Result<(), ReplayError> {
+        for (frame_no, frame) in self.frames.iter().enumerate() {
+            let players = state.chars.len();
+            if frame.inputs.len() != players || frame.hashes.len() != players {
+                return Err(ReplayError::PlayerCount {
+                    frame: frame_no,
+                    players,
+                    inputs: frame.inputs.len(),
+                    hashes: frame.hashes.len(),
+                });
+            }
+            system.update(state, frame.inputs.clone());
+            for (player, (char, &expected)) in state.chars.iter().zip(&frame.hashes).enumerate() {
+                let actual = state_hash(char);
+                if actual != expected {
+                    return Err(ReplayError::Desync {
+                        frame: frame_no,
+                        player,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Builds the battle described by the header, without any graphics.
+    pub fn build_battle(&self) -> (BattleSystem, BattleState) {
+        if self.header.engine_version != ENGINE_VERSION {
+            eprintln!(
+                "replay was recorded with engine {}, running {}",
+                self.header.engine_version, ENGINE_VERSION
+            );
+        }
+        let mut system = BattleSystem::new();
+        system.set_screen_width(self.header.screen_width);
+        let chars = self
+            .header
+            .chars
+            .iter()
+            .map(|def| system.add_player(def, self.header.screen_width))
+            .collect();
+        (system, BattleState::new(chars, self.header.seed))
+    }
+}
+
+// FNV-1a over the parts of the character that matter for a desync check.
+// Hand rolled so the value is stable across platforms and compiler versions.
+pub fn state_hash(char: &CharState) -> u64 {
+    let (pos_x, pos_y) = char.get_position();
+    let (vel_x, vel_y) = char.get_velocity();
+    let words: [u32; 6] = [
+        char.get_state_no() as u32,
+        pos_x.to_bits(),
+        pos_y.to_bits(),
+        vel_x.to_bits(),
+        vel_y.to_bits(),
+        char.get_life() as u32,
+    ];
+
+    fnv1a(&words)
+}
+
+// Hash of the whole match, for checksums exchanged during netplay.
+pub fn battle_hash(state: &BattleState) -> u64 {
+    let mut words = vec![state.frame as u32, state.rng.seed() as u32];
+    for char in &state.chars {
+        let hash = state_hash(char);
+        words.push(hash as u32);
+        words.push((hash >> 32) as u32);
+    }
+    fnv1a(&words)
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv1a(words: &[u32]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for word in words {
+        for byte in word.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_idle(frames: usize) -> Replay {
+        let mut replay = Replay::new(ReplayHeader {
+            engine_version: ENGINE_VERSION.to_string(),
+            seed: 0,
+            screen_width: 1280.0,
+            stage: String::new(),
+            chars: vec!["./resources/kfm720.def".to_string()],
+        });
+        let (mut system, mut state) = replay.build_battle();
+        for _ in 0..frames {
+            let inputs = vec![InputFrame::NoInput];
+            system.update(&mut state, inputs.clone());
+            replay.record(inputs, &state);
+        }
+        replay
+    }
+
+    #[test]
+    fn test_replay_round_trip() {
+        let replay = record_idle(120);
+        let json = serde_json::to_string(&replay).unwrap();
+        let loaded: Replay = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, replay);
+
+        let (mut system, mut state) = loaded.build_battle();
+        assert_eq!(loaded.verify(&mut system, &mut state), Ok(()));
+    }
+
+    #[test]
+    fn test_replay_desync() {
+        let mut replay = record_idle(30);
+        replay.frames[10].hashes[0] ^= 1;
+
+        let (mut system, mut state) = replay.build_battle();
+        match replay.verify(&mut system, &mut state) {
+            Err(ReplayError::Desync { frame, player, .. }) => {
+                assert_eq!(frame, 10);
+                assert_eq!(player, 0);
+            }
+            other => panic!("expected a desync, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replay_missing_hashes() {
+        let mut replay = record_idle(30);
+        replay.frames[5].hashes.clear();
+
+        let (mut system, mut state) = replay.build_battle();
+        assert_eq!(
+            replay.verify(&mut system, &mut state),
+            Err(ReplayError::PlayerCount {
+                frame: 5,
+                players: 1,
+                inputs: 1,
+                hashes: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_replay_missing_inputs() {
+        let mut replay = record_idle(30);
+        replay.frames[7].inputs.clear();
+
+        let (mut system, mut state) = replay.build_battle();
+        assert_eq!(
+            replay.verify(&mut system, &mut state),
+            Err(ReplayError::PlayerCount {
+                frame: 7,
+                players: 1,
+                inputs: 0,
+                hashes: 1
+            })
+        );
+    }
+}
@@ -1,89 +1,281 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
 
-use crate::spec::state::StateDef;
-use crate::spec::triggers::ExpressionContext;
+use crate::game::random::Rng;
+use crate::spec::controllers::StateArgs;
+use crate::spec::state::{MoveType, Physics, StateDef, StateType};
+use crate::spec::triggers::{Expression, ExpressionContext};
+use crate::utils::warn_once;
 use crate::CharState;
 
+// A state that keeps changing to itself would otherwise hang the tick.
+const MAX_STATE_CHANGES: usize = 16;
+
+// Only holds the parsed statedefs. Which state was entered last lives on the
+// CharState, so the manager itself never changes during a match and every
+// char loaded from the same files (p1 and p2 in a mirror match) shares one.
+#[derive(Clone)]
 pub struct StateManager {
-    state_map: HashMap<i32, StateDef>,
-    current_state: i32,
-    last_state: i32,
+    state_map: Arc<HashMap<i32, StateDef>>,
 }
 
 impl StateManager {
     pub fn new(state_map: HashMap<i32, StateDef>) -> Self {
         Self {
-            state_map,
-            current_state: 0,
-            last_state: 0,
+            state_map: Arc::new(state_map),
         }
     }
 
-    pub fn add_state(&mut self, state_no: i32, state: StateDef) {
-        self.state_map.insert(state_no, state);
+    // The actions the statedefs' anim and the ChangeAnims change to, where
+    // those are plain numbers. ChangeAnim2 is left out, it's another char's.
+    pub fn referenced_actions(&self) -> BTreeSet<i32> {
+        let mut actions = BTreeSet::new();
+        for state_def in self.state_map.values() {
+            if let Some(anim_no) = state_def.anim.as_ref().and_then(|anim| anim.constant_int()) {
+                actions.insert(anim_no);
+            }
+            for state in &state_def.states {
+                if let StateArgs::ChangeAnim(args) = &state.args {
+                    actions.extend(args.constant_anim_no());
+                }
+            }
+        }
+        actions
     }
 
-    pub fn update(&mut self, char: &mut CharState, ctx: &mut ExpressionContext) {
-        self.current_state = char.state_no;
-        self.run_state(char, -1, ctx);
-        self.run_state(char, self.current_state, ctx);
-        self.last_state = self.current_state;
-    }
+    // MUGEN's order: -3 and -1 are the char's own states so they're skipped
+    // in a custom state, -2 always runs. `self` is the char's own manager and
+    // `owner` its state owner's, whose statedefs it runs while in a custom
+    // state. A ChangeState starts running the new state straight away, in the
+    // same tick, and so does landing on `floor` once the state is done. The
+    // triggers and controllers roll from `rng`, the battle's.
+    pub fn update(
+        &self,
+        char: &mut CharState,
+        owner: Option<&StateManager>,
+        ctx: &mut ExpressionContext,
+        rng: &mut Rng,
+        floor: f32,
+    ) {
+        let rng = Cell::from_mut(rng);
+        if char.last_state == char.state_no {
+            // time stands still in a hitpause
+            if !char.in_hit_pause() {
+                char.increment_state_time();
+                ctx.mid_frame_update(char);
+            }
+        } else {
+            // changed state on its own, e.g. walking or landing
+            self.states(char, owner).enter_state(char, ctx, rng);
+        }
+
+        if !char.in_custom_state() {
+            self.run_state(char, -3, owner, ctx, rng);
+        }
+        self.run_state(char, -2, owner, ctx, rng);
+        if !char.in_custom_state() {
+            self.run_state(char, -1, owner, ctx, rng);
+        }
 
-    pub fn get_current_state(&self) -> i32 {
-        self.current_state
+        let mut state_no = char.state_no;
+        for _ in 0..MAX_STATE_CHANGES {
+            if !self.run_state(char, state_no, owner, ctx, rng) {
+                if !char.land(floor) {
+                    return;
+                }
+                self.states(char, owner).enter_state(char, ctx, rng);
+            }
+            state_no = char.state_no;
+        }
+        warn_once(format!(
+            "changed state more than {} times in one tick, stopping in state {}",
+            MAX_STATE_CHANGES, state_no
+        ));
     }
 
-    pub fn get_last_state(&self) -> i32 {
-        self.last_state 
+    // Where the char's numbered states come from: the state owner's statedefs
+    // in a custom state, its own otherwise.
+    fn states<'a>(&'a self, char: &CharState, owner: Option<&'a StateManager>) -> &'a StateManager {
+        match owner {
+            Some(owner) if char.in_custom_state() => owner,
+            _ => self,
+        }
     }
 
-    fn run_state(&self, char: &mut CharState, state_no: i32, ctx: &mut ExpressionContext) {
-        let state_container = self.state_map.get(&state_no).unwrap();
+    // Applies the statedef parameters of the state the char just changed to.
+    fn enter_state(&self, char: &mut CharState, ctx: &mut ExpressionContext, rng: &Cell<Rng>) {
+        self.apply_state_def(char, ctx, rng);
+        // the state's first triggers read what the statedef set
+        ctx.mid_frame_update(char);
+    }
 
-        // only do this stuff to initialize a new state
-        if self.last_state != self.current_state {
-            if let Some(anim_no) = state_container.anim {
-                char.set_animation_no(anim_no);
+    fn apply_state_def(&self, char: &mut CharState, ctx: &mut ExpressionContext, rng: &Cell<Rng>) {
+        char.last_state = char.state_no;
+        let state_container = match self.state_map.get(&char.state_no) {
+            Some(state_container) => state_container,
+            None => {
+                warn_once(format!("changed to state {}, which doesn't exist", char.state_no));
+                return;
             }
+        };
 
-            if let Some(ctrl_flag) = state_container.ctrl {
-                char.set_ctrl_flag(if ctrl_flag { 1 } else { 0 });
-            }
+        ctx.mid_frame_update(char);
+        let ctx = ctx.with_rng(rng);
+        if let Some(anim_no) = &state_container.anim {
+            char.set_animation_no(anim_no.evaluate_int(&ctx));
+        }
 
-            if let Some(vel) = state_container.velset {
-                char.set_velocity(vel);
-            }
+        if let Some(ctrl_flag) = &state_container.ctrl {
+            char.set_ctrl_flag(ctrl_flag.evaluate_boolean(&ctx) as i32);
+        }
+
+        if let Some((x, y)) = &state_container.velset {
+            char.velocity.x = x.evaluate_float(&ctx);
+            char.velocity.y = y.evaluate_float(&ctx);
+        }
 
+        // U keeps what the previous state had
+        if state_container.state_type != StateType::U {
             char.set_state_type(state_container.state_type);
+        }
+        if state_container.move_type != MoveType::U {
+            char.set_move_type(state_container.move_type);
+        }
+        if state_container.physics != Physics::U {
             char.set_state_physics(state_container.physics);
-        } else if state_no != -1 {
-            char.increment_state_time();
         }
 
-        // deal with persitency
-        if state_no > 0 {
-            if char.get_persistent_value() == 0 && char.get_persistent_counter() > 0 {
-                return;
-            } else if char.get_persistent_value() > 1
-                && ((char.get_persistent_counter() + 1) % char.get_persistent_value()) != 0
-            {
-                return;
-            }
-            char.increment_persistent_counter();
+        if let Some(power_add) = &state_container.power_add {
+            let max_power = ctx.get_float("data.power").unwrap_or(3000.0) as i32;
+            char.add_power(power_add.evaluate_int(&ctx), max_power);
+        }
+        if let Some(juggle) = &state_container.juggle {
+            char.juggle = juggle.evaluate_int(&ctx);
+        }
+        if let Some(spr_priority) = &state_container.spr_priority {
+            char.spr_priority = spr_priority.evaluate_int(&ctx);
+        }
+        // the flags are all off unless given
+        let flag = |flag: &Option<Expression>| {
+            flag.as_ref().is_some_and(|flag| flag.evaluate_boolean(&ctx))
+        };
+        if flag(&state_container.face_p2) {
+            char.face_p2 = true;
         }
-        for state in &state_container.states {
-            if state_no == 400 {
-                //dbg!(state_no);
-                //dbg!(&state.label);
-                //dbg!(char.get_state_time());
+
+        if !flag(&state_container.hit_def_persist) {
+            char.hit_def = None;
+        }
+        if !flag(&state_container.move_hit_persist) {
+            char.reset_move_hit();
+        }
+        if !flag(&state_container.hit_count_persist) {
+            char.hit_count = 0;
+        }
+    }
+
+    // Runs the controllers of `state_no` until one of them changes state, in
+    // which case the new state is entered and this returns true. The negative
+    // states are always the char's own.
+    fn run_state(
+        &self,
+        char: &mut CharState,
+        state_no: i32,
+        owner: Option<&StateManager>,
+        ctx: &mut ExpressionContext,
+        rng: &Cell<Rng>,
+    ) -> bool {
+        let state_map = if state_no < 0 {
+            &self.state_map
+        } else {
+            &self.states(char, owner).state_map
+        };
+        // chars don't need a -3, -2 or -1
+        let state_container = match state_map.get(&state_no) {
+            Some(state_container) => state_container,
+            None => return false,
+        };
+
+        for (index, state) in state_container.states.iter().enumerate() {
+            if char.in_hit_pause() && !state.ignore_hit_pause {
+                continue;
             }
 
-            ctx.mid_frame_update(char);
-            if state.trigger_handler.evaluate(ctx) {
-                (state.controller)(char, state.args.clone(), ctx);
+            let triggered = state.trigger_handler.evaluate(&ctx.with_rng(rng));
+            // triggers can assign with :=, even ones that end up false
+            ctx.apply_assignments(char);
+            if !triggered || !char.persistent_fire(state_no, index, state.persistency) {
+                continue;
+            }
+            (state.controller)(char, state.args.clone(), &ctx.with_rng(rng));
+            ctx.apply_assignments(char);
+            if char.last_state != char.state_no {
+                self.states(char, owner).enter_state(char, ctx, rng);
+                return true;
             }
+            // only a controller that ran can have changed what the next
+            // triggers read
             ctx.mid_frame_update(char);
         }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::test_utils::battle;
+    use crate::spec::cns::CNSFile;
+    use crate::utils::ini::parse_ini;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_persistent_and_ignore_hit_pause() {
+        let ini = parse_ini(
+            "[Statedef 0]\n\
+             [State 0, once]\n\
+             type = VarAdd\n\
+             trigger1 = 1\n\
+             v = 0\n\
+             value = 1\n\
+             persistent = 0\n\
+             [State 0, every other tick]\n\
+             type = VarAdd\n\
+             trigger1 = 1\n\
+             v = 1\n\
+             value = 1\n\
+             persistent = 2\n\
+             [State 0, in hitpause too]\n\
+             type = VarAdd\n\
+             trigger1 = 1\n\
+             v = 2\n\
+             value = 1\n\
+             ignorehitpause = 1\n",
+        );
+        let manager = StateManager::new(CNSFile::parse_states(&ini));
+        let (_, mut state) = battle(1, 0);
+        let char = &mut state.chars[0];
+        char.set_state(0);
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        let mut rng = Rng::new(0);
+        let mut tick = |char: &mut CharState| {
+            ctx.update(char);
+            manager.update(char, None, &mut ctx, &mut rng, 0.0);
+            char.tick_hit_pause();
+        };
+
+        for _ in 0..4 {
+            tick(char);
+        }
+        let vars = |char: &CharState| [0, 1, 2].map(|i| char.get_int_var(i));
+        assert_eq!(vars(char), [1, 2, 4]);
+
+        // only the ignorehitpause controller runs while frozen
+        char.start_hit_pause(3, false);
+        for _ in 0..3 {
+            tick(char);
+        }
+        assert_eq!(vars(char), [1, 2, 7]);
     }
 }
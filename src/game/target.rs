@@ -0,0 +1,251 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::char::CharState;
+
+// A char that was hit (or grabbed) by one of this char's HitDefs. `id` is the
+// HitDef's id, which the Target controllers can pick targets by.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Target {
+    pub player: usize,
+    pub id: i32,
+}
+
+// What a Target controller does to the targets. Controllers only get their
+// own char, so these are queued on it and applied by `apply_effects` once its
+// states have run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TargetEffect {
+    State(i32),
+    Bind { time: i32, offset: Vec2 },
+    LifeAdd { value: i32, kill: bool },
+    VelSet(Option<f32>, Option<f32>),
+    VelAdd(Option<f32>, Option<f32>),
+    PowerAdd(i32),
+    Facing(i32),
+    Drop { exclude_id: i32, keep_one: bool },
+}
+
+// Keeps a char at an offset from another one for `time` ticks. The x offset
+// goes the way the other char is facing.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bind {
+    pub to: usize,
+    pub time: i32,
+    pub offset: Vec2,
+}
+
+// Applies what `owner`'s Target controllers queued this tick. `max_power` is
+// each player's [Data] power.
+pub fn apply_effects(chars: &mut [CharState], owner: usize, max_power: &[i32]) {
+    let effects: Vec<(i32, TargetEffect)> = chars[owner].target_effects.drain(..).collect();
+    for (id, effect) in effects {
+        if let TargetEffect::Drop {
+            exclude_id,
+            keep_one,
+        } = effect
+        {
+            drop_targets(&mut chars[owner], exclude_id, keep_one);
+            continue;
+        }
+
+        let players: Vec<usize> = chars[owner]
+            .targets
+            .iter()
+            .filter(|target| id == -1 || target.id == id)
+            .map(|target| target.player)
+            .collect();
+        for player in players {
+            if player == owner || player >= chars.len() {
+                continue;
+            }
+            let (owner_char, target) = pair_mut(chars, owner, player);
+            apply_effect(owner_char, owner, target, &effect, max_power[player]);
+        }
+    }
+}
+
+fn apply_effect(
+    owner_char: &CharState,
+    owner: usize,
+    target: &mut CharState,
+    effect: &TargetEffect,
+    max_power: i32,
+) {
+    match *effect {
+        TargetEffect::State(state_no) => enter_custom_state(owner_char, owner, target, state_no),
+        TargetEffect::Bind { time, offset } => {
+            target.bind = Some(Bind {
+                to: owner,
+                time,
+                offset,
+            });
+        }
+        TargetEffect::LifeAdd { value, kill } => target.add_life(value, kill),
+        // velocities are given the way the owner faces
+        TargetEffect::VelSet(x, y) => {
+            if let Some(x) = x {
+                target.velocity.x = x * owner_char.facing * target.facing;
+            }
+            if let Some(y) = y {
+                target.velocity.y = y;
+            }
+        }
+        TargetEffect::VelAdd(x, y) => {
+            if let Some(x) = x {
+                target.velocity.x += x * owner_char.facing * target.facing;
+            }
+            if let Some(y) = y {
+                target.velocity.y += y;
+            }
+        }
+        TargetEffect::PowerAdd(power) => target.add_power(power, max_power),
+        // positive faces the same way as the owner, negative the other way
+        TargetEffect::Facing(facing) => {
+            if facing > 0 {
+                target.facing = owner_char.facing;
+            } else if facing < 0 {
+                target.facing = -owner_char.facing;
+            }
+        }
+        TargetEffect::Drop { .. } => {}
+    }
+}
+
+// Puts `target` in one of `owner`'s states, running them with `owner`'s
+// anims until it SelfStates back.
+pub fn enter_custom_state(
+    owner_char: &CharState,
+    owner: usize,
+    target: &mut CharState,
+    state_no: i32,
+) {
+    target.state_owner = Some(owner);
+    target.animator.lend_actions(&owner_char.animator);
+    target.set_state(state_no);
+}
+
+// exclude_id = -1 drops every target, otherwise the ones with that id are
+// kept, only the first of them with keep_one.
+fn drop_targets(char: &mut CharState, exclude_id: i32, keep_one: bool) {
+    if exclude_id == -1 {
+        char.targets.clear();
+        return;
+    }
+    char.targets.retain(|target| target.id == exclude_id);
+    if keep_one {
+        char.targets.truncate(1);
+    }
+}
+
+// Moves bound chars to the char they're bound to. Runs once every char has
+// moved, so the bind uses where the other char ended up this tick.
+pub fn resolve_binds(chars: &mut [CharState]) {
+    for i in 0..chars.len() {
+        let bind = match chars[i].bind {
+            Some(bind) if bind.to != i && bind.to < chars.len() => bind,
+            Some(_) => {
+                chars[i].bind = None;
+                continue;
+            }
+            None => continue,
+        };
+        let (char, to) = pair_mut(chars, i, bind.to);
+        char.position.x = to.position.x + bind.offset.x * to.facing;
+        char.position.y = to.position.y + bind.offset.y;
+        char.bind = if bind.time > 1 {
+            Some(Bind {
+                time: bind.time - 1,
+                ..bind
+            })
+        } else {
+            None
+        };
+    }
+}
+
+pub fn pair_mut(chars: &mut [CharState], a: usize, b: usize) -> (&mut CharState, &mut CharState) {
+    if a < b {
+        let (head, tail) = chars.split_at_mut(b);
+        (&mut head[a], &mut tail[0])
+    } else {
+        let (head, tail) = chars.split_at_mut(a);
+        (&mut tail[0], &mut head[b])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::battle::{BattleState, BattleSystem};
+    use crate::game::input::InputFrame;
+    use crate::game::state_manager::StateManager;
+    use crate::game::test_utils::battle;
+    use crate::spec::cns::CNSFile;
+    use crate::utils::ini::parse_ini;
+
+    fn tick(system: &mut BattleSystem, state: &mut BattleState) {
+        system.update(state, vec![InputFrame::NoInput, InputFrame::NoInput]);
+    }
+
+    #[test]
+    fn test_target_state_runs_owners_states() {
+        let (mut system, mut state) = battle(2, 0);
+        // p2's own 200 is nothing like KFM's, and its -2 marks var(5)
+        let ini = parse_ini(
+            "[Statedef 200]\n\
+             anim = 0\n\
+             [Statedef -2]\n\
+             [State -2, mark]\n\
+             type = VarSet\n\
+             trigger1 = 1\n\
+             v = 5\n\
+             value = 1\n",
+        );
+        system.set_state_manager(1, StateManager::new(CNSFile::parse_states(&ini)));
+        state.chars[0].add_target(1, 0);
+        state.chars[0].queue_target_effect(-1, TargetEffect::State(200));
+        apply_effects(&mut state.chars, 0, &[3000, 3000]);
+        assert_eq!(state.chars[1].state_owner, Some(0));
+
+        tick(&mut system, &mut state);
+        let target = &state.chars[1];
+        assert_eq!(target.get_state_no(), 200);
+        assert_eq!(target.get_anim_no(), 200);
+        assert_eq!(target.state_owner, Some(0));
+        assert_eq!(target.get_int_var(5), 1);
+    }
+
+    #[test]
+    fn test_bind_follows_owner() {
+        let (mut system, mut state) = battle(2, 0);
+        state.chars[0].add_target(1, 0);
+        let offset = Vec2::new(40.0, -20.0);
+        state.chars[0].queue_target_effect(-1, TargetEffect::Bind { time: 2, offset });
+        apply_effects(&mut state.chars, 0, &[3000, 3000]);
+        state.chars[1].player_push = false;
+
+        tick(&mut system, &mut state);
+        let (owner, target) = (&state.chars[0], &state.chars[1]);
+        assert_eq!(target.position.x, owner.position.x + 40.0 * owner.facing);
+        assert_eq!(target.position.y, owner.position.y - 20.0);
+        tick(&mut system, &mut state);
+        assert!(state.chars[1].bind.is_none());
+    }
+
+    #[test]
+    fn test_drop_targets() {
+        let (_, mut state) = battle(2, 0);
+        let char = &mut state.chars[0];
+        char.add_target(1, 5);
+        char.add_target(2, 5);
+        char.add_target(3, 7);
+
+        drop_targets(char, 5, false);
+        assert_eq!(char.targets.len(), 2);
+        drop_targets(char, 5, true);
+        assert_eq!(char.targets, [Target { player: 1, id: 5 }]);
+        drop_targets(char, -1, false);
+        assert!(char.targets.is_empty());
+    }
+}
@@ -0,0 +1,14 @@
+use super::battle::{BattleState, BattleSystem};
+
+// The char the tests play with.
+pub const KFM: &str = "./resources/kfm720.def";
+
+// A match between `players` KFMs on a 1280 wide screen, with its rng
+// seeded with `seed`.
+pub fn battle(players: usize, seed: u64) -> (BattleSystem, BattleState) {
+    let mut system = BattleSystem::new();
+    let chars = (0..players)
+        .map(|_| system.add_player(KFM, 1280.0))
+        .collect();
+    (system, BattleState::new(chars, seed))
+}
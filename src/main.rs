@@ -3,62 +3,116 @@ use ggez::{
     event,
     glam::*,
     graphics::{self, Color, ImageFormat},
-    Context, GameResult,
+    Context, GameError, GameResult,
 };
-use spec::{
-    cmd::{self, CmdFile, CommandList},
-    cns::CNSFile,
-    constants::char_constants::{parse_char_constants, CharConstants},
-    triggers::ExpressionContext,
+use spec::{config::input_config::InputConfig, def::char_def::CharDef};
+use std::{
+    env,
+    net::TcpListener,
+    path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use std::{collections::HashMap, env, fs, path};
 mod game;
+mod net;
 mod spec;
 mod utils;
 mod debug; 
 
-use game::animation::Animator;
 use game::{
+    battle::{BattleState, BattleSystem},
     char::*,
-    state_manager::{self, StateManager},
+    gamepad::{GamepadEvent, GamepadEventQueue, GamepadRouter},
+    input::InputFrame,
+    replay::{Replay, ReplayHeader, ENGINE_VERSION},
+    GameState, GameSystem,
+};
+use ggez::event::GamepadId;
+use ggez::input::gamepad::gilrs;
+use net::{
+    lockstep::{LockstepSession, Spectator},
+    rollback::RollbackSession,
+    transport::{LossyTransport, Transport, UdpTransport},
+    NetError, Netplay,
 };
 use utils::sprite_sheet::SpriteSheet;
 
+const CHAR_DEF_PATH: &str = "./resources/kfm720.def";
+
 struct MainState {
-    char: CharState,
     char_sys: CharSystem,
-    state_manager: StateManager,
-    expression_context: ExpressionContext,
+    game: GameSystem,
+    state: GameState,
+    gamepad_events: GamepadEventQueue<GamepadId>,
+    gamepads: GamepadRouter<GamepadId>,
+    recording: Option<(Replay, String)>, // replay being recorded and where to save it
+    netplay: Option<Netplay>,
 }
 
 impl MainState {
     /// Load images and create meshes.
-    fn new(ctx: &mut Context) -> GameResult<MainState> {
-        let sprite_sheet: SpriteSheet = SpriteSheet::new("./resources/kfm720.sff", ctx);
-        let animator: Animator = Animator::new("./resources/kfm720.air");
-        let cmd_file = CmdFile::new("./resources/kfm720.cmd");
-        let command_list = CommandList::new(&cmd_file);
-        let anim_no_set = animator.get_anim_action_no_set(); 
-        let mut expression_context: ExpressionContext = ExpressionContext::new(ctx.gfx.size().0, anim_no_set);
-        let input_states = cmd_file.parse_states();
-        let command_states = CNSFile::new("./resources/common1.cns").get_states();
-        let (char_cns, constants) = CNSFile::new("./resources/kfm720.cns").get_char_constants();
-        expression_context.set_char_constants(&constants);
-        let mut char_states = char_cns.get_states();
-        char_states.extend(input_states);
-        char_states.extend(command_states);
-        let state_manager = StateManager::new(char_states);
-
-        let char = CharBuilder::new()
-            .animator(animator)
-            .command_list(command_list)
-            .build();
-        let char_sys = CharSystem::new(sprite_sheet, constants);
+    fn new(
+        ctx: &mut Context,
+        record_path: Option<String>,
+        netplay: Option<Netplay>,
+        seed: u64,
+    ) -> GameResult<MainState> {
+        let screen_width = ctx.gfx.size().0;
+        let char_def = CharDef::new(CHAR_DEF_PATH);
+        let sprite_file = char_def.get_filenames().sprite_sheet.expect("missing sprite in .def");
+        let sprite_sheet: SpriteSheet = SpriteSheet::new(&char_def.path(&sprite_file), ctx);
+
+        // always a two player match, locally both players share the keyboard
+        // and gamepads, in netplay the other one is on the other machine
+        let players = 2;
+        let seed = match &netplay {
+            Some(Netplay::Lockstep(session)) => session.seed(),
+            _ => seed,
+        };
+        let mut battle = BattleSystem::new();
+        battle.set_screen_width(screen_width);
+        let chars: Vec<CharState> = (0..players)
+            .map(|_| battle.add_player(CHAR_DEF_PATH, screen_width))
+            .collect();
+        // these are drawn as nothing
+        let missing_sprites = sprite_sheet.missing(chars[0].animator.sprites());
+        if !missing_sprites.is_empty() {
+            eprintln!(
+                "{}: the AIR shows sprites the SFF doesn't have: {:?}",
+                CHAR_DEF_PATH, missing_sprites
+            );
+        }
+
+        let input_config = InputConfig::new("./resources/mugen.cfg");
+        let local_players = if netplay.is_some() { 1 } else { players };
+        let bindings = (0..local_players)
+            .map(|player| input_config.player(player))
+            .collect();
+        let char_sys = CharSystem::new(sprite_sheet, bindings);
+
+        if record_path.is_some() && netplay.is_some() {
+            eprintln!("--record is ignored during netplay");
+        }
+        let recording = record_path.filter(|_| netplay.is_none()).map(|path| {
+            let header = ReplayHeader {
+                engine_version: ENGINE_VERSION.to_string(),
+                seed,
+                screen_width,
+                stage: String::new(),
+                chars: vec![CHAR_DEF_PATH.to_string(); players],
+            };
+            (Replay::new(header), path)
+        });
+
         let s = MainState {
-            char,
             char_sys,
-            state_manager,
-            expression_context,
+            game: GameSystem { battle },
+            state: GameState {
+                battle: BattleState::new(chars, seed),
+            },
+            gamepad_events: GamepadEventQueue::new(),
+            gamepads: GamepadRouter::new(),
+            recording,
+            netplay,
         };
         Ok(s)
     }
@@ -68,32 +122,106 @@ impl event::EventHandler<ggez::GameError> for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
         const DESIRED_FPS: u32 = 60;
 
+        self.gamepad_events.poll_connections(ctx);
+        self.gamepads.pump(&mut self.gamepad_events);
+
         while ctx.time.check_update_time(DESIRED_FPS) {
-            //self.rotation += 0.01;
-            // self.char.update(ctx);
-            let frame = self.char_sys.update(ctx);
-            self.char
-                .update(ctx.time.ticks() as i32, frame, &self.char_sys.constants);
-            self.expression_context.update(&self.char);
-            self.state_manager
-                .update(&mut self.char, &mut self.expression_context);
+            let battle = &mut self.game.battle;
+            let state = &mut self.state.battle;
+            let net_error = |e: NetError| GameError::CustomError(e.to_string());
+            match &mut self.netplay {
+                Some(Netplay::Rollback(session)) => {
+                    session.poll().map_err(net_error)?;
+                    // otherwise wait for the other side to catch up
+                    if session.ready(state) {
+                        let input = self.char_sys.update(0, ctx, &self.gamepads);
+                        session.advance(battle, state, input).map_err(net_error)?;
+                    }
+                    continue;
+                }
+                Some(Netplay::Lockstep(session)) => {
+                    let (char_sys, gamepads) = (&mut self.char_sys, &self.gamepads);
+                    session
+                        .advance(battle, state, || char_sys.update(0, ctx, gamepads))
+                        .map_err(net_error)?;
+                    continue;
+                }
+                Some(Netplay::Spectate(spectator)) => {
+                    if spectator.update(battle, state).is_err() {
+                        println!("the host has ended the match");
+                        ctx.request_quit();
+                    }
+                    continue;
+                }
+                None => {}
+            }
+
+            let inputs: Vec<InputFrame> = (0..self.char_sys.local_players())
+                .map(|player| self.char_sys.update(player, ctx, &self.gamepads))
+                .collect();
+            battle.update(state, inputs.clone());
+            if let Some((replay, _)) = &mut self.recording {
+                replay.record(inputs, state);
+            }
         }
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let mut canvas =
-            graphics::Canvas::from_frame(ctx, graphics::Color::from([0.1, 0.2, 0.3, 1.0]));
+        // SuperPause dims the stage, which is only the background for now
+        let [r, g, b] = self.state.battle.background(match self.state.battle.pause {
+            Some((_, pause)) if pause.darken => [0.05, 0.1, 0.15],
+            _ => [0.1, 0.2, 0.3],
+        });
+        let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::new(r, g, b, 1.0));
 
         // Draw an image.
 
-        let (a, b) = self.char.draw();
-        let pos = self.char.position;
-        let draw_pos = self.char.draw_position;
         let size = ctx.gfx.size();
-        self.char_sys.draw(size, &mut canvas, draw_pos, pos, a, b);
+        let env_shake = match self.state.battle.env_shake {
+            Some(env_shake) => env_shake.offset(),
+            None => 0.0,
+        };
+        if let Some(env_color) = self.state.battle.env_color {
+            let [r, g, b] = env_color.color;
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(graphics::Rect::new(0.0, 0.0, size.0, size.1))
+                    .color(Color::from_rgb(r, g, b))
+                    .z(if env_color.under { i32::MIN } else { i32::MAX }),
+            );
+        }
+        for i in 0..self.state.battle.chars.len() {
+            let render = self.state.battle.render(i);
+            let after_images = self.state.battle.after_images(i);
+            let char = &mut self.state.battle.chars[i];
+            let sprite = char.draw();
+            let draw_pos = char.draw_position;
+            for (ghost, render) in after_images.iter().rev() {
+                self.char_sys.draw(
+                    size,
+                    &mut canvas,
+                    draw_pos,
+                    ghost.position + Vec2::new(0.0, env_shake),
+                    char.spr_priority - 1,
+                    ghost.sprite,
+                    render,
+                );
+            }
+            let pos = char.position + Vec2::new(char.shake_offset(), env_shake);
+            self.char_sys.draw(
+                size,
+                &mut canvas,
+                draw_pos,
+                pos,
+                char.spr_priority,
+                sprite,
+                &render,
+            );
+        }
         
-        let debug_text = debug::char_debug(&self.char); 
+        let debug_text = debug::char_debug(&self.state.battle.chars[0]); 
         let debug_pos = Vec2::new(0.0, size.1-160.0); 
         DebugSystem::draw( debug_text, &mut canvas, debug_pos); 
         // // Draw an image with some options, and different filter modes.
@@ -116,9 +244,136 @@ impl event::EventHandler<ggez::GameError> for MainState {
 
         Ok(())
     }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        if let Some((replay, path)) = &self.recording {
+            match replay.save(path) {
+                Ok(()) => println!("saved replay to {}", path),
+                Err(e) => eprintln!("could not save replay {}: {}", path, e),
+            }
+        }
+        Ok(false)
+    }
+
+    fn gamepad_button_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        btn: gilrs::Button,
+        id: GamepadId,
+    ) -> GameResult {
+        self.gamepad_events.push(GamepadEvent::ButtonDown(id, btn));
+        Ok(())
+    }
+
+    fn gamepad_button_up_event(
+        &mut self,
+        _ctx: &mut Context,
+        btn: gilrs::Button,
+        id: GamepadId,
+    ) -> GameResult {
+        self.gamepad_events.push(GamepadEvent::ButtonUp(id, btn));
+        Ok(())
+    }
+
+    fn gamepad_axis_event(
+        &mut self,
+        _ctx: &mut Context,
+        axis: gilrs::Axis,
+        value: f32,
+        id: GamepadId,
+    ) -> GameResult {
+        self.gamepad_events.push(GamepadEvent::Axis(id, axis, value));
+        Ok(())
+    }
+}
+
+// Re-simulates a recorded match without opening a window.
+fn verify_replay(replay_path: &str) -> GameResult {
+    let replay = Replay::load(replay_path).map_err(GameError::CustomError)?;
+    let (mut system, mut state) = replay.build_battle();
+    match replay.verify(&mut system, &mut state) {
+        Ok(()) => {
+            println!("{}: {} frames, no desync", replay_path, replay.frames.len());
+            Ok(())
+        }
+        Err(e) => Err(GameError::CustomError(format!("{}: {}", replay_path, e))),
+    }
+}
+
+// Rollback over UDP:
+//   --rollback <peer addr> [--bind <addr>] [--player 1|2] [--delay <frames>]
+//   [--latency <ms>] [--jitter <ms>] [--loss <percent>]
+//   The last three put a simulated bad connection between the two instances.
+// Lockstep over TCP:
+//   --host <bind addr> [--delay <frames>], --join <host addr>
+//   --spectate <host addr>
+// Rollback peers have to be started with the same --seed, lockstep takes the
+// host's.
+fn netplay(
+    arg_value: impl Fn(&str) -> Option<String>,
+    seed: u64,
+) -> GameResult<Option<Netplay>> {
+    let number = |flag: &str, default: u32| {
+        arg_value(flag)
+            .map(|value| value.parse::<u32>().unwrap_or(default))
+            .unwrap_or(default)
+    };
+    if let Some(bind) = arg_value("--host") {
+        println!("waiting for an opponent on {}", bind);
+        let listener = TcpListener::bind(bind)?;
+        let session = LockstepSession::host(listener, number("--delay", 2) as usize, seed)?;
+        return Ok(Some(Netplay::Lockstep(session)));
+    }
+    if let Some(host) = arg_value("--join") {
+        return Ok(Some(Netplay::Lockstep(LockstepSession::join(host)?)));
+    }
+    if let Some(host) = arg_value("--spectate") {
+        return Ok(Some(Netplay::Spectate(Spectator::connect(host)?)));
+    }
+    let peer = match arg_value("--rollback") {
+        Some(peer) => peer,
+        None => return Ok(None),
+    };
+
+    let bind = arg_value("--bind").unwrap_or_else(|| "0.0.0.0:7550".to_string());
+    let udp = UdpTransport::bind(&bind, &peer)?;
+    let (latency, jitter, loss) = (number("--latency", 0), number("--jitter", 0), number("--loss", 0));
+    let transport: Box<dyn Transport> = if latency > 0 || jitter > 0 || loss > 0 {
+        Box::new(LossyTransport::new(
+            udp,
+            Duration::from_millis(latency as u64),
+            Duration::from_millis(jitter as u64),
+            loss as f32 / 100.0,
+        ))
+    } else {
+        Box::new(udp)
+    };
+    let local_player = number("--player", 1).clamp(1, 2) as usize - 1;
+    let session = RollbackSession::new(transport, local_player, number("--delay", 2) as usize);
+    Ok(Some(Netplay::Rollback(session)))
+}
+
+// Choosing the seed is the one place the clock is allowed near the
+// simulation. It ends up in the replay header or is shared with the peer.
+fn seed_from_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or(0)
 }
 
 pub fn main() -> GameResult {
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    if let Some(replay_path) = arg_value("--replay") {
+        return verify_replay(&replay_path);
+    }
+
     let resource_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
         let mut path = path::PathBuf::from(manifest_dir);
         path.push("resources");
@@ -131,6 +386,13 @@ pub fn main() -> GameResult {
 
     let (mut ctx, events_loop) = cb.build()?;
 
-    let state = MainState::new(&mut ctx).unwrap();
+    let seed = match arg_value("--seed") {
+        Some(seed) => seed.parse().unwrap_or(0),
+        // rollback has no handshake, both sides fall back to the same seed
+        None if arg_value("--rollback").is_some() => 0,
+        None => seed_from_clock(),
+    };
+    let netplay = netplay(&arg_value, seed)?;
+    let state = MainState::new(&mut ctx, arg_value("--record"), netplay, seed).unwrap();
     event::run(ctx, events_loop, state)
 }
@@ -0,0 +1,570 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use serde::{Deserialize, Serialize};
+
+use super::NetError;
+use crate::game::battle::{BattleState, BattleSystem};
+use crate::game::input::InputFrame;
+use crate::game::replay::battle_hash;
+
+#[derive(Serialize, Deserialize)]
+enum LockstepMessage {
+    // first message on every connection to the host
+    Play,
+    Spectate,
+    // host -> opponent, so both sides run the same match
+    Hello { input_delay: usize, seed: u64 },
+    // between the two players
+    Input { frame: usize, input: InputFrame },
+    Checksum { frame: usize, hash: u64 },
+    // host -> spectator: the match as of `state.frame`, then every frame's
+    // inputs from there on
+    Snapshot { state: BattleState },
+    Frame { inputs: Vec<InputFrame> },
+}
+
+// Length prefixed json messages over a non blocking TCP stream. Once the
+// other end goes away the stream only hands out what was already received.
+//
+// Sends never block: whatever the socket won't take yet waits in `outgoing`
+// and goes out on a later `flush`.
+struct MessageStream {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl MessageStream {
+    fn new(stream: TcpStream) -> io::Result<MessageStream> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(MessageStream {
+            stream,
+            buf: Vec::new(),
+            outgoing: Vec::new(),
+            closed: false,
+        })
+    }
+
+    // Errors only matter to whoever needed the message, so a failed send
+    // just marks the stream closed.
+    fn send(&mut self, message: &LockstepMessage) {
+        if self.closed {
+            return;
+        }
+        let body = serde_json::to_vec(message).unwrap();
+        self.outgoing
+            .extend_from_slice(&(body.len() as u32).to_le_bytes());
+        self.outgoing.extend(body);
+        self.flush();
+    }
+
+    // For the handshake, where the caller wants to know it went wrong.
+    fn write(&mut self, message: &LockstepMessage) -> io::Result<()> {
+        self.send(message);
+        match self.closed {
+            true => Err(io::ErrorKind::BrokenPipe.into()),
+            false => Ok(()),
+        }
+    }
+
+    // Writes as much of `outgoing` as the socket takes right now.
+    fn flush(&mut self) {
+        let mut written = 0;
+        while written < self.outgoing.len() && !self.closed {
+            match self.stream.write(&self.outgoing[written..]) {
+                Ok(0) => self.closed = true,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => self.closed = true,
+            }
+        }
+        self.outgoing.drain(..written);
+    }
+
+    // bytes sent but not yet taken by the socket
+    fn pending(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    fn recv(&mut self) -> Option<LockstepMessage> {
+        if let Some(message) = self.parse() {
+            return Some(message);
+        }
+        let mut chunk = [0; 4096];
+        while !self.closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => self.closed = true,
+            }
+        }
+        self.parse()
+    }
+
+    fn parse(&mut self) -> Option<LockstepMessage> {
+        if self.buf.len() < 4 {
+            return None;
+        }
+        let len = u32::from_le_bytes(self.buf[..4].try_into().unwrap()) as usize;
+        if self.buf.len() < 4 + len {
+            return None;
+        }
+        let message = serde_json::from_slice(&self.buf[4..4 + len]);
+        self.buf.drain(..4 + len);
+        match message {
+            Ok(message) => Some(message),
+            Err(e) => {
+                eprintln!("dropping bad lockstep message: {}", e);
+                self.parse()
+            }
+        }
+    }
+
+    fn recv_blocking(&mut self) -> io::Result<LockstepMessage> {
+        loop {
+            if let Some(message) = self.recv() {
+                return Ok(message);
+            }
+            if self.closed {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+}
+
+// Delay based lockstep for two players. Before simulating frame N each side
+// sends its input for frame N + delay, then waits until the other side's input
+// for N has arrived. Nothing is ever predicted, so it needs a connection good
+// enough to deliver inputs within the delay, which on a LAN is a given.
+//
+// The host also relays the confirmed inputs to any number of spectators. One
+// that falls too far behind is dropped rather than holding up the match.
+//
+// Every CHECKSUM_INTERVAL frames both sides send the battle_hash after the
+// frame, so a desync is caught instead of playing out two different matches.
+pub struct LockstepSession {
+    peer: MessageStream,
+    local_player: usize,
+    input_delay: usize,
+    seed: u64, // picked by the host
+    // inputs from frame `inputs_start` on, the older ones were simulated and
+    // relayed
+    local_inputs: VecDeque<InputFrame>,
+    remote_inputs: VecDeque<InputFrame>,
+    inputs_start: usize,
+    local_checksums: HashMap<usize, u64>,
+    remote_checksums: HashMap<usize, u64>,
+    spectator_listener: Option<TcpListener>,
+    joining: Vec<MessageStream>, // connected, haven't said what they want yet
+    spectators: Vec<MessageStream>,
+    waiting_spectators: Vec<MessageStream>, // get a snapshot on the next frame
+}
+
+impl LockstepSession {
+    // how much a spectator may have waiting to be sent before it's dropped
+    const MAX_SPECTATOR_BACKLOG: usize = 1 << 20;
+    const CHECKSUM_INTERVAL: usize = 10;
+
+    // Waits for an opponent on `listener`. Later connections to it become
+    // spectators.
+    pub fn host(
+        listener: TcpListener,
+        input_delay: usize,
+        seed: u64,
+    ) -> io::Result<LockstepSession> {
+        let mut waiting_spectators = Vec::new();
+        let mut peer = loop {
+            let (stream, _) = listener.accept()?;
+            let mut stream = MessageStream::new(stream)?;
+            match stream.recv_blocking()? {
+                LockstepMessage::Play => break stream,
+                LockstepMessage::Spectate => waiting_spectators.push(stream),
+                _ => eprintln!("unexpected first message from a client"),
+            }
+        };
+        peer.write(&LockstepMessage::Hello { input_delay, seed })?;
+        listener.set_nonblocking(true)?;
+
+        let mut session = LockstepSession::new(peer, 0, input_delay, seed);
+        session.spectator_listener = Some(listener);
+        session.waiting_spectators = waiting_spectators;
+        Ok(session)
+    }
+
+    pub fn join<A: ToSocketAddrs>(host_addr: A) -> io::Result<LockstepSession> {
+        let mut peer = MessageStream::new(TcpStream::connect(host_addr)?)?;
+        peer.write(&LockstepMessage::Play)?;
+        match peer.recv_blocking()? {
+            LockstepMessage::Hello { input_delay, seed } => {
+                Ok(LockstepSession::new(peer, 1, input_delay, seed))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "host didn't say hello",
+            )),
+        }
+    }
+
+    fn new(
+        peer: MessageStream,
+        local_player: usize,
+        input_delay: usize,
+        seed: u64,
+    ) -> LockstepSession {
+        LockstepSession {
+            peer,
+            local_player,
+            input_delay,
+            seed,
+            local_inputs: vec![InputFrame::NoInput; input_delay].into(),
+            remote_inputs: vec![InputFrame::NoInput; input_delay].into(),
+            inputs_start: 0,
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            spectator_listener: None,
+            joining: Vec::new(),
+            spectators: Vec::new(),
+            waiting_spectators: Vec::new(),
+        }
+    }
+
+    // Call every tick. `read_input` is only called once the next local input
+    // is due, so no input is lost while waiting on the peer. Returns whether a
+    // frame was simulated.
+    pub fn advance(
+        &mut self,
+        system: &mut BattleSystem,
+        state: &mut BattleState,
+        read_input: impl FnOnce() -> InputFrame,
+    ) -> Result<bool, NetError> {
+        self.accept_spectators();
+        self.flush();
+        while let Some(message) = self.peer.recv() {
+            match message {
+                LockstepMessage::Input { frame, input } => {
+                    if frame == self.inputs_start + self.remote_inputs.len() {
+                        self.remote_inputs.push_back(input);
+                    }
+                }
+                LockstepMessage::Checksum { frame, hash } => {
+                    self.remote_checksums.insert(frame, hash);
+                    self.compare_checksum(frame)?;
+                }
+                _ => {}
+            }
+        }
+
+        let frame = state.frame as usize;
+        let local_end = self.inputs_start + self.local_inputs.len();
+        if local_end <= frame + self.input_delay {
+            let input = read_input();
+            self.peer.send(&LockstepMessage::Input {
+                frame: local_end,
+                input: input.clone(),
+            });
+            self.local_inputs.push_back(input);
+        }
+        if self.inputs_start + self.remote_inputs.len() <= frame {
+            return match self.peer.closed {
+                true => Err(NetError::Disconnected),
+                false => Ok(false),
+            };
+        }
+
+        for mut spectator in self.waiting_spectators.drain(..) {
+            spectator.send(&LockstepMessage::Snapshot {
+                state: state.save_state(),
+            });
+            self.spectators.push(spectator);
+        }
+
+        // both sides have this frame's inputs, so they're done with once
+        // it's simulated
+        let local_input = self.local_inputs.pop_front().unwrap();
+        let remote_input = self.remote_inputs.pop_front().unwrap();
+        self.inputs_start += 1;
+        let inputs = if self.local_player == 0 {
+            vec![local_input, remote_input]
+        } else {
+            vec![remote_input, local_input]
+        };
+        let relay = LockstepMessage::Frame {
+            inputs: inputs.clone(),
+        };
+        // spectators that went away or can't keep up are just dropped
+        self.spectators.retain_mut(|spectator| {
+            spectator.send(&relay);
+            if spectator.pending() > Self::MAX_SPECTATOR_BACKLOG {
+                eprintln!("dropping a spectator that fell behind");
+                return false;
+            }
+            !spectator.closed
+        });
+        system.update(state, inputs);
+
+        if frame % Self::CHECKSUM_INTERVAL == 0 {
+            let hash = battle_hash(state);
+            self.local_checksums.insert(frame, hash);
+            self.peer.send(&LockstepMessage::Checksum { frame, hash });
+            self.compare_checksum(frame)?;
+        }
+        Ok(true)
+    }
+
+    fn compare_checksum(&mut self, frame: usize) -> Result<(), NetError> {
+        if let (Some(&local), Some(&remote)) = (
+            self.local_checksums.get(&frame),
+            self.remote_checksums.get(&frame),
+        ) {
+            self.local_checksums.remove(&frame);
+            self.remote_checksums.remove(&frame);
+            if local != remote {
+                return Err(NetError::Desync {
+                    frame: frame as i32,
+                    local,
+                    remote,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn accept_spectators(&mut self) {
+        if let Some(listener) = &self.spectator_listener {
+            while let Ok((stream, _)) = listener.accept() {
+                if let Ok(stream) = MessageStream::new(stream) {
+                    self.joining.push(stream);
+                }
+            }
+        }
+        let mut joining = Vec::new();
+        for mut stream in self.joining.drain(..) {
+            match stream.recv() {
+                Some(LockstepMessage::Spectate) => self.waiting_spectators.push(stream),
+                None if !stream.closed => joining.push(stream),
+                // the match already has its opponent
+                _ => {}
+            }
+        }
+        self.joining = joining;
+    }
+
+    // Sends whatever the peer and spectators haven't taken yet. `advance`
+    // does this every tick; call it after the last frame until it returns
+    // true so nothing is left behind.
+    pub fn flush(&mut self) -> bool {
+        self.peer.flush();
+        for spectator in &mut self.spectators {
+            spectator.flush();
+        }
+        let streams = std::iter::once(&self.peer).chain(&self.spectators);
+        streams
+            .filter(|stream| !stream.closed)
+            .all(|stream| stream.pending() == 0)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn spectator_count(&self) -> usize {
+        self.spectators.len() + self.waiting_spectators.len()
+    }
+}
+
+// Read only view of a match hosted by a `LockstepSession`. Starts from
+// whatever frame the match was on when it joined.
+pub struct Spectator {
+    host: MessageStream,
+    joined: bool,
+}
+
+impl Spectator {
+    pub fn connect<A: ToSocketAddrs>(host_addr: A) -> io::Result<Spectator> {
+        let mut host = MessageStream::new(TcpStream::connect(host_addr)?)?;
+        host.write(&LockstepMessage::Spectate)?;
+        Ok(Spectator {
+            host,
+            joined: false,
+        })
+    }
+
+    // Whether the snapshot has arrived yet. Until then `state` is untouched.
+    pub fn joined(&self) -> bool {
+        self.joined
+    }
+
+    // Simulates every frame the host has relayed so far. Errors once the host
+    // is gone and everything it sent has been played.
+    pub fn update(
+        &mut self,
+        system: &mut BattleSystem,
+        state: &mut BattleState,
+    ) -> Result<(), NetError> {
+        self.host.flush();
+        while let Some(message) = self.host.recv() {
+            match message {
+                LockstepMessage::Snapshot { state: snapshot } => {
+                    state.load_serialized(snapshot);
+                    self.joined = true;
+                }
+                LockstepMessage::Frame { inputs } if self.joined => system.update(state, inputs),
+                _ => {}
+            }
+        }
+        match self.host.closed {
+            true => Err(NetError::Disconnected),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::replay::battle_hash;
+    use crate::game::test_utils::battle;
+    use crate::spec::cmd::{Button, ButtonKind, Key};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    const FRAMES: i32 = 240;
+
+    fn input(player: usize, frame: usize) -> InputFrame {
+        let key = Key::Button(ButtonKind::Single(Button::a));
+        match (frame + player * 13) % 45 {
+            0 => InputFrame::Pressed(key),
+            10 => InputFrame::Released(key),
+            _ => InputFrame::NoInput,
+        }
+    }
+
+    // Plays the whole match. With `halfway` the session stops in the middle,
+    // says so on the channel and waits for a spectator to show up.
+    fn play(mut session: LockstepSession, mut halfway: Option<mpsc::Sender<()>>) -> u64 {
+        let (mut system, mut state) = battle(2, session.seed());
+        let player = session.local_player;
+        let mut sent = 0;
+        while state.frame < FRAMES {
+            let simulated = session
+                .advance(&mut system, &mut state, || {
+                    sent += 1;
+                    input(player, sent - 1)
+                })
+                .unwrap();
+            if !simulated {
+                thread::sleep(Duration::from_millis(1));
+            }
+            // neither side is more than a delay or two ahead of the other
+            let most = 2 * session.input_delay + 2;
+            assert!(session.local_inputs.len() <= most);
+            assert!(session.remote_inputs.len() <= most);
+            if state.frame == FRAMES / 2 {
+                if let Some(halfway) = halfway.take() {
+                    halfway.send(()).unwrap();
+                    while session.spectator_count() == 0 {
+                        session.accept_spectators();
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }
+        }
+        while !session.flush() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        battle_hash(&state)
+    }
+
+    #[test]
+    fn test_lockstep_with_late_spectator() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (halfway, reached_halfway) = mpsc::channel();
+        let host = thread::spawn(move || {
+            let session = LockstepSession::host(listener, 3, 77).unwrap();
+            play(session, Some(halfway))
+        });
+        let guest = thread::spawn(move || {
+            let session = LockstepSession::join(addr).unwrap();
+            play(session, None)
+        });
+
+        reached_halfway.recv().unwrap();
+        let mut spectator = Spectator::connect(addr).unwrap();
+        // the snapshot brings the host's seed along
+        let (mut system, mut state) = battle(2, 0);
+        while !spectator.joined() || state.frame < FRAMES {
+            let result = spectator.update(&mut system, &mut state);
+            if state.frame >= FRAMES {
+                break;
+            }
+            result.unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let host_hash = host.join().unwrap();
+        let guest_hash = guest.join().unwrap();
+        assert_eq!(host_hash, guest_hash);
+        assert_eq!(battle_hash(&state), host_hash);
+    }
+
+    // Plays idle with its own `seed` rather than the session's until the
+    // session gives up.
+    fn play_until_error(mut session: LockstepSession, seed: u64) -> NetError {
+        let (mut system, mut state) = battle(2, seed);
+        loop {
+            match session.advance(&mut system, &mut state, || InputFrame::NoInput) {
+                Ok(true) => {}
+                Ok(false) => thread::sleep(Duration::from_millis(1)),
+                Err(e) => return e,
+            }
+        }
+    }
+
+    #[test]
+    fn test_lockstep_checksums_catch_desync() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let host = thread::spawn(move || {
+            let session = LockstepSession::host(listener, 3, 77).unwrap();
+            play_until_error(session, 77)
+        });
+        let guest = thread::spawn(move || {
+            let session = LockstepSession::join(addr).unwrap();
+            play_until_error(session, 78)
+        });
+
+        let errors = [host.join().unwrap(), guest.join().unwrap()];
+        // whoever compares first quits, the other may only see it leave
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, NetError::Desync { frame: 0, .. })));
+        assert!(errors
+            .iter()
+            .all(|e| matches!(e, NetError::Desync { .. } | NetError::Disconnected)));
+    }
+
+    #[test]
+    fn test_send_does_not_block_on_a_stalled_reader() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _reader = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = MessageStream::new(listener.accept().unwrap().0).unwrap();
+        let frame = LockstepMessage::Frame {
+            inputs: vec![InputFrame::NoInput; 100],
+        };
+        // nobody reads, so once the socket is full the rest waits in the stream
+        while stream.pending() <= LockstepSession::MAX_SPECTATOR_BACKLOG {
+            stream.send(&frame);
+            assert!(!stream.closed);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+pub mod lockstep;
+pub mod rollback;
+pub mod transport;
+
+use lockstep::{LockstepSession, Spectator};
+use rollback::RollbackSession;
+use transport::Transport;
+
+pub enum Netplay {
+    Rollback(RollbackSession<Box<dyn Transport>>),
+    Lockstep(LockstepSession),
+    Spectate(Spectator),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NetError {
+    // both sides simulated `frame` with the same inputs and got different states
+    Desync { frame: i32, local: u64, remote: u64 },
+    // the other side went away before sending what we needed
+    Disconnected,
+}
+
+impl std::fmt::Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetError::Desync {
+                frame,
+                local,
+                remote,
+            } => write!(
+                f,
+                "desync at frame {} (local {:x}, remote {:x})",
+                frame, local, remote
+            ),
+            NetError::Disconnected => write!(f, "peer disconnected"),
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::transport::Transport;
+use super::NetError;
+use crate::game::battle::{BattleState, BattleSystem};
+use crate::game::input::InputFrame;
+use crate::game::replay::battle_hash;
+use crate::spec::cmd::Key;
+
+#[derive(Debug, Serialize, Deserialize)]
+enum NetMessage {
+    // Every local input from `start` on. `received` is how many of the
+    // receiver's inputs the sender has, which acks them.
+    Input {
+        received: usize,
+        start: usize,
+        inputs: Vec<InputFrame>,
+    },
+    Checksum {
+        frame: i32,
+        hash: u64,
+    },
+}
+
+// GGPO style rollback for two players. The session runs ahead of the remote
+// player by predicting their input, and when the real input turns out to be
+// different it rewinds to the first wrong frame and simulates forward again.
+//
+// Local input is delayed by `input_delay` frames, which trades a little
+// responsiveness for fewer rollbacks.
+pub struct RollbackSession<T> {
+    transport: T,
+    local_player: usize,
+    // Inputs from frame `*_start` on; older ones can't be rolled back to
+    // or resent anymore and are dropped.
+    local_inputs: Vec<InputFrame>,
+    local_start: usize,
+    remote_inputs: Vec<InputFrame>, // confirmed
+    remote_start: usize,
+    remote_received: usize, // how many local inputs the peer has
+    predicted: HashMap<i32, InputFrame>,
+    snapshots: HashMap<i32, BattleState>, // state at the start of each frame
+    rollback_to: Option<i32>,
+    checked_frame: i32,
+    local_checksums: HashMap<i32, u64>,
+    remote_checksums: HashMap<i32, u64>,
+    pub rollbacks: usize,
+}
+
+impl<T: Transport> RollbackSession<T> {
+    // how far the session will run ahead of the last confirmed remote input
+    pub const MAX_PREDICTION: i32 = 8;
+    const CHECKSUM_INTERVAL: i32 = 10;
+
+    pub fn new(transport: T, local_player: usize, input_delay: usize) -> Self {
+        Self {
+            transport,
+            local_player,
+            local_inputs: vec![InputFrame::NoInput; input_delay],
+            local_start: 0,
+            remote_inputs: Vec::new(),
+            remote_start: 0,
+            remote_received: 0,
+            predicted: HashMap::new(),
+            snapshots: HashMap::new(),
+            rollback_to: None,
+            checked_frame: 0,
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            rollbacks: 0,
+        }
+    }
+
+    // Reads everything the peer sent. Call once per tick before `ready`.
+    pub fn poll(&mut self) -> Result<(), NetError> {
+        while let Some(packet) = self.transport.recv() {
+            match serde_json::from_slice(&packet) {
+                Ok(NetMessage::Input {
+                    received,
+                    start,
+                    inputs,
+                }) => {
+                    self.remote_received = self.remote_received.max(received);
+                    self.confirm_remote(start, inputs);
+                }
+                Ok(NetMessage::Checksum { frame, hash }) => {
+                    self.remote_checksums.insert(frame, hash);
+                    self.compare_checksum(frame)?;
+                }
+                Err(e) => eprintln!("dropping bad netplay packet: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    // False while we're too far ahead of the peer, in which case the game
+    // should wait instead of reading input for this tick.
+    pub fn ready(&mut self, state: &BattleState) -> bool {
+        let ready = state.frame - (self.remote_count() as i32) < Self::MAX_PREDICTION;
+        if !ready {
+            // keep the peer fed so they can catch up
+            self.send_inputs();
+        }
+        ready
+    }
+
+    // Simulates one tick with `local_input`, rolling back first if the peer's
+    // inputs showed a prediction was wrong.
+    pub fn advance(
+        &mut self,
+        system: &mut BattleSystem,
+        state: &mut BattleState,
+        local_input: InputFrame,
+    ) -> Result<(), NetError> {
+        self.local_inputs.push(local_input);
+        self.send_inputs();
+
+        if let Some(from) = self.rollback_to.take() {
+            let target = state.frame;
+            state.load_state(&self.snapshots[&from]);
+            while state.frame < target {
+                self.simulate(system, state);
+            }
+            self.rollbacks += 1;
+        }
+        self.simulate(system, state);
+        self.check_confirmed_frames(state)
+    }
+
+    fn simulate(&mut self, system: &mut BattleSystem, state: &mut BattleState) {
+        let frame = state.frame;
+        self.snapshots.insert(frame, state.save_state());
+
+        let remote_input = match self.remote_inputs.get(frame as usize - self.remote_start) {
+            Some(input) => input.clone(),
+            None => {
+                let prediction = self.predict();
+                self.predicted.insert(frame, prediction.clone());
+                prediction
+            }
+        };
+        let local_input = self.local_inputs[frame as usize - self.local_start].clone();
+        let inputs = if self.local_player == 0 {
+            vec![local_input, remote_input]
+        } else {
+            vec![remote_input, local_input]
+        };
+        system.update(state, inputs);
+    }
+
+    // Directions are reported as held every frame they're down, so the peer
+    // most likely still holds whatever it last pressed or held. Buttons are
+    // never reported as held.
+    fn predict(&self) -> InputFrame {
+        match self.remote_inputs.last() {
+            Some(InputFrame::Pressed(key @ Key::Direction(_))) | Some(InputFrame::Held(key)) => {
+                InputFrame::Held(key.clone())
+            }
+            _ => InputFrame::NoInput,
+        }
+    }
+
+    fn confirm_remote(&mut self, start: usize, inputs: Vec<InputFrame>) {
+        for (frame, input) in (start..).zip(inputs) {
+            if frame != self.remote_count() {
+                continue; // already have it, or there's a gap before it
+            }
+            if let Some(prediction) = self.predicted.remove(&(frame as i32)) {
+                if prediction != input {
+                    let from = self
+                        .rollback_to
+                        .map_or(frame as i32, |f| f.min(frame as i32));
+                    self.rollback_to = Some(from);
+                }
+            }
+            self.remote_inputs.push(input);
+        }
+    }
+
+    // how many remote inputs have been confirmed, dropped ones included
+    fn remote_count(&self) -> usize {
+        self.remote_start + self.remote_inputs.len()
+    }
+
+    fn send_inputs(&mut self) {
+        let local_count = self.local_start + self.local_inputs.len();
+        let start = self.remote_received.clamp(self.local_start, local_count);
+        self.send(NetMessage::Input {
+            received: self.remote_count(),
+            start,
+            inputs: self.local_inputs[start - self.local_start..].to_vec(),
+        });
+    }
+
+    fn send(&mut self, message: NetMessage) {
+        let packet = serde_json::to_vec(&message).unwrap();
+        self.transport.send(&packet);
+    }
+
+    // Frames before both the current frame and the first unconfirmed remote
+    // input can't be rolled back anymore, so they're checksummed and their
+    // snapshots and inputs dropped.
+    fn check_confirmed_frames(&mut self, state: &BattleState) -> Result<(), NetError> {
+        let confirmed = state.frame.min(self.remote_count() as i32);
+        while self.checked_frame < confirmed {
+            let frame = self.checked_frame;
+            if frame % Self::CHECKSUM_INTERVAL == 0 {
+                let after = if frame + 1 == state.frame {
+                    state
+                } else {
+                    &self.snapshots[&(frame + 1)]
+                };
+                let hash = battle_hash(after);
+                self.local_checksums.insert(frame, hash);
+                self.send(NetMessage::Checksum { frame, hash });
+                self.compare_checksum(frame)?;
+            }
+            self.checked_frame += 1;
+        }
+        self.snapshots.retain(|&frame, _| frame >= confirmed);
+        // the last confirmed remote input is what `predict` repeats, and
+        // local inputs stay until the peer has acked them
+        let confirmed = confirmed as usize;
+        drop_inputs_before(
+            &mut self.remote_inputs,
+            &mut self.remote_start,
+            confirmed.saturating_sub(1),
+        );
+        drop_inputs_before(
+            &mut self.local_inputs,
+            &mut self.local_start,
+            confirmed.min(self.remote_received),
+        );
+        // checksums whose counterpart was lost in transit
+        let oldest = confirmed as i32 - Self::CHECKSUM_INTERVAL * 60;
+        self.local_checksums.retain(|&frame, _| frame >= oldest);
+        self.remote_checksums.retain(|&frame, _| frame >= oldest);
+        Ok(())
+    }
+
+    fn compare_checksum(&mut self, frame: i32) -> Result<(), NetError> {
+        if let (Some(&local), Some(&remote)) = (
+            self.local_checksums.get(&frame),
+            self.remote_checksums.get(&frame),
+        ) {
+            self.local_checksums.remove(&frame);
+            self.remote_checksums.remove(&frame);
+            if local != remote {
+                return Err(NetError::Desync {
+                    frame,
+                    local,
+                    remote,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Drops the inputs of frames before `frame` from a buffer starting at `start`.
+fn drop_inputs_before(inputs: &mut Vec<InputFrame>, start: &mut usize, frame: usize) {
+    if frame > *start {
+        let count = (frame - *start).min(inputs.len());
+        inputs.drain(..count);
+        *start += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::replay::state_hash;
+    use crate::game::test_utils::battle;
+    use crate::net::transport::{LossyTransport, UdpTransport};
+    use crate::spec::cmd::{Direction, DirectionKind};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::net::UdpSocket;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    // In memory transport; `delay` polls pass before a packet shows up.
+    struct Pipe {
+        outgoing: Rc<RefCell<VecDeque<(usize, Vec<u8>)>>>,
+        incoming: Rc<RefCell<VecDeque<(usize, Vec<u8>)>>>,
+        delay: usize,
+    }
+
+    impl Transport for Pipe {
+        fn send(&mut self, packet: &[u8]) {
+            self.outgoing
+                .borrow_mut()
+                .push_back((self.delay, packet.to_vec()));
+        }
+
+        fn recv(&mut self) -> Option<Vec<u8>> {
+            let mut incoming = self.incoming.borrow_mut();
+            if let Some((0, _)) = incoming.front() {
+                return incoming.pop_front().map(|(_, packet)| packet);
+            }
+            // nothing ready, one poll has gone by
+            for (wait, _) in incoming.iter_mut() {
+                *wait = wait.saturating_sub(1);
+            }
+            None
+        }
+    }
+
+    fn pipes(delay: usize) -> (Pipe, Pipe) {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        (
+            Pipe {
+                outgoing: a.clone(),
+                incoming: b.clone(),
+                delay,
+            },
+            Pipe {
+                outgoing: b,
+                incoming: a,
+                delay,
+            },
+        )
+    }
+
+    // goes quiet at the end so the last predictions are right and both
+    // peers settle on the same final state
+    fn input(player: usize, frame: usize) -> InputFrame {
+        let key = Key::Direction(DirectionKind::Single(Direction::F));
+        if frame >= 150 {
+            return InputFrame::NoInput;
+        }
+        // held for 25 of every 50 frames, reported the way InputSystem does
+        match (frame + player * 17) % 50 {
+            0 => InputFrame::Pressed(key),
+            1..=24 => InputFrame::Held(key),
+            25 => InputFrame::Released(key),
+            _ => InputFrame::NoInput,
+        }
+    }
+
+    struct Peer<T> {
+        session: RollbackSession<T>,
+        system: BattleSystem,
+        state: BattleState,
+        player: usize,
+        sent: usize,
+    }
+
+    impl<T: Transport> Peer<T> {
+        fn new(transport: T, player: usize) -> Self {
+            let (system, state) = battle(2, 0);
+            Self {
+                session: RollbackSession::new(transport, player, 2),
+                system,
+                state,
+                player,
+                sent: 0,
+            }
+        }
+
+        // Stops advancing at `frames` but keeps resending, so a peer that
+        // finished first still gets its last inputs through.
+        fn tick(&mut self, frames: i32) {
+            self.session.poll().unwrap();
+            if self.state.frame >= frames {
+                self.session.send_inputs();
+            } else if self.session.ready(&self.state) {
+                let local = input(self.player, self.sent);
+                self.sent += 1;
+                self.session
+                    .advance(&mut self.system, &mut self.state, local)
+                    .unwrap();
+            }
+        }
+    }
+
+    // What the match looks like with every input known up front.
+    fn offline_hashes(frames: i32, input_delay: usize) -> Vec<u64> {
+        let (mut system, mut state) = battle(2, 0);
+        while state.frame < frames {
+            let frame = state.frame as usize;
+            let inputs = (0..2)
+                .map(|player| match frame.checked_sub(input_delay) {
+                    Some(sent) => input(player, sent),
+                    None => InputFrame::NoInput,
+                })
+                .collect();
+            system.update(&mut state, inputs);
+        }
+        state.chars.iter().map(state_hash).collect()
+    }
+
+    #[test]
+    fn test_rollback_matches_offline() {
+        let (a, b) = pipes(3);
+        let mut p1 = Peer::new(a, 0);
+        let mut p2 = Peer::new(b, 1);
+        while p1.state.frame < 200 || p2.state.frame < 200 {
+            p1.tick(200);
+            p2.tick(200);
+        }
+
+        assert!(p1.session.rollbacks > 0);
+        // only the frames that can still be rolled back or resent are kept
+        let kept = 2 * RollbackSession::<Pipe>::MAX_PREDICTION as usize;
+        for session in [&p1.session, &p2.session] {
+            assert!(session.local_inputs.len() <= kept);
+            assert!(session.remote_inputs.len() <= kept);
+        }
+        let expected = offline_hashes(200, 2);
+        for state in [&p1.state, &p2.state] {
+            let hashes: Vec<u64> = state.chars.iter().map(state_hash).collect();
+            assert_eq!(hashes, expected);
+        }
+    }
+
+    #[test]
+    fn test_udp_localhost_with_loss() {
+        let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (addr_a, addr_b) = (
+            socket_a.local_addr().unwrap(),
+            socket_b.local_addr().unwrap(),
+        );
+        let shim = |transport| {
+            LossyTransport::new(
+                transport,
+                Duration::from_millis(4),
+                Duration::from_millis(4),
+                0.2,
+            )
+        };
+        let mut p1 = Peer::new(shim(UdpTransport::new(socket_a, addr_b).unwrap()), 0);
+        let mut p2 = Peer::new(shim(UdpTransport::new(socket_b, addr_a).unwrap()), 1);
+
+        let mut progress = (Instant::now(), 0);
+        while p1.state.frame < 200 || p2.state.frame < 200 {
+            let frames = p1.state.frame + p2.state.frame;
+            if frames > progress.1 {
+                progress = (Instant::now(), frames);
+            }
+            assert!(
+                progress.0.elapsed() < Duration::from_secs(5),
+                "netplay stalled"
+            );
+            p1.tick(200);
+            p2.tick(200);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // however the packets were lost or delayed, both peers end up where
+        // the match goes with every input known up front
+        let expected = offline_hashes(200, 2);
+        for state in [&p1.state, &p2.state] {
+            let hashes: Vec<u64> = state.chars.iter().map(state_hash).collect();
+            assert_eq!(hashes, expected);
+        }
+    }
+
+    #[test]
+    fn test_prediction_repeats_held_direction() {
+        let (a, _b) = pipes(0);
+        let mut session = RollbackSession::new(a, 0, 2);
+        let forward = Key::Direction(DirectionKind::Single(Direction::F));
+        assert_eq!(session.predict(), InputFrame::NoInput);
+
+        session.confirm_remote(0, vec![InputFrame::Pressed(forward.clone())]);
+        assert_eq!(session.predict(), InputFrame::Held(forward.clone()));
+        session.confirm_remote(1, vec![InputFrame::Held(forward.clone())]);
+        assert_eq!(session.predict(), InputFrame::Held(forward.clone()));
+        session.confirm_remote(2, vec![InputFrame::Released(forward)]);
+        assert_eq!(session.predict(), InputFrame::NoInput);
+    }
+}
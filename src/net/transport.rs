@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+// Unreliable, unordered datagrams to a single peer. The netplay sessions
+// resend everything that hasn't been acknowledged, so a transport is free to
+// drop or reorder packets.
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]);
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, packet: &[u8]) {
+        (**self).send(packet)
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        (**self).recv()
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: SocketAddr,
+}
+
+impl UdpTransport {
+    const MAX_PACKET_SIZE: usize = 4096;
+
+    pub fn bind(local_addr: &str, peer_addr: &str) -> io::Result<UdpTransport> {
+        let peer = peer_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no peer address"))?;
+        UdpTransport::new(UdpSocket::bind(local_addr)?, peer)
+    }
+
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> io::Result<UdpTransport> {
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport { socket, peer })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) {
+        // the peer may not be up yet, it'll get the resend
+        let _ = self.socket.send_to(packet, self.peer);
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut buf = [0; Self::MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.peer => return Some(buf[..len].to_vec()),
+                Ok(_) => continue, // not from our peer
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                // windows reports an earlier send to a closed port here
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+// Wraps a transport to simulate a bad connection: outgoing packets are held
+// back for `latency` plus up to `jitter`, and `loss` (0.0 - 1.0) of them are
+// dropped outright.
+pub struct LossyTransport<T> {
+    inner: T,
+    latency: Duration,
+    jitter: Duration,
+    loss: f32,
+    rng: u64,
+    outgoing: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl<T: Transport> LossyTransport<T> {
+    pub fn new(inner: T, latency: Duration, jitter: Duration, loss: f32) -> Self {
+        Self {
+            inner,
+            latency,
+            jitter,
+            loss,
+            rng: 0x2545f4914f6cdd1d,
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    // xorshift, the shim doesn't need anything better
+    fn next_random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        let mut held = VecDeque::new();
+        while let Some((due, packet)) = self.outgoing.pop_front() {
+            if due <= now {
+                self.inner.send(&packet);
+            } else {
+                held.push_back((due, packet));
+            }
+        }
+        self.outgoing = held;
+    }
+}
+
+impl<T: Transport> Transport for LossyTransport<T> {
+    fn send(&mut self, packet: &[u8]) {
+        if self.next_random() >= self.loss {
+            let jitter = self.jitter.mul_f32(self.next_random());
+            let due = Instant::now() + self.latency + jitter;
+            self.outgoing.push_back((due, packet.to_vec()));
+        }
+        self.flush();
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.flush();
+        self.inner.recv()
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+use regex::Regex;
+
+use crate::game::fx::Trans;
+
+// One [Begin Action] of an AIR file. Past the last element it plays on from
+// loop_start.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Action {
+    pub elements: Vec<Element>,
+    pub loop_start: usize,
+}
+
+// `group, image, x, y, time, flip, trans, xscale, yscale, angle`, and the
+// collision boxes it's shown with. Group -1 is a blank element and a time of
+// -1 holds the element for good.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    pub group: i64,
+    pub image: i64,
+    pub x: i64,
+    pub y: i64,
+    pub time: i64,
+    pub flags: ElementFlags,
+    pub clsn1: Vec<Clsn>, // attack boxes
+    pub clsn2: Vec<Clsn>, // boxes it can be hit in
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ElementFlags {
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub trans: Option<Trans>, // None when the element doesn't blend
+    pub scale: Vec2,
+    pub angle: f32, // degrees counterclockwise
+}
+
+impl Default for ElementFlags {
+    fn default() -> Self {
+        Self {
+            flip_h: false,
+            flip_v: false,
+            trans: None,
+            scale: Vec2::ONE,
+            angle: 0.0,
+        }
+    }
+}
+
+// A collision box relative to the char's position, facing right. The AIR can
+// give the corners either way round, they're sorted so left < right and
+// top < bottom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clsn {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Clsn {
+    fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> Clsn {
+        Clsn {
+            left: x1.min(x2),
+            top: y1.min(y2),
+            right: x1.max(x2),
+            bottom: y1.max(y2),
+        }
+    }
+
+    // Where the box is for a char at `position` facing `facing`.
+    pub fn at(&self, position: Vec2, facing: f32) -> Clsn {
+        Clsn::new(
+            position.x + self.left * facing,
+            position.y + self.top,
+            position.x + self.right * facing,
+            position.y + self.bottom,
+        )
+    }
+
+    pub fn overlaps(&self, other: &Clsn) -> bool {
+        self.left < other.right
+            && other.left < self.right
+            && self.top < other.bottom
+            && other.top < self.bottom
+    }
+}
+
+// The boxes the next element gets. A ClsnNDefault lasts for the rest of the
+// action, a plain ClsnN only for the element after it.
+#[derive(Default)]
+struct Boxes {
+    default: [Vec<Clsn>; 2],
+    next: [Option<Vec<Clsn>>; 2],
+    reading: Option<(usize, bool)>, // (clsn1 or 2, default)
+}
+
+impl Boxes {
+    fn take(&mut self, i: usize) -> Vec<Clsn> {
+        self.next[i].take().unwrap_or_else(|| self.default[i].clone())
+    }
+}
+
+// Reads every action in one pass over the file. Lines it can't make sense of
+// are reported and skipped.
+pub fn parse(air: &str) -> HashMap<u64, Action> {
+    let action_regex = Regex::new(r"(?i)^\[\s*begin\s+action\s+(\d+)\s*\]").unwrap();
+    let clsn_regex =
+        Regex::new(r"(?i)^clsn\s*([12])\s*(default)?\s*(?::|\[\s*\d+\s*\]\s*=)\s*(.*)$").unwrap();
+    let mut actions: HashMap<u64, Action> = HashMap::new();
+    let mut action = None;
+    let mut boxes = Boxes::default();
+    for (line_no, line) in air.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            action = action_regex
+                .captures(line)
+                .and_then(|caps| caps[1].parse::<u64>().ok());
+            if let Some(action) = action {
+                actions.insert(action, Action::default());
+            }
+            boxes = Boxes::default();
+            continue;
+        }
+        let current = match action.and_then(|action| actions.get_mut(&action)) {
+            Some(current) => current,
+            None => continue,
+        };
+        let report = |problem: &str| eprintln!("AIR line {}: {}, {}", line_no + 1, problem, line);
+
+        if line.eq_ignore_ascii_case("loopstart") {
+            current.loop_start = current.elements.len();
+        } else if let Some(caps) = clsn_regex.captures(line) {
+            let i = if &caps[1] == "1" { 0 } else { 1 };
+            let is_default = caps.get(2).is_some();
+            if line.contains('[') {
+                let corners: Vec<f32> = caps[3]
+                    .split(',')
+                    .filter_map(|corner| corner.trim().parse().ok())
+                    .collect();
+                let clsn = match corners[..] {
+                    [x1, y1, x2, y2] => Clsn::new(x1, y1, x2, y2),
+                    _ => {
+                        report("a box needs 4 corners");
+                        continue;
+                    }
+                };
+                // like MUGEN, a box belongs to the header above it even when
+                // it's numbered as the other kind (KFM's 1000 and 1300 are)
+                match boxes.reading {
+                    Some((reading, true)) => boxes.default[reading].push(clsn),
+                    Some((reading, false)) => {
+                        boxes.next[reading].get_or_insert_with(Vec::new).push(clsn)
+                    }
+                    None => report("a box without a Clsn header"),
+                }
+            } else {
+                if is_default {
+                    boxes.default[i].clear();
+                } else {
+                    boxes.next[i] = Some(Vec::new());
+                }
+                boxes.reading = Some((i, is_default));
+            }
+        } else {
+            let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+            let numbers: Vec<i64> = columns
+                .iter()
+                .take(5)
+                .map_while(|column| column.parse().ok())
+                .collect();
+            let (group, image, x, y, time) = match numbers[..] {
+                [group, image, x, y, time] => (group, image, x, y, time),
+                _ => {
+                    report("not an element");
+                    continue;
+                }
+            };
+            let flags = element_flags(&columns[5..], report);
+            current.elements.push(Element {
+                group,
+                image,
+                x,
+                y,
+                time,
+                flags,
+                clsn1: boxes.take(0),
+                clsn2: boxes.take(1),
+            });
+            boxes.reading = None;
+        }
+    }
+    actions
+}
+
+fn element_flags(columns: &[&str], report: impl Fn(&str)) -> ElementFlags {
+    let column = |i: usize| columns.get(i).copied().unwrap_or_default();
+    let float = |i: usize, default: f32| column(i).parse::<f32>().unwrap_or(default);
+    let flip = column(0).to_lowercase();
+    ElementFlags {
+        flip_h: flip.contains('h'),
+        flip_v: flip.contains('v'),
+        trans: match element_trans(column(1)) {
+            Ok(trans) => trans,
+            Err(()) => {
+                report("unknown blending");
+                None
+            }
+        },
+        scale: Vec2::new(float(2, 1.0), float(3, 1.0)),
+        angle: float(4, 0.0),
+    }
+}
+
+// A is additive, A1 adds half, S subtracts and AS###D### is additive with
+// the source and destination alphas.
+fn element_trans(trans: &str) -> Result<Option<Trans>, ()> {
+    let trans = trans.to_lowercase();
+    match trans.as_str() {
+        "" => Ok(None),
+        "a" => Ok(Some(Trans::Add)),
+        "a1" => Ok(Some(Trans::Add1)),
+        "s" => Ok(Some(Trans::Sub)),
+        _ => {
+            let re = Regex::new(r"^as(\d+)d(\d+)$").unwrap();
+            let caps = re.captures(&trans).ok_or(())?;
+            let alpha = |i: usize| caps[i].parse::<i32>().map(|alpha| alpha.min(256));
+            Ok(Some(Trans::AddAlpha(
+                alpha(1).map_err(|_| ())?,
+                alpha(2).map_err(|_| ())?,
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_flags() {
+        let air = "
+            [Begin Action 7] ; a comment
+            Clsn2Default: 1
+             Clsn2[0] = -10,0,10,-20
+            Loopstart
+            1,0, 0,0, 3
+            1,1, 5,-2, 3, V, A1
+            1,2, 0,0, 3, HV, AS64D192, 2, 0.5, 45
+            1,3, 0,0, 3, , S
+            ";
+        let elements = &parse(air)[&7].elements;
+        let flags: Vec<ElementFlags> = elements.iter().map(|element| element.flags).collect();
+        assert_eq!(flags.len(), 4);
+        assert_eq!(flags[0], ElementFlags::default());
+        assert!(flags[1].flip_v && !flags[1].flip_h);
+        assert_eq!(flags[1].trans, Some(Trans::Add1));
+        assert_eq!((elements[1].x, elements[1].y), (5, -2));
+        assert!(flags[2].flip_h && flags[2].flip_v);
+        assert_eq!(flags[2].trans, Some(Trans::AddAlpha(64, 192)));
+        assert_eq!(flags[2].scale, Vec2::new(2.0, 0.5));
+        assert_eq!(flags[2].angle, 45.0);
+        assert_eq!(flags[3].trans, Some(Trans::Sub));
+    }
+
+    #[test]
+    fn test_clsn_boxes() {
+        let air = "
+            [Begin Action 1]
+            Clsn2Default: 1
+             Clsn2[0] = 10,0,-10,-20
+            1,0, 0,0, 3
+            Clsn1: 1
+             Clsn1[0] = 5,-15,30,-10
+            Clsn2: 2
+             Clsn2[0] = -10,0,10,-20
+             Clsn2[1] = 10,-15,30,-10
+            Loopstart
+            1,1, 0,0, 3
+            1,2, 0,0, -1
+
+            [Begin Action 2]
+            2,0, 0,0, 3
+            ";
+        let actions = parse(air);
+        let body = Clsn {
+            left: -10.0,
+            top: -20.0,
+            right: 10.0,
+            bottom: 0.0,
+        };
+        let elements = &actions[&1].elements;
+        assert_eq!(actions[&1].loop_start, 1);
+        assert_eq!(elements[0].clsn1, []);
+        assert_eq!(elements[0].clsn2, [body]);
+        // a plain Clsn only lasts the element after it
+        assert_eq!(elements[1].clsn1.len(), 1);
+        assert_eq!(elements[1].clsn2.len(), 2);
+        assert_eq!(elements[2].clsn1, []);
+        assert_eq!(elements[2].clsn2, [body]);
+        assert_eq!(elements[2].time, -1);
+        // the defaults don't carry over to the next action
+        assert_eq!(actions[&2].elements[0].clsn2, []);
+    }
+
+    #[test]
+    fn test_clsn_overlap() {
+        let punch = Clsn::new(64.0, -320.0, 244.0, -284.0);
+        let body = Clsn::new(-40.0, 0.0, 76.0, -320.0);
+        // facing each other 200 apart
+        let p1 = Vec2::new(-100.0, 0.0);
+        let p2 = Vec2::new(100.0, 0.0);
+        assert!(punch.at(p1, 1.0).overlaps(&body.at(p2, -1.0)));
+        // facing away, the punch goes the other way
+        assert!(!punch.at(p1, -1.0).overlaps(&body.at(p2, -1.0)));
+        // touching isn't overlapping
+        let above = Clsn::new(-40.0, -320.0, 76.0, -400.0);
+        assert!(!body.overlaps(&above));
+    }
+
+    #[test]
+    fn test_kfm() {
+        let air = std::fs::read_to_string("./resources/kfm720.air").unwrap();
+        let actions = parse(&air);
+        assert_eq!(actions.len(), 117);
+        assert!(actions[&5].elements[0].flags.flip_h);
+        assert!(!actions[&5].elements[1].flags.flip_h);
+        let flags = actions[&820].elements[9].flags;
+        assert!(flags.flip_h && flags.flip_v);
+        let flags = actions[&5200].elements[0].flags;
+        assert!(flags.flip_h && flags.flip_v);
+        assert_eq!(actions[&0].elements.len(), 11);
+        assert!(actions[&0].elements.iter().all(|e| e.clsn2.len() == 2));
+
+        // the jab only hits on its third element
+        let jab = &actions[&200].elements;
+        let hits: Vec<usize> = jab.iter().map(|element| element.clsn1.len()).collect();
+        assert_eq!(hits, [0, 0, 1, 0, 0]);
+        assert_eq!(jab[2].clsn2.len(), 3);
+        assert_eq!(jab[3].clsn2.len(), 2);
+        // boxes listed as Clsn2 under a Clsn1 header
+        assert_eq!(actions[&1300].elements[0].clsn1.len(), 2);
+    }
+}
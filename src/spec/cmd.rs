@@ -1,4 +1,5 @@
 use crate::utils::ini::*;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     str::FromStr,
@@ -6,7 +7,7 @@ use std::{
 
 use super::{cns::CNSFile, state::StateDef, triggers::ExpressionContext};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Button {
     a,
     b,
@@ -15,10 +16,12 @@ pub enum Button {
     y,
     z,
     s,
+    d,
+    w,
 }
 
 impl Button {
-    pub const buttons: [Button; 7] = [
+    pub const buttons: [Button; 9] = [
         Button::a,
         Button::b,
         Button::c,
@@ -26,10 +29,12 @@ impl Button {
         Button::y,
         Button::z,
         Button::s,
+        Button::d,
+        Button::w,
     ];
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ButtonKind {
     Simultaneous(Vec<Button>),
     Single(Button),
@@ -77,12 +82,14 @@ impl TryFrom<char> for Button {
             'y' => Ok(Button::y),
             'z' => Ok(Button::z),
             's' => Ok(Button::s),
+            'd' => Ok(Button::d),
+            'w' => Ok(Button::w),
             _ => Err("Invalid Button"),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     B,
     DB,
@@ -95,6 +102,19 @@ pub enum Direction {
 }
 
 impl Direction {
+    // The same direction for a char facing the other way.
+    pub fn mirrored(self) -> Direction {
+        match self {
+            Direction::B => Direction::F,
+            Direction::DB => Direction::DF,
+            Direction::DF => Direction::DB,
+            Direction::F => Direction::B,
+            Direction::UF => Direction::UB,
+            Direction::UB => Direction::UF,
+            dir => dir,
+        }
+    }
+
     fn four_way_match(&self, other: &Direction) -> bool {
         match (self, other) {
             (Direction::B, Direction::DB)
@@ -113,7 +133,7 @@ impl Direction {
         }
     }
 }
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum DirectionKind {
     FourWay(Direction),
     Single(Direction),
@@ -155,7 +175,7 @@ impl FromStr for Direction {
         }
     }
 }
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Key {
     Direction(DirectionKind),
     Button(ButtonKind),
@@ -183,7 +203,8 @@ pub fn get_ordinal_pair(cardinals: (Direction, Direction)) -> Key {
     Key::Direction(DirectionKind::Single(dir))
 }
 
-pub fn get_ordinals(cardinals: HashSet<Direction>) -> Key {
+// None for an empty set.
+pub fn get_ordinals(cardinals: HashSet<Direction>) -> Option<Key> {
     let dir = if cardinals.contains(&Direction::D) && cardinals.contains(&Direction::B) {
         Direction::DB
     } else if cardinals.contains(&Direction::D) && cardinals.contains(&Direction::F) {
@@ -193,9 +214,13 @@ pub fn get_ordinals(cardinals: HashSet<Direction>) -> Key {
     } else if cardinals.contains(&Direction::U) && cardinals.contains(&Direction::B) {
         Direction::UB
     } else {
-        cardinals.into_iter().nth(0usize).unwrap()
+        // Fixed priority so the same set always maps to the same direction.
+        *[Direction::U, Direction::D, Direction::B, Direction::F]
+            .iter()
+            .find(|dir| cardinals.contains(dir))
+            .or_else(|| cardinals.iter().next())?
     };
-    Key::Direction(DirectionKind::Single(dir))
+    Some(Key::Direction(DirectionKind::Single(dir)))
 }
 
 impl FromStr for Key {
@@ -352,7 +377,7 @@ pub fn remove_inbetweens(cmds: Vec<Element>) -> Vec<Element> {
     result
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Element {
     Released(Key, u32),
     Held(Key),
@@ -466,16 +491,27 @@ impl CmdFile {
             .get_section(Self::COMMAND_KEY)
             .ok_or("no commands".to_string())?;
 
-        let commands = match commands_ini {
+        let mut commands: Vec<Command> = match commands_ini {
             SectionContainer::Multiple(commands) => commands
                 .iter()
                 .map(|ini| self.command_from_ini(ini))
                 .collect(),
             SectionContainer::Single(command) => vec![self.command_from_ini(command)],
         };
+        // the get-hit states need it to fall-recover, MUGEN's is x+y
+        if !commands.iter().any(|command| command.name == Self::RECOVERY_COMMAND) {
+            commands.push(Command {
+                name: Self::RECOVERY_COMMAND.to_string(),
+                command: Sequence::from_str("x+y").unwrap(),
+                time: 1,
+                buffer_time: self.defaults.buffer_time,
+            });
+        }
         Ok(commands)
     }
 
+    const RECOVERY_COMMAND: &str = "recovery";
+
     const COMMAND_KEY: &str = "command";
     const NAME_KEY: &str = "name";
     const TIME_KEY: &str = "time";
@@ -499,7 +535,7 @@ impl CmdFile {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CommandList {
     pub commands: Vec<Command>,
     default_time: u32,
@@ -545,7 +581,7 @@ impl CommandList {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Command {
     pub name: String,
     pub command: Sequence,
@@ -553,7 +589,7 @@ pub struct Command {
     pub buffer_time: i32,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Sequence {
     pub elements: Vec<Element>,
 }
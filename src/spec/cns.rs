@@ -8,7 +8,7 @@ use super::controllers::{get_controller, StateArgs, *};
 use super::state::{
     MoveType, Physics, State, StateDef, StateType, Trigger, TriggerHandler, TriggerSet,
 };
-use super::triggers::{Condition, ExpressionContext};
+use super::triggers::{split_components, Condition, Expression, ExpressionContext};
 
 pub struct CNSFile {
     ini: Ini,
@@ -120,39 +120,23 @@ impl CNSFile {
             Physics::default()
         };
 
-        let anim: Option<i32> = ini.get(Self::ANIM_KEY);
-        let velset: Option<(i32, i32)> = ini.get_tuple(Self::VEL_SET_KEY);
-        let ctrl = if let Some(ctrl_val) = ini.get::<i32>(Self::CTRL_KEY) {
-            Some(ctrl_val != 0)
-        } else {
-            None
-        };
-        let power_add: Option<i32> = ini.get(Self::POWER_ADD_KEY);
-        let juggle: Option<i32> = ini.get(Self::JUGGLE_KEY);
-        let face_p2 = if let Some(face_p2_val) = ini.get::<i32>(Self::FACE_P2_KEY) {
-            face_p2_val != 0
-        } else {
-            false
-        };
-        let hit_def_persist =
-            if let Some(hit_def_persist_val) = ini.get::<i32>(Self::HIT_DEF_PERSIST_KEY) {
-                hit_def_persist_val != 0
-            } else {
-                false
-            };
-        let move_hit_persist =
-            if let Some(move_hit_persist_val) = ini.get::<i32>(Self::MOVE_HIT_PERSIST_KEY) {
-                move_hit_persist_val != 0
-            } else {
-                false
-            };
-        let hit_count_persist =
-            if let Some(hit_count_persist_val) = ini.get::<i32>(Self::HIT_COUNT_PERSIST_KEY) {
-                hit_count_persist_val != 0
-            } else {
-                false
-            };
-        let spr_priority = ini.get::<u8>(Self::SPR_PRIORITY_KEY);
+        let expression = |key: &str| ini.get::<String>(key).map(|expn| Expression::new(&expn));
+        let anim = expression(Self::ANIM_KEY);
+        let velset = ini.get::<String>(Self::VEL_SET_KEY).map(|velset| {
+            let components = split_components(&velset);
+            (
+                Expression::new(components[0]),
+                Expression::new(components.get(1).copied().unwrap_or("0")),
+            )
+        });
+        let ctrl = expression(Self::CTRL_KEY);
+        let power_add = expression(Self::POWER_ADD_KEY);
+        let juggle = expression(Self::JUGGLE_KEY);
+        let face_p2 = expression(Self::FACE_P2_KEY);
+        let hit_def_persist = expression(Self::HIT_DEF_PERSIST_KEY);
+        let move_hit_persist = expression(Self::MOVE_HIT_PERSIST_KEY);
+        let hit_count_persist = expression(Self::HIT_COUNT_PERSIST_KEY);
+        let spr_priority = expression(Self::SPR_PRIORITY_KEY);
 
         StateDef {
             state_type,
@@ -175,15 +159,21 @@ impl CNSFile {
     const PERSISTENCY_KEY: &str = "persistent";
     const PERSISTENCY_DEFAULT: i32 = 1;
 
-    const IGNORE_HIT_PAUSE_KEY: &str = "ignorehipause";
+    const IGNORE_HIT_PAUSE_KEY: &str = "ignorehitpause";
     const IGNORE_HIT_PAUSE_DEFAULT: i32 = 0;
     pub fn parse_state(name: &str, ini: &IniSection) -> State {
         let state_label: String = name.split(",").nth(1).unwrap().to_string();
 
         let controller_name = ini.get_string(Self::TYPE_KEY).unwrap().to_lowercase();
-        let controller = get_controller(&controller_name);
         let triggers = Self::parse_triggers(ini);
-        let args = StateArgs::new(&controller_name, ini);
+        // a controller that can't be parsed does nothing
+        let (controller, args) = match StateArgs::new(&controller_name, ini) {
+            Ok(args) => (get_controller(&controller_name), args),
+            Err(e) => {
+                eprintln!("[{}] {}: {}", name, controller_name, e);
+                (get_controller(NULL_SCTRL), StateArgs::Null)
+            }
+        };
         State {
             label: state_label,
             controller,
@@ -258,4 +248,30 @@ mod tests {
         let state_label = "state -1, crouching light kick";
         assert_eq!(CNSFile::get_state_num(state_label), -1);
     }
+
+    #[test]
+    fn test_parse_errors_dont_panic() {
+        let ini = parse_ini(
+            "[Statedef 200]\n\
+             facep2 = 1\n\
+             hitdefpersist = var(1) > 0\n\
+             [State 200, no value]\n\
+             type = ChangeState\n\
+             trigger1 = 1\n\
+             [State 200, anim]\n\
+             type = ChangeAnim\n\
+             trigger1 = 1\n\
+             value = 200\n",
+        );
+        let states = CNSFile::parse_states(&ini);
+        let state_def = &states[&200];
+        assert!(state_def.face_p2.is_some());
+        assert!(state_def.hit_def_persist.is_some());
+        assert!(state_def.move_hit_persist.is_none());
+
+        // the ChangeState without a value does nothing, the rest still loads
+        assert_eq!(state_def.states.len(), 2);
+        assert!(matches!(state_def.states[0].args, StateArgs::Null));
+        assert!(matches!(state_def.states[1].args, StateArgs::ChangeAnim(_)));
+    }
 }
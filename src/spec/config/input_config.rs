@@ -0,0 +1,685 @@
+use crate::spec::cmd::{Button, Direction};
+use crate::utils::ini::*;
+use ggez::input::gamepad::gilrs::{Axis, Button as PadButton};
+use ggez::winit::event::VirtualKeyCode;
+use serde::Deserialize;
+use std::{fs, path::Path, str::FromStr};
+
+// A single physical input that can drive a direction or a button.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputBinding {
+    Key(VirtualKeyCode),
+    PadButton(PadButton),
+    PadAxis(Axis, AxisDirection),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+impl AxisDirection {
+    pub fn active(&self, value: f32, deadzone: f32) -> bool {
+        match self {
+            AxisDirection::Positive => value > deadzone,
+            AxisDirection::Negative => value < -deadzone,
+        }
+    }
+}
+
+// Keyboard names are case insensitive and also accept the SDL key codes
+// that M.U.G.E.N writes into mugen.cfg (273 = Up, 122 = z, ...).
+// Gamepad bindings are written "Pad:South" or "Axis:LeftStickX-".
+impl FromStr for InputBinding {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some((kind, name)) = s.split_once(':') {
+            return match kind.trim().to_lowercase().as_str() {
+                "pad" | "joy" => pad_button_from_name(name)
+                    .map(InputBinding::PadButton)
+                    .ok_or("Invalid gamepad button"),
+                "axis" => pad_axis_from_name(name).ok_or("Invalid gamepad axis"),
+                "key" => key_from_name(name)
+                    .map(InputBinding::Key)
+                    .ok_or("Invalid key"),
+                _ => Err("Invalid binding kind"),
+            };
+        }
+        if let Some(key) = s.parse::<i32>().ok().and_then(key_from_sdl_code) {
+            return Ok(InputBinding::Key(key));
+        }
+        key_from_name(s).map(InputBinding::Key).ok_or("Invalid key")
+    }
+}
+
+// Gamepad sections don't need the "Pad:"/"Axis:" prefix.
+fn pad_binding_from_str(s: &str) -> Result<InputBinding, &'static str> {
+    if s.contains(':') {
+        return InputBinding::from_str(s);
+    }
+    if let Some(button) = pad_button_from_name(s) {
+        return Ok(InputBinding::PadButton(button));
+    }
+    pad_axis_from_name(s).ok_or("Invalid gamepad binding")
+}
+
+fn pad_button_from_name(name: &str) -> Option<PadButton> {
+    match name.trim().to_lowercase().as_str() {
+        "south" => Some(PadButton::South),
+        "east" => Some(PadButton::East),
+        "north" => Some(PadButton::North),
+        "west" => Some(PadButton::West),
+        "c" => Some(PadButton::C),
+        "z" => Some(PadButton::Z),
+        "lefttrigger" => Some(PadButton::LeftTrigger),
+        "lefttrigger2" => Some(PadButton::LeftTrigger2),
+        "righttrigger" => Some(PadButton::RightTrigger),
+        "righttrigger2" => Some(PadButton::RightTrigger2),
+        "select" => Some(PadButton::Select),
+        "start" => Some(PadButton::Start),
+        "mode" => Some(PadButton::Mode),
+        "leftthumb" => Some(PadButton::LeftThumb),
+        "rightthumb" => Some(PadButton::RightThumb),
+        "dpadup" => Some(PadButton::DPadUp),
+        "dpaddown" => Some(PadButton::DPadDown),
+        "dpadleft" => Some(PadButton::DPadLeft),
+        "dpadright" => Some(PadButton::DPadRight),
+        _ => None,
+    }
+}
+
+fn pad_axis_from_name(name: &str) -> Option<InputBinding> {
+    let name = name.trim();
+    let (axis_name, direction) = if let Some(axis_name) = name.strip_suffix('+') {
+        (axis_name, AxisDirection::Positive)
+    } else if let Some(axis_name) = name.strip_suffix('-') {
+        (axis_name, AxisDirection::Negative)
+    } else {
+        return None;
+    };
+    let axis = match axis_name.to_lowercase().as_str() {
+        "leftstickx" => Axis::LeftStickX,
+        "leftsticky" => Axis::LeftStickY,
+        "leftz" => Axis::LeftZ,
+        "rightstickx" => Axis::RightStickX,
+        "rightsticky" => Axis::RightStickY,
+        "rightz" => Axis::RightZ,
+        "dpadx" => Axis::DPadX,
+        "dpady" => Axis::DPadY,
+        _ => return None,
+    };
+    Some(InputBinding::PadAxis(axis, direction))
+}
+
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    let name = name.trim().to_lowercase();
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return key_from_char(c);
+    }
+    match name.as_str() {
+        "up" => Some(VirtualKeyCode::Up),
+        "down" => Some(VirtualKeyCode::Down),
+        "left" => Some(VirtualKeyCode::Left),
+        "right" => Some(VirtualKeyCode::Right),
+        "space" => Some(VirtualKeyCode::Space),
+        "return" | "enter" => Some(VirtualKeyCode::Return),
+        "tab" => Some(VirtualKeyCode::Tab),
+        "escape" | "esc" => Some(VirtualKeyCode::Escape),
+        "backspace" => Some(VirtualKeyCode::Back),
+        "lshift" => Some(VirtualKeyCode::LShift),
+        "rshift" => Some(VirtualKeyCode::RShift),
+        "lctrl" | "lcontrol" => Some(VirtualKeyCode::LControl),
+        "rctrl" | "rcontrol" => Some(VirtualKeyCode::RControl),
+        "lalt" => Some(VirtualKeyCode::LAlt),
+        "ralt" => Some(VirtualKeyCode::RAlt),
+        "kp0" => Some(VirtualKeyCode::Numpad0),
+        "kp1" => Some(VirtualKeyCode::Numpad1),
+        "kp2" => Some(VirtualKeyCode::Numpad2),
+        "kp3" => Some(VirtualKeyCode::Numpad3),
+        "kp4" => Some(VirtualKeyCode::Numpad4),
+        "kp5" => Some(VirtualKeyCode::Numpad5),
+        "kp6" => Some(VirtualKeyCode::Numpad6),
+        "kp7" => Some(VirtualKeyCode::Numpad7),
+        "kp8" => Some(VirtualKeyCode::Numpad8),
+        "kp9" => Some(VirtualKeyCode::Numpad9),
+        _ => None,
+    }
+}
+
+fn key_from_char(c: char) -> Option<VirtualKeyCode> {
+    match c {
+        'a' => Some(VirtualKeyCode::A),
+        'b' => Some(VirtualKeyCode::B),
+        'c' => Some(VirtualKeyCode::C),
+        'd' => Some(VirtualKeyCode::D),
+        'e' => Some(VirtualKeyCode::E),
+        'f' => Some(VirtualKeyCode::F),
+        'g' => Some(VirtualKeyCode::G),
+        'h' => Some(VirtualKeyCode::H),
+        'i' => Some(VirtualKeyCode::I),
+        'j' => Some(VirtualKeyCode::J),
+        'k' => Some(VirtualKeyCode::K),
+        'l' => Some(VirtualKeyCode::L),
+        'm' => Some(VirtualKeyCode::M),
+        'n' => Some(VirtualKeyCode::N),
+        'o' => Some(VirtualKeyCode::O),
+        'p' => Some(VirtualKeyCode::P),
+        'q' => Some(VirtualKeyCode::Q),
+        'r' => Some(VirtualKeyCode::R),
+        's' => Some(VirtualKeyCode::S),
+        't' => Some(VirtualKeyCode::T),
+        'u' => Some(VirtualKeyCode::U),
+        'v' => Some(VirtualKeyCode::V),
+        'w' => Some(VirtualKeyCode::W),
+        'x' => Some(VirtualKeyCode::X),
+        'y' => Some(VirtualKeyCode::Y),
+        'z' => Some(VirtualKeyCode::Z),
+        '0' => Some(VirtualKeyCode::Key0),
+        '1' => Some(VirtualKeyCode::Key1),
+        '2' => Some(VirtualKeyCode::Key2),
+        '3' => Some(VirtualKeyCode::Key3),
+        '4' => Some(VirtualKeyCode::Key4),
+        '5' => Some(VirtualKeyCode::Key5),
+        '6' => Some(VirtualKeyCode::Key6),
+        '7' => Some(VirtualKeyCode::Key7),
+        '8' => Some(VirtualKeyCode::Key8),
+        '9' => Some(VirtualKeyCode::Key9),
+        ',' => Some(VirtualKeyCode::Comma),
+        '.' => Some(VirtualKeyCode::Period),
+        '/' => Some(VirtualKeyCode::Slash),
+        ';' => Some(VirtualKeyCode::Semicolon),
+        _ => None,
+    }
+}
+
+// SDL 1.2 key codes, as written by M.U.G.E.N 1.0
+fn key_from_sdl_code(code: i32) -> Option<VirtualKeyCode> {
+    match code {
+        8 => Some(VirtualKeyCode::Back),
+        9 => Some(VirtualKeyCode::Tab),
+        13 => Some(VirtualKeyCode::Return),
+        27 => Some(VirtualKeyCode::Escape),
+        32..=126 => key_from_char(char::from_u32(code as u32)?),
+        256..=265 => key_from_name(&format!("kp{}", code - 256)),
+        273 => Some(VirtualKeyCode::Up),
+        274 => Some(VirtualKeyCode::Down),
+        275 => Some(VirtualKeyCode::Right),
+        276 => Some(VirtualKeyCode::Left),
+        303 => Some(VirtualKeyCode::RShift),
+        304 => Some(VirtualKeyCode::LShift),
+        305 => Some(VirtualKeyCode::RControl),
+        306 => Some(VirtualKeyCode::LControl),
+        307 => Some(VirtualKeyCode::RAlt),
+        308 => Some(VirtualKeyCode::LAlt),
+        _ => None,
+    }
+}
+
+// Simultaneous opposing cardinal directions (SOCD) resolution.
+// Left+Right or Up+Down held together is turned into a single direction (or
+// none) before it reaches the command buffer, so the result never depends on
+// HashSet iteration order.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SocdPolicy {
+    Neutral,        // opposing directions cancel out
+    #[default]
+    LastInputWins,  // the most recently pressed direction wins
+    FirstInputWins, // the direction that was held first wins
+    UpPriority,     // up beats down, left + right is neutral
+}
+
+impl FromStr for SocdPolicy {
+    type Err = &'static str;
+    // Also takes IKEMEN's SOCDResolution numbers.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "neutral" | "4" => Ok(SocdPolicy::Neutral),
+            "last" | "lastinputwins" | "1" => Ok(SocdPolicy::LastInputWins),
+            "first" | "firstinputwins" | "3" => Ok(SocdPolicy::FirstInputWins),
+            "up" | "uppriority" | "2" => Ok(SocdPolicy::UpPriority),
+            _ => Err("invalid SOCD policy"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerBindings {
+    pub joystick: Option<usize>, // Index of the gamepad driving this player, if any
+    pub deadzone: f32,
+    pub socd: SocdPolicy, // How opposing directions held together are resolved
+    pub directions: Vec<(InputBinding, Direction)>,
+    pub buttons: Vec<(InputBinding, Button)>,
+}
+
+impl PlayerBindings {
+    const DEFAULT_DEADZONE: f32 = 0.3;
+
+    pub fn empty() -> Self {
+        Self {
+            joystick: None,
+            deadzone: Self::DEFAULT_DEADZONE,
+            socd: SocdPolicy::default(),
+            directions: Vec::new(),
+            buttons: Vec::new(),
+        }
+    }
+
+    fn bind(&mut self, action: InputAction, binding: InputBinding) {
+        match action {
+            InputAction::Direction(dir) => self.directions.push((binding, dir)),
+            InputAction::Button(btn) => self.buttons.push((binding, btn)),
+        }
+    }
+
+    fn from_names(names: &[(InputAction, &str)]) -> Result<Self, String> {
+        let mut bindings = Self::empty();
+        for (action, name) in names {
+            let binding = InputBinding::from_str(name)
+                .map_err(|e| format!("{:?} = {}: {}", action, name, e))?;
+            bindings.bind(*action, binding);
+        }
+        Ok(bindings)
+    }
+
+    pub fn default_p1() -> Result<Self, String> {
+        Self::from_names(&[
+            (InputAction::Direction(Direction::U), "Up"),
+            (InputAction::Direction(Direction::D), "Down"),
+            (InputAction::Direction(Direction::B), "Left"),
+            (InputAction::Direction(Direction::F), "Right"),
+            (InputAction::Button(Button::a), "a"),
+            (InputAction::Button(Button::b), "s"),
+            (InputAction::Button(Button::c), "d"),
+            (InputAction::Button(Button::x), "z"),
+            (InputAction::Button(Button::y), "x"),
+            (InputAction::Button(Button::z), "c"),
+            (InputAction::Button(Button::s), "Space"),
+            (InputAction::Button(Button::d), "q"),
+            (InputAction::Button(Button::w), "w"),
+        ])
+    }
+
+    pub fn default_p2() -> Result<Self, String> {
+        Self::from_names(&[
+            (InputAction::Direction(Direction::U), "i"),
+            (InputAction::Direction(Direction::D), "k"),
+            (InputAction::Direction(Direction::B), "j"),
+            (InputAction::Direction(Direction::F), "l"),
+            (InputAction::Button(Button::a), "f"),
+            (InputAction::Button(Button::b), "g"),
+            (InputAction::Button(Button::c), "h"),
+            (InputAction::Button(Button::x), "r"),
+            (InputAction::Button(Button::y), "t"),
+            (InputAction::Button(Button::z), "y"),
+            (InputAction::Button(Button::s), "Return"),
+            (InputAction::Button(Button::d), "v"),
+            (InputAction::Button(Button::w), "b"),
+        ])
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum InputAction {
+    Direction(Direction),
+    Button(Button),
+}
+
+impl FromStr for InputAction {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "jump" | "up" => Ok(InputAction::Direction(Direction::U)),
+            "crouch" | "down" => Ok(InputAction::Direction(Direction::D)),
+            "left" => Ok(InputAction::Direction(Direction::B)),
+            "right" => Ok(InputAction::Direction(Direction::F)),
+            "start" => Ok(InputAction::Button(Button::s)),
+            name => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Button::try_from(c).map(InputAction::Button),
+                    _ => Err("Invalid input action"),
+                }
+            }
+        }
+    }
+}
+
+// Order of the "Buttons" array in IKEMEN's config.json
+const IKEMEN_BUTTON_ORDER: [InputAction; 13] = [
+    InputAction::Direction(Direction::U),
+    InputAction::Direction(Direction::D),
+    InputAction::Direction(Direction::B),
+    InputAction::Direction(Direction::F),
+    InputAction::Button(Button::a),
+    InputAction::Button(Button::b),
+    InputAction::Button(Button::c),
+    InputAction::Button(Button::x),
+    InputAction::Button(Button::y),
+    InputAction::Button(Button::z),
+    InputAction::Button(Button::s),
+    InputAction::Button(Button::d),
+    InputAction::Button(Button::w),
+];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JsonInputConfig {
+    #[serde(default)]
+    key_config: Vec<JsonPlayerConfig>,
+    #[serde(default)]
+    joystick_config: Vec<JsonPlayerConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JsonPlayerConfig {
+    #[serde(default = "JsonPlayerConfig::keyboard")]
+    joystick: i32,
+    #[serde(default)]
+    deadzone: Option<f32>,
+    #[serde(default, rename = "SOCD")]
+    socd: Option<String>,
+    buttons: Vec<String>,
+}
+
+impl JsonPlayerConfig {
+    fn keyboard() -> i32 {
+        -1
+    }
+}
+
+pub struct InputConfig {
+    players: Vec<PlayerBindings>,
+}
+
+impl InputConfig {
+    pub const MAX_PLAYERS: usize = 2;
+
+    // Loads mugen.cfg style ini files, or IKEMEN style json when the
+    // extension says so. Falls back to the built in bindings if the file is
+    // missing, unreadable or invalid.
+    pub fn new(config_file_path: &str) -> InputConfig {
+        if !Path::new(config_file_path).exists() {
+            eprintln!(
+                "input config {} not found, using default bindings",
+                config_file_path
+            );
+            return Self::default();
+        }
+
+        let is_json = config_file_path.to_lowercase().ends_with(".json");
+        let result = fs::read_to_string(config_file_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                if is_json {
+                    Self::from_json(&text)
+                } else {
+                    Ok(Self::from_ini(&parse_ini(&text)))
+                }
+            });
+
+        result.unwrap_or_else(|e| {
+            eprintln!("invalid input config {}: {}", config_file_path, e);
+            Self::default()
+        })
+    }
+
+    pub fn player(&self, player_no: usize) -> PlayerBindings {
+        self.players
+            .get(player_no)
+            .cloned()
+            .unwrap_or_else(PlayerBindings::empty)
+    }
+
+    const DEADZONE_KEY: &str = "deadzone";
+    const JOYSTICK_KEY: &str = "joystick";
+    const SOCD_KEY: &str = "socd";
+
+    pub fn from_ini(ini: &Ini) -> InputConfig {
+        let mut players = Vec::new();
+        for player_no in 1..=Self::MAX_PLAYERS {
+            let keys_section = format!("p{} keys", player_no);
+            let joystick_section = format!("p{} joystick", player_no);
+            if ini.get_section(&keys_section).is_none()
+                && ini.get_section(&joystick_section).is_none()
+            {
+                players.push(Self::default_player(player_no - 1));
+                continue;
+            }
+
+            let mut bindings = PlayerBindings::empty();
+            if let Some(SectionContainer::Single(section)) = ini.get_section(&keys_section) {
+                if let Some(socd) = section.get_string(Self::SOCD_KEY) {
+                    bindings.socd = Self::parse_socd(&socd);
+                }
+                Self::bind_section(&mut bindings, section, InputBinding::from_str);
+            }
+            if let Some(SectionContainer::Single(section)) = ini.get_section(&joystick_section) {
+                bindings.joystick = section.get::<usize>(Self::JOYSTICK_KEY).or(Some(0));
+                if let Some(deadzone) = section.get::<f32>(Self::DEADZONE_KEY) {
+                    bindings.deadzone = deadzone;
+                }
+                Self::bind_section(&mut bindings, section, pad_binding_from_str);
+            }
+            players.push(bindings);
+        }
+        InputConfig { players }
+    }
+
+    fn bind_section(
+        bindings: &mut PlayerBindings,
+        section: &IniSection,
+        parse: fn(&str) -> Result<InputBinding, &'static str>,
+    ) {
+        for (action_name, action) in Self::ini_actions() {
+            if let Some(value) = section.get_string(action_name) {
+                Self::bind_list(bindings, action, &value, parse);
+            }
+        }
+    }
+
+    fn ini_actions() -> [(&'static str, InputAction); 13] {
+        [
+            ("jump", InputAction::Direction(Direction::U)),
+            ("crouch", InputAction::Direction(Direction::D)),
+            ("left", InputAction::Direction(Direction::B)),
+            ("right", InputAction::Direction(Direction::F)),
+            ("a", InputAction::Button(Button::a)),
+            ("b", InputAction::Button(Button::b)),
+            ("c", InputAction::Button(Button::c)),
+            ("x", InputAction::Button(Button::x)),
+            ("y", InputAction::Button(Button::y)),
+            ("z", InputAction::Button(Button::z)),
+            ("start", InputAction::Button(Button::s)),
+            ("d", InputAction::Button(Button::d)),
+            ("w", InputAction::Button(Button::w)),
+        ]
+    }
+
+    pub fn from_json(json: &str) -> Result<InputConfig, String> {
+        let config: JsonInputConfig = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut players: Vec<PlayerBindings> = (0..Self::MAX_PLAYERS)
+            .map(|_| PlayerBindings::empty())
+            .collect();
+
+        for (player_no, player) in config.key_config.iter().enumerate().take(Self::MAX_PLAYERS) {
+            if let Some(socd) = &player.socd {
+                players[player_no].socd = Self::parse_socd(socd);
+            }
+            Self::bind_json(&mut players[player_no], player, InputBinding::from_str);
+        }
+        for (player_no, player) in config
+            .joystick_config
+            .iter()
+            .enumerate()
+            .take(Self::MAX_PLAYERS)
+        {
+            if player.joystick >= 0 {
+                players[player_no].joystick = Some(player.joystick as usize);
+            }
+            if let Some(deadzone) = player.deadzone {
+                players[player_no].deadzone = deadzone;
+            }
+            Self::bind_json(&mut players[player_no], player, pad_binding_from_str);
+        }
+        Ok(InputConfig { players })
+    }
+
+    fn bind_json(
+        bindings: &mut PlayerBindings,
+        player: &JsonPlayerConfig,
+        parse: fn(&str) -> Result<InputBinding, &'static str>,
+    ) {
+        for (action, value) in IKEMEN_BUTTON_ORDER.iter().zip(player.buttons.iter()) {
+            Self::bind_list(bindings, *action, value, parse);
+        }
+    }
+
+    fn parse_socd(value: &str) -> SocdPolicy {
+        SocdPolicy::from_str(value).unwrap_or_else(|e| {
+            eprintln!("SOCD = {}: {}", value, e);
+            SocdPolicy::default()
+        })
+    }
+
+    // A value may hold several bindings, e.g. "DPadUp, LeftStickY+"
+    fn bind_list(
+        bindings: &mut PlayerBindings,
+        action: InputAction,
+        value: &str,
+        parse: fn(&str) -> Result<InputBinding, &'static str>,
+    ) {
+        for name in value.split(',') {
+            // IKEMEN writes "Not used" for unbound buttons
+            if name.trim().is_empty() || name.trim().eq_ignore_ascii_case("not used") {
+                continue;
+            }
+            match parse(name) {
+                Ok(binding) => bindings.bind(action, binding),
+                Err(e) => eprintln!("{:?} = {}: {}", action, name, e),
+            }
+        }
+    }
+
+    fn default_player(player_idx: usize) -> PlayerBindings {
+        let bindings = match player_idx {
+            0 => PlayerBindings::default_p1(),
+            1 => PlayerBindings::default_p2(),
+            _ => Ok(PlayerBindings::empty()),
+        };
+        bindings.unwrap_or_else(|e| {
+            eprintln!("default bindings for p{}: {}", player_idx + 1, e);
+            PlayerBindings::empty()
+        })
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            players: (0..Self::MAX_PLAYERS).map(Self::default_player).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdl_key_codes() {
+        assert_eq!(
+            InputBinding::from_str("273"),
+            Ok(InputBinding::Key(VirtualKeyCode::Up))
+        );
+        assert_eq!(
+            InputBinding::from_str("122"),
+            Ok(InputBinding::Key(VirtualKeyCode::Z))
+        );
+    }
+
+    #[test]
+    fn test_pad_bindings() {
+        assert_eq!(
+            pad_binding_from_str("South"),
+            Ok(InputBinding::PadButton(PadButton::South))
+        );
+        assert_eq!(
+            pad_binding_from_str("LeftStickX-"),
+            Ok(InputBinding::PadAxis(
+                Axis::LeftStickX,
+                AxisDirection::Negative
+            ))
+        );
+    }
+
+    #[test]
+    fn test_default_bindings() {
+        assert!(PlayerBindings::default_p1().is_ok());
+        assert!(PlayerBindings::default_p2().is_ok());
+        assert!(
+            PlayerBindings::from_names(&[(InputAction::Button(Button::a), "NoSuchKey")]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_unreadable_config_uses_defaults() {
+        // a directory exists but can't be read as a file
+        let config = InputConfig::new("./resources");
+        let default_p1 = PlayerBindings::default_p1().unwrap();
+        assert_eq!(config.player(0).buttons, default_p1.buttons);
+        assert_eq!(config.player(0).directions, default_p1.directions);
+    }
+
+    #[test]
+    fn test_mugen_cfg() {
+        let cfg = "[P1 Keys]\nJump = 273\nA = a\nStart = Return\n\n[P2 Joystick]\nJoystick = 1\nDeadzone = 0.5\nJump = DPadUp\nD = Axis:RightZ+\n";
+        let config = InputConfig::from_ini(&parse_ini(cfg));
+
+        let p1 = config.player(0);
+        assert_eq!(p1.joystick, None);
+        assert!(p1
+            .directions
+            .contains(&(InputBinding::Key(VirtualKeyCode::Up), Direction::U)));
+        assert!(p1
+            .buttons
+            .contains(&(InputBinding::Key(VirtualKeyCode::Return), Button::s)));
+
+        let p2 = config.player(1);
+        assert_eq!(p2.joystick, Some(1));
+        assert_eq!(p2.deadzone, 0.5);
+        assert!(p2.buttons.contains(&(
+            InputBinding::PadAxis(Axis::RightZ, AxisDirection::Positive),
+            Button::d
+        )));
+    }
+
+    #[test]
+    fn test_ikemen_json() {
+        let json = r#"{
+            "KeyConfig": [{"Joystick": -1, "Buttons": ["UP", "DOWN", "LEFT", "RIGHT", "z", "x", "c", "a", "s", "d", "RETURN", "q", "w"]}],
+            "JoystickConfig": [{"Joystick": 0, "Buttons": ["DPadUp", "DPadDown", "DPadLeft", "DPadRight", "South", "East", "Not used", "West", "North", "Not used", "Start", "LeftTrigger", "RightTrigger"]}]
+        }"#;
+        let config = InputConfig::from_json(json).unwrap();
+
+        let p1 = config.player(0);
+        assert_eq!(p1.joystick, Some(0));
+        assert!(p1
+            .buttons
+            .contains(&(InputBinding::Key(VirtualKeyCode::Q), Button::d)));
+        assert!(p1
+            .buttons
+            .contains(&(InputBinding::PadButton(PadButton::Start), Button::s)));
+        // "Not used" leaves the keyboard binding as the only one
+        assert_eq!(
+            p1.buttons
+                .iter()
+                .filter(|(_, btn)| *btn == Button::z)
+                .count(),
+            1
+        );
+    }
+}
@@ -44,6 +44,14 @@ impl CharConstants {
             None
         }
     }
+
+    // For the engine's own lookups, which don't care how the value was written.
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.get(name)? {
+            ConstantValue::Float(f) => Some(f),
+            ConstantValue::Int(i) => Some(i as f32),
+        }
+    }
 }
 
 pub struct ConstantIterator<'a> {
@@ -306,7 +314,7 @@ fn parse_int_constant(
     constants: &mut CharConstants,
 ) {
     let item_val = ini.get_int(section, item).unwrap_or(default);
-    constants.insert_int(&format!("{:?}.{:?}", section, item), item_val);
+    constants.insert_int(&format!("{}.{}", section, item), item_val);
 }
 
 fn parse_float_constant(
@@ -317,7 +325,7 @@ fn parse_float_constant(
     constants: &mut CharConstants,
 ) {
     let item_val = ini.get_float(section, item).unwrap_or(default as f64);
-    constants.insert_float(&format!("{:?}.{:?}", section, item), item_val as f32);
+    constants.insert_float(&format!("{}.{}", section, item), item_val as f32);
 }
 
 fn parse_constant(
@@ -378,11 +386,11 @@ fn parse_float_tuple_constant(
 ) {
     let vals = ini.get_float_tuple(section, item).unwrap_or(default);
     constants.insert_float(
-        &format!("{:?}.{:?}.{:?}", section, item, suffix.0),
+        &format!("{}.{}.{}", section, item, suffix.0),
         vals.0 as f32,
     );
     constants.insert_float(
-        &format!("{:?}.{:?}.{:?}", section, item, suffix.1),
+        &format!("{}.{}.{}", section, item, suffix.1),
         vals.1 as f32,
     );
 }
@@ -396,8 +404,8 @@ fn parse_int_tuple_constant(
     constants: &mut CharConstants,
 ) {
     let vals = ini.get_int_tuple(section, item).unwrap_or(default);
-    constants.insert_int(&format!("{:?}.{:?}.{:?}", section, item, suffix.0), vals.0);
-    constants.insert_int(&format!("{:?}.{:?}.{:?}", section, item, suffix.1), vals.1);
+    constants.insert_int(&format!("{}.{}.{}", section, item, suffix.0), vals.0);
+    constants.insert_int(&format!("{}.{}.{}", section, item, suffix.1), vals.1);
 }
 
 fn parse_constant_with_suffix(
@@ -407,12 +415,19 @@ fn parse_constant_with_suffix(
     item: (&str, ConstantValue),
     constants: &mut CharConstants,
 ) {
-    parse_constant(
-        ini,
-        section,
-        (format!("{:?}.{:?}", item.0, suffix).as_str(), item.1),
-        constants,
-    )
+    // looked up as `item` but stored as e.g. velocity.walk.fwd.x, to match
+    // the constants that come in pairs
+    let name = format!("{}.{}.{}", section, item.0, suffix);
+    match item.1 {
+        ConstantValue::Float(default) => {
+            let val = ini.get_float(section, item.0).unwrap_or(default as f64);
+            constants.insert_float(&name, val as f32);
+        }
+        ConstantValue::Int(default) => {
+            let val = ini.get_int(section, item.0).unwrap_or(default);
+            constants.insert_int(&name, val);
+        }
+    }
 }
 
 fn parse_xy_constant(
@@ -449,8 +464,8 @@ fn parse_velocity_neu_constants(ini: &Ini, constants: &mut CharConstants) {
             .get_float_tuple(VELOCITY_KEY, constant.0)
             .unwrap_or(default);
         let replaced = constant.0.replace(".neu", "");
-        constants.insert_float(&format!("{:?}.{:?}.x", VELOCITY_KEY, constant.0), vals.0);
-        constants.insert_float(&format!("{:?}.{:?}.y", VELOCITY_KEY, replaced), vals.1);
+        constants.insert_float(&format!("{}.{}.x", VELOCITY_KEY, constant.0), vals.0);
+        constants.insert_float(&format!("{}.{}.y", VELOCITY_KEY, replaced), vals.1);
     }
 }
 
@@ -484,3 +499,27 @@ pub fn parse_char_constants(ini: &Ini) -> CharConstants {
     parse_movement_constants(ini, &mut constants);
     constants
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float(constants: &CharConstants, name: &str) -> f32 {
+        match constants.get(name) {
+            Some(ConstantValue::Float(f)) => f,
+            _ => panic!("no float constant {}", name),
+        }
+    }
+
+    #[test]
+    fn test_velocity_constants() {
+        let ini = parse_ini("[Velocity]\nwalk.fwd = 4.5\njump.neu = 0,-20\n");
+        let constants = parse_char_constants(&ini);
+
+        assert_eq!(float(&constants, "velocity.walk.fwd.x"), 4.5);
+        assert_eq!(float(&constants, "velocity.walk.back.x"), -8.8);
+        assert_eq!(float(&constants, "velocity.jump.neu.x"), 0.0);
+        assert_eq!(float(&constants, "velocity.jump.y"), -20.0);
+        assert_eq!(float(&constants, "movement.yaccel"), 1.76);
+    }
+}
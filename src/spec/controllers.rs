@@ -1,18 +1,29 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use crate::{
-    game::char::CharState,
+    game::{
+        char::{CharState, NUM_FVARS, NUM_SYS_VARS, NUM_VARS},
+        env_shake::EnvShake,
+        fx::{AfterImage, EnvColor, PalFx, ScreenEffect, Trans},
+        get_hit::Fall,
+        hit::{HitBy, HitDef, HitOverride, ReversalDef, HIT_OVERRIDE_SLOTS},
+        pause::Pause,
+        target::{Bind, TargetEffect},
+    },
     utils::ini::{Ini, IniSection},
 };
+use glam::Vec2;
 
-use super::triggers::{Expression, ExpressionContext};
+use super::hit_attr::{AnimType, AttackAttr, AttackKind, AttrSet, HitFlags, HitType};
+use super::triggers::{split_components, Expression, TriggerContext};
 use evalexpr::{Node, ValueType};
 
 const INVALID_TYPE_FOR_ARGS_ERR: &'static str = "invalid type for state args";
 
 pub type StateController = Box<StateControllerFunc>;
 
-pub type StateControllerFunc = dyn Fn(&mut CharState, StateArgs, &ExpressionContext);
+pub type StateControllerFunc = dyn Fn(&mut CharState, StateArgs, &TriggerContext);
 
 pub fn get_controller(state_name: &str) -> StateController {
     match state_name {
@@ -26,6 +37,39 @@ pub fn get_controller(state_name: &str) -> StateController {
         n if n == CTRL_SET_SCTRL => Box::new(ctrl_set),
         n if n == CHANGE_ANIM_SCTRL => Box::new(change_anim),
         n if n == VAR_SET_SCTRL => Box::new(var_set),
+        n if n == VAR_RANDOM_SCTRL => Box::new(var_random),
+        n if n == VAR_ADD_SCTRL => Box::new(var_add),
+        n if n == VAR_RANGE_SET_SCTRL => Box::new(var_range_set),
+        n if n == PARENT_VAR_SET_SCTRL || n == PARENT_VAR_ADD_SCTRL => Box::new(null),
+        n if n == GRAVITY_SCTRL => Box::new(gravity),
+        n if n == POS_FREEZE_SCTRL => Box::new(pos_freeze),
+        n if n == WIDTH_SCTRL => Box::new(width),
+        n if n == PLAYER_PUSH_SCTRL => Box::new(player_push),
+        n if n == SELF_STATE_SCTRL => Box::new(self_state),
+        n if n == CHANGE_ANIM_2_SCTRL => Box::new(change_anim2),
+        n if TARGET_SCTRLS.contains(&n) => Box::new(target),
+        n if n == BIND_TO_TARGET_SCTRL => Box::new(bind_to_target),
+        n if n == PAUSE_SCTRL || n == SUPER_PAUSE_SCTRL => Box::new(pause),
+        n if n == HIT_FALL_DAMAGE_SCTRL => Box::new(hit_fall_damage),
+        n if n == HIT_FALL_VEL_SCTRL => Box::new(hit_fall_vel),
+        n if n == HIT_FALL_SET_SCTRL => Box::new(hit_fall_set),
+        n if n == FALL_ENV_SHAKE_SCTRL => Box::new(fall_env_shake),
+        n if n == HIT_BY_SCTRL || n == NOT_HIT_BY_SCTRL => Box::new(hit_by),
+        n if n == HIT_OVERRIDE_SCTRL => Box::new(hit_override),
+        n if n == REVERSAL_DEF_SCTRL => Box::new(reversal_def),
+        n if n == HIT_DEF_SCTRL => Box::new(hit_def),
+        n if n == PAL_FX_SCTRL => Box::new(pal_fx),
+        n if n == BG_PAL_FX_SCTRL => Box::new(bg_pal_fx),
+        n if n == ALL_PAL_FX_SCTRL => Box::new(all_pal_fx),
+        n if n == AFTER_IMAGE_SCTRL => Box::new(after_image),
+        n if n == AFTER_IMAGE_TIME_SCTRL => Box::new(after_image_time),
+        n if n == ENV_SHAKE_SCTRL => Box::new(env_shake),
+        n if n == ENV_COLOR_SCTRL => Box::new(env_color),
+        n if n == TRANS_SCTRL => Box::new(trans),
+        n if n == ANGLE_DRAW_SCTRL => Box::new(angle_draw),
+        n if n == ANGLE_SET_SCTRL => Box::new(angle_set),
+        n if n == ANGLE_ADD_SCTRL => Box::new(angle_add),
+        n if n == ANGLE_MUL_SCTRL => Box::new(angle_mul),
         _ => {
             eprintln!("unknown sctrl");
             Box::new(null)
@@ -39,9 +83,33 @@ pub enum StateArgs {
     ChangeState(ChangeStateArgs),
     VelArgs(VelArgs),
     PosArgs(PosArgs),
-    CtrlSet(i32),
+    CtrlSet(Expression),
     ChangeAnim(ChangeAnimArgs),
     VarSet(VarSetArgs),
+    VarRandom(VarRandomArgs),
+    VarAdd(VarSetArgs),
+    VarRangeSet(VarRangeSetArgs),
+    PosFreeze(Expression),
+    Width(WidthArgs),
+    PlayerPush(Expression),
+    SelfState(ChangeStateArgs),
+    ChangeAnim2(ChangeAnimArgs),
+    Target(Expression, TargetArgs), // target id, -1 for all
+    BindToTarget(BindToTargetArgs),
+    Pause(PauseArgs),
+    HitFallSet(HitFallSetArgs),
+    HitBy(HitByArgs),
+    HitOverride(HitOverrideArgs),
+    ReversalDef(ReversalDefArgs),
+    HitDef(HitDefArgs),
+    PalFx(PalFxArgs),
+    AfterImage(AfterImageArgs),
+    AfterImageTime(Expression),
+    EnvShake(EnvShakeArgs),
+    EnvColor(EnvColorArgs),
+    Trans(Trans, Option<(Expression, Expression)>), // alpha, for addalpha
+    AngleDraw(Option<Expression>, Option<(Expression, Expression)>), // angle, scale
+    Angle(Expression),
 }
 
 impl StateArgs {
@@ -52,21 +120,74 @@ impl StateArgs {
     const X_ARG: &str = "x";
     const Y_ARG: &str = "y";
 
-    pub fn new(name: &str, ini: &IniSection) -> Self {
-        match name {
+    // Errors when a key the controller can't do without is missing.
+    pub fn new(name: &str, ini: &IniSection) -> Result<Self, String> {
+        let args = match name {
             n if n == NULL_SCTRL => StateArgs::Null,
-            n if n == CHANGE_STATE_SCTRL => Self::change_state_args(ini),
+            n if n == CHANGE_STATE_SCTRL => Self::change_state_args(ini)?,
             n if n == VEL_SET_SCTRL || n == VEL_MUL_SCTRL || n == VEL_ADD_SCTRL => {
                 Self::vel_args(ini)
             }
             n if n == POS_SET_SCTRL || n == POS_ADD_SCTRL => Self::pos_args(ini),
-            n if n == CTRL_SET_SCTRL => Self::ctrl_set_args(ini),
-            n if n == CHANGE_ANIM_SCTRL => Self::change_anim_args(ini),
+            n if n == CTRL_SET_SCTRL => Self::ctrl_set_args(ini)?,
+            n if n == CHANGE_ANIM_SCTRL => Self::change_anim_args(ini)?,
             n if n == VAR_SET_SCTRL => Self::var_set_args(ini),
+            n if n == VAR_RANDOM_SCTRL => Self::var_random_args(ini),
+            n if n == VAR_ADD_SCTRL => Self::var_add_args(ini),
+            n if n == VAR_RANGE_SET_SCTRL => Self::var_range_set_args(ini),
+            n if n == PARENT_VAR_SET_SCTRL || n == PARENT_VAR_ADD_SCTRL => {
+                Self::parent_var_args(n, ini)
+            }
+            n if n == GRAVITY_SCTRL => StateArgs::Null,
+            n if n == POS_FREEZE_SCTRL => Self::pos_freeze_args(ini),
+            n if n == WIDTH_SCTRL => Self::width_args(ini),
+            n if n == PLAYER_PUSH_SCTRL => Self::player_push_args(ini),
+            n if n == SELF_STATE_SCTRL => match Self::change_state_args(ini)? {
+                Self::ChangeState(args) => Self::SelfState(args),
+                args => args,
+            },
+            n if n == CHANGE_ANIM_2_SCTRL => match Self::change_anim_args(ini)? {
+                Self::ChangeAnim(args) => Self::ChangeAnim2(args),
+                args => args,
+            },
+            n if TARGET_SCTRLS.contains(&n) => Self::target_args(n, ini),
+            n if n == BIND_TO_TARGET_SCTRL => Self::bind_to_target_args(ini),
+            n if n == PAUSE_SCTRL || n == SUPER_PAUSE_SCTRL => Self::pause_args(n, ini),
+            n if n == HIT_FALL_DAMAGE_SCTRL
+                || n == HIT_FALL_VEL_SCTRL
+                || n == FALL_ENV_SHAKE_SCTRL =>
+            {
+                StateArgs::Null
+            }
+            n if n == HIT_FALL_SET_SCTRL => Self::hit_fall_set_args(ini),
+            n if n == HIT_BY_SCTRL || n == NOT_HIT_BY_SCTRL => Self::hit_by_args(n, ini),
+            n if n == HIT_OVERRIDE_SCTRL => Self::hit_override_args(ini),
+            n if n == REVERSAL_DEF_SCTRL => Self::reversal_def_args(ini),
+            n if n == HIT_DEF_SCTRL => Self::hit_def_args(ini),
+            n if n == PAL_FX_SCTRL || n == BG_PAL_FX_SCTRL || n == ALL_PAL_FX_SCTRL => {
+                Self::pal_fx_args(ini)
+            }
+            n if n == AFTER_IMAGE_SCTRL => Self::after_image_args(ini),
+            n if n == AFTER_IMAGE_TIME_SCTRL => Self::after_image_time_args(ini),
+            n if n == ENV_SHAKE_SCTRL => Self::env_shake_args(ini),
+            n if n == ENV_COLOR_SCTRL => Self::env_color_args(ini),
+            n if n == TRANS_SCTRL => Self::trans_args(ini),
+            n if n == ANGLE_DRAW_SCTRL => Self::angle_draw_args(ini),
+            n if n == ANGLE_SET_SCTRL || n == ANGLE_ADD_SCTRL || n == ANGLE_MUL_SCTRL => {
+                Self::Angle(Self::required(ini, Self::VALUE_ARG)?)
+            }
             _ => {
                 eprintln!("unknown sctrl");
                 StateArgs::Null
             }
+        };
+        Ok(args)
+    }
+
+    fn required(ini: &IniSection, key: &str) -> Result<Expression, String> {
+        match ini.get::<String>(key) {
+            Some(value) => Ok(Expression::new(&value)),
+            None => Err(format!("missing {}", key)),
         }
     }
 
@@ -96,38 +217,27 @@ impl StateArgs {
         Self::PosArgs(PosArgs { x, y })
     }
 
-    fn change_state_args(ini: &IniSection) -> Self {
-        let value = ini.get::<i32>(Self::VALUE_ARG).unwrap();
-        let mut ctrl: Option<i32> = None;
-        let mut anim: Option<i32> = None;
-
-        if let Some(ctrl_flag) = ini.get::<i32>(Self::CTRL_ARG) {
-            ctrl = Some(ctrl_flag);
-        }
-
-        if let Some(anim_no) = ini.get::<i32>(Self::ANIM_ARG) {
-            anim = Some(anim_no);
-        }
+    fn change_state_args(ini: &IniSection) -> Result<Self, String> {
+        let expression = |key: &str| ini.get::<String>(key).map(|expn| Expression::new(&expn));
 
-        Self::ChangeState(ChangeStateArgs {
-            state_no: value,
-            ctrl_flag: ctrl,
-            anim_no: anim,
-        })
+        Ok(Self::ChangeState(ChangeStateArgs {
+            state_no: Self::required(ini, Self::VALUE_ARG)?,
+            ctrl_flag: expression(Self::CTRL_ARG),
+            anim_no: expression(Self::ANIM_ARG),
+        }))
     }
 
-    fn ctrl_set_args(ini: &IniSection) -> Self {
-        let value = ini.get::<i32>(Self::VALUE_ARG).unwrap();
-        Self::CtrlSet(value)
+    fn ctrl_set_args(ini: &IniSection) -> Result<Self, String> {
+        Ok(Self::CtrlSet(Self::required(ini, Self::VALUE_ARG)?))
     }
 
-    fn change_anim_args(ini: &IniSection) -> Self {
-        let anim_no = Expression::new(ini.get::<String>(Self::VALUE_ARG).unwrap().as_str());
+    fn change_anim_args(ini: &IniSection) -> Result<Self, String> {
+        let anim_no = Self::required(ini, Self::VALUE_ARG)?;
         let mut elem: Option<Expression> = None;
         if let Some(elem_exp) = ini.get::<String>(Self::ELEM_ARG) {
             elem = Some(Expression::new(elem_exp.as_str()));
         }
-        Self::ChangeAnim(ChangeAnimArgs { anim_no, elem })
+        Ok(Self::ChangeAnim(ChangeAnimArgs { anim_no, elem }))
     }
 
     const V_ARG: &str = "v";
@@ -135,49 +245,721 @@ impl StateArgs {
     const FV_ARG: &str = "fv";
     const FVAR_ARG: &str = "fvar";
     const SYSVAR_ARG: &str = "sysvar";
-    fn var_set_args(ini: &IniSection) -> Self {
-        if let Some(v) = ini.get::<i32>(Self::V_ARG) {
-            let value_expn: String = ini.get(Self::VALUE_ARG).unwrap();
-            return Self::VarSet(VarSetArgs::Int(v as usize, Expression::new(&value_expn)));
+    const SYSFVAR_ARG: &str = "sysfvar";
+    // Shared by VarSet, VarAdd and the ParentVar ones: either v/fv = index
+    // with value = expression, or var(n)/fvar(n)/sysvar(n)/sysfvar(n) =
+    // expression.
+    fn var_args(name: &str, ini: &IniSection) -> Option<VarSetArgs> {
+        let args = if let Some(v) = ini.get::<i32>(Self::V_ARG) {
+            let value_expn: String = ini.get(Self::VALUE_ARG)?;
+            VarSetArgs::Int(v as usize, Expression::new(&value_expn))
         } else if let Some(fv) = ini.get::<i32>(Self::FV_ARG) {
-            let value_expn: String = ini.get(Self::VALUE_ARG).unwrap();
-            return Self::VarSet(VarSetArgs::Float(fv as usize, Expression::new(&value_expn)));
+            let value_expn: String = ini.get(Self::VALUE_ARG)?;
+            VarSetArgs::Float(fv as usize, Expression::new(&value_expn))
         } else {
-            for i in 0 as usize..60 as usize {
-                let var_label = format!("{}({})", Self::VAR_ARG, i);
-                let fvar_label = format!("{}({})", Self::FVAR_ARG, i);
-                let sysvar_label = format!("{}({})", Self::SYSVAR_ARG, i);
-                if let Some(int_expn) = ini.get::<String>(&var_label) {
-                    return Self::VarSet(VarSetArgs::Int(i, Expression::new(&int_expn)));
-                } else if let Some(float_expn) = ini.get::<String>(&fvar_label) {
-                    return Self::VarSet(VarSetArgs::Float(i, Expression::new(&float_expn)));
-                } else if let Some(sysvar_expn) = ini.get::<String>(&sysvar_label) {
-                    return Self::VarSet(VarSetArgs::System(i, Expression::new(&sysvar_expn)));
+            let labelled = [
+                (Self::VAR_ARG, NUM_VARS),
+                (Self::FVAR_ARG, NUM_FVARS),
+                (Self::SYSVAR_ARG, NUM_SYS_VARS),
+                (Self::SYSFVAR_ARG, NUM_SYS_VARS),
+            ];
+            let found = labelled.iter().find_map(|&(label, count)| {
+                (0..count).find_map(|i| {
+                    let expn = ini.get::<String>(&format!("{}({})", label, i))?;
+                    Some((label, i, Expression::new(&expn)))
+                })
+            });
+            match found {
+                Some((Self::VAR_ARG, i, expn)) => VarSetArgs::Int(i, expn),
+                Some((Self::FVAR_ARG, i, expn)) => VarSetArgs::Float(i, expn),
+                Some((Self::SYSVAR_ARG, i, expn)) => VarSetArgs::System(i, expn),
+                Some((_, i, expn)) => VarSetArgs::SystemFloat(i, expn),
+                None => {
+                    eprintln!("{} has no variable to set, or it's out of range", name);
+                    return None;
                 }
             }
-            dbg!("Invalid VarSet paramater.");
-            Self::Null
+        };
+        match args {
+            VarSetArgs::Int(i, _) if i >= NUM_VARS => {
+                eprintln!("{}: var({}) is out of range, there are {}", name, i, NUM_VARS)
+            }
+            VarSetArgs::Float(i, _) if i >= NUM_FVARS => {
+                eprintln!("{}: fvar({}) is out of range, there are {}", name, i, NUM_FVARS)
+            }
+            _ => return Some(args),
+        }
+        None
+    }
+
+    fn var_set_args(ini: &IniSection) -> Self {
+        Self::var_args(VAR_SET_SCTRL, ini).map_or(Self::Null, Self::VarSet)
+    }
+
+    fn var_add_args(ini: &IniSection) -> Self {
+        Self::var_args(VAR_ADD_SCTRL, ini).map_or(Self::Null, Self::VarAdd)
+    }
+
+    // Only helpers have a parent, and there are no helpers yet.
+    fn parent_var_args(name: &str, ini: &IniSection) -> Self {
+        if Self::var_args(name, ini).is_some() {
+            eprintln!("{} is only for helpers, it's ignored", name);
+        }
+        Self::Null
+    }
+
+    const FVALUE_ARG: &str = "fvalue";
+    const FIRST_ARG: &str = "first";
+    const LAST_ARG: &str = "last";
+    fn var_range_set_args(ini: &IniSection) -> Self {
+        let (value, float, count) = if let Some(value) = ini.get::<String>(Self::VALUE_ARG) {
+            (value, false, NUM_VARS)
+        } else if let Some(fvalue) = ini.get::<String>(Self::FVALUE_ARG) {
+            (fvalue, true, NUM_FVARS)
+        } else {
+            eprintln!("{} needs a value or fvalue", VAR_RANGE_SET_SCTRL);
+            return Self::Null;
+        };
+        let first = ini.get::<usize>(Self::FIRST_ARG).unwrap_or(0);
+        let mut last = ini.get::<usize>(Self::LAST_ARG).unwrap_or(count - 1);
+        if last >= count {
+            eprintln!(
+                "{}: last = {} is out of range, there are {}",
+                VAR_RANGE_SET_SCTRL, last, count
+            );
+            last = count - 1;
         }
+        Self::VarRangeSet(VarRangeSetArgs {
+            first,
+            last,
+            value: Expression::new(&value),
+            float,
+        })
+    }
+
+    const RANGE_ARG: &str = "range";
+    fn var_random_args(ini: &IniSection) -> Self {
+        let v = match ini.get::<i32>(Self::V_ARG) {
+            Some(v) if (v as usize) < NUM_VARS => v as usize,
+            Some(v) => {
+                eprintln!("{}: var({}) is out of range, there are {}", VAR_RANDOM_SCTRL, v, NUM_VARS);
+                return Self::Null;
+            }
+            None => {
+                eprintln!("{} is missing {}", VAR_RANDOM_SCTRL, Self::V_ARG);
+                return Self::Null;
+            }
+        };
+        // range = max, or range = min, max
+        let range: String = ini.get(Self::RANGE_ARG).unwrap_or_else(|| "0, 999".to_string());
+        let (min, max) = match split_components(&range)[..] {
+            [min, max, ..] => (Expression::new(min), Expression::new(max)),
+            _ => (Expression::new("0"), Expression::new(&range)),
+        };
+        Self::VarRandom(VarRandomArgs { v, min, max })
+    }
+
+    fn pos_freeze_args(ini: &IniSection) -> Self {
+        let value: String = ini.get(Self::VALUE_ARG).unwrap_or_else(|| "1".to_string());
+        Self::PosFreeze(Expression::new(&value))
+    }
+
+    // front, back. back can be left out and is then 0.
+    fn front_back(ini: &IniSection, key: &str) -> Option<(Expression, Expression)> {
+        let value: String = ini.get(key)?;
+        Some(match split_components(&value)[..] {
+            [front, back, ..] => (Expression::new(front), Expression::new(back)),
+            _ => (Expression::new(&value), Expression::new("0")),
+        })
+    }
+
+    const EDGE_ARG: &str = "edge";
+    const PLAYER_ARG: &str = "player";
+    fn width_args(ini: &IniSection) -> Self {
+        // value sets both
+        let value = Self::front_back(ini, Self::VALUE_ARG);
+        Self::Width(WidthArgs {
+            edge: Self::front_back(ini, Self::EDGE_ARG).or_else(|| value.clone()),
+            player: Self::front_back(ini, Self::PLAYER_ARG).or(value),
+        })
+    }
+
+    fn player_push_args(ini: &IniSection) -> Self {
+        let value: String = ini.get(Self::VALUE_ARG).unwrap_or_else(|| "1".to_string());
+        Self::PlayerPush(Expression::new(&value))
+    }
+
+    const ID_ARG: &str = "id";
+    const TIME_ARG: &str = "time";
+    const POS_ARG: &str = "pos";
+    const KILL_ARG: &str = "kill";
+    const EXCLUDE_ID_ARG: &str = "excludeid";
+    const KEEP_ONE_ARG: &str = "keepone";
+    // pos = x, y. Anything after y, e.g. BindToTarget's postype, is left to
+    // the caller.
+    fn pos_arg(ini: &IniSection) -> (Expression, Expression) {
+        let pos: String = ini.get(Self::POS_ARG).unwrap_or_else(|| "0, 0".to_string());
+        match split_components(&pos)[..] {
+            [x, y, ..] => (Expression::new(x), Expression::new(y)),
+            _ => (Expression::new(&pos), Expression::new("0")),
+        }
+    }
+
+    fn target_args(name: &str, ini: &IniSection) -> Self {
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        let value = || match ini.get::<String>(Self::VALUE_ARG) {
+            Some(value) => Some(Expression::new(&value)),
+            None => {
+                eprintln!("{} is missing value", name);
+                None
+            }
+        };
+        let args = match name {
+            TARGET_STATE_SCTRL => value().map(TargetArgs::State),
+            TARGET_BIND_SCTRL => Some(TargetArgs::Bind {
+                time: expression(Self::TIME_ARG, "1"),
+                pos: Self::pos_arg(ini),
+            }),
+            // there's no attack/defence scaling yet, so `absolute` is ignored
+            TARGET_LIFE_ADD_SCTRL => value().map(|value| TargetArgs::LifeAdd {
+                value,
+                kill: expression(Self::KILL_ARG, "1"),
+            }),
+            TARGET_VEL_SET_SCTRL | TARGET_VEL_ADD_SCTRL => {
+                let (x, y) = Self::xy(ini);
+                let args = VelArgs { x, y };
+                Some(if name == TARGET_VEL_SET_SCTRL {
+                    TargetArgs::VelSet(args)
+                } else {
+                    TargetArgs::VelAdd(args)
+                })
+            }
+            TARGET_POWER_ADD_SCTRL => value().map(TargetArgs::PowerAdd),
+            TARGET_FACING_SCTRL => value().map(TargetArgs::Facing),
+            _ => Some(TargetArgs::Drop {
+                exclude_id: expression(Self::EXCLUDE_ID_ARG, "-1"),
+                keep_one: expression(Self::KEEP_ONE_ARG, "1"),
+            }),
+        };
+        match args {
+            Some(args) => Self::Target(expression(Self::ID_ARG, "-1"), args),
+            None => Self::Null,
+        }
+    }
+
+    const MOVE_TIME_ARG: &str = "movetime";
+    const DARKEN_ARG: &str = "darken";
+    const P2_DEF_MUL_ARG: &str = "p2defmul";
+    const POWER_ADD_ARG: &str = "poweradd";
+    const UNHITTABLE_ARG: &str = "unhittable";
+    const SOUND_ARG: &str = "sound";
+    // SuperPause's defaults are MUGEN's, its anim and sound are the super
+    // spark from fightfx and there's no fightfx or sound yet.
+    fn pause_args(name: &str, ini: &IniSection) -> Self {
+        let super_pause = name == SUPER_PAUSE_SCTRL;
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        if super_pause
+            && (ini.get::<String>(Self::ANIM_ARG).is_some()
+                || ini.get::<String>(Self::SOUND_ARG).is_some())
+        {
+            eprintln!("{}: anim and sound aren't supported yet", name);
+        }
+        let (time, darken, p2_def_mul, unhittable) = if super_pause {
+            ("30", "1", "1.5", "1")
+        } else {
+            ("0", "0", "1", "0")
+        };
+        Self::Pause(PauseArgs {
+            super_pause,
+            time: expression(Self::TIME_ARG, time),
+            move_time: expression(Self::MOVE_TIME_ARG, "0"),
+            darken: expression(Self::DARKEN_ARG, darken),
+            p2_def_mul: expression(Self::P2_DEF_MUL_ARG, p2_def_mul),
+            power_add: expression(Self::POWER_ADD_ARG, "0"),
+            unhittable: expression(Self::UNHITTABLE_ARG, unhittable),
+        })
+    }
+
+    const X_VEL_ARG: &str = "xvel";
+    const Y_VEL_ARG: &str = "yvel";
+    fn hit_fall_set_args(ini: &IniSection) -> Self {
+        let optional = |key: &str| ini.get::<String>(key).map(|expn| Expression::new(&expn));
+        Self::HitFallSet(HitFallSetArgs {
+            value: Expression::new(
+                &ini.get::<String>(Self::VALUE_ARG)
+                    .unwrap_or_else(|| "-1".to_string()),
+            ),
+            x_vel: optional(Self::X_VEL_ARG),
+            y_vel: optional(Self::Y_VEL_ARG),
+        })
+    }
+
+    // Attr strings are parsed when the states load, a bad one turns the
+    // controller into a Null.
+    fn attr_arg<T: FromStr<Err = &'static str>>(
+        name: &str,
+        ini: &IniSection,
+        key: &str,
+    ) -> Option<T> {
+        let attr: String = match ini.get(key) {
+            Some(attr) => attr,
+            None => {
+                eprintln!("{} is missing {}", name, key);
+                return None;
+            }
+        };
+        match T::from_str(&attr) {
+            Ok(attr) => Some(attr),
+            Err(err) => {
+                eprintln!("{}: {}, in {}", name, err, attr);
+                None
+            }
+        }
+    }
+
+    // value is slot 0, value2 slot 1
+    const VALUE_2_ARG: &str = "value2";
+    fn hit_by_args(name: &str, ini: &IniSection) -> Self {
+        let (slot, key) = match ini.get::<String>(Self::VALUE_2_ARG) {
+            Some(_) => (1, Self::VALUE_2_ARG),
+            None => (0, Self::VALUE_ARG),
+        };
+        match Self::attr_arg(name, ini, key) {
+            Some(attr) => Self::HitBy(HitByArgs {
+                slot,
+                attr,
+                time: Expression::new(
+                    &ini.get::<String>(Self::TIME_ARG)
+                        .unwrap_or_else(|| "1".to_string()),
+                ),
+                only: name == HIT_BY_SCTRL,
+            }),
+            None => Self::Null,
+        }
+    }
+
+    const ATTR_ARG: &str = "attr";
+    const STATE_NO_ARG: &str = "stateno";
+    const SLOT_ARG: &str = "slot";
+    const FORCE_AIR_ARG: &str = "forceair";
+    fn hit_override_args(ini: &IniSection) -> Self {
+        let attr = match Self::attr_arg(HIT_OVERRIDE_SCTRL, ini, Self::ATTR_ARG) {
+            Some(attr) => attr,
+            None => return Self::Null,
+        };
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        Self::HitOverride(HitOverrideArgs {
+            attr,
+            state_no: expression(Self::STATE_NO_ARG, "0"),
+            slot: expression(Self::SLOT_ARG, "0"),
+            time: expression(Self::TIME_ARG, "1"),
+            force_air: expression(Self::FORCE_AIR_ARG, "0"),
+        })
+    }
+
+    const REVERSAL_ATTR_ARG: &str = "reversal.attr";
+    const PAUSE_TIME_ARG: &str = "pausetime";
+    const P1_STATE_NO_ARG: &str = "p1stateno";
+    const P2_STATE_NO_ARG: &str = "p2stateno";
+    fn reversal_def_args(ini: &IniSection) -> Self {
+        let attr = match Self::attr_arg(REVERSAL_DEF_SCTRL, ini, Self::REVERSAL_ATTR_ARG) {
+            Some(attr) => attr,
+            None => return Self::Null,
+        };
+        let pause_time: String = ini
+            .get(Self::PAUSE_TIME_ARG)
+            .unwrap_or_else(|| "0, 0".to_string());
+        let pause_time = match split_components(&pause_time)[..] {
+            [p1, p2, ..] => (Expression::new(p1), Expression::new(p2)),
+            _ => (Expression::new(&pause_time), Expression::new(&pause_time)),
+        };
+        let expression = |key: &str| ini.get::<String>(key).map(|expn| Expression::new(&expn));
+        Self::ReversalDef(ReversalDefArgs {
+            attr,
+            pause_time,
+            p1_state: expression(Self::P1_STATE_NO_ARG),
+            p2_state: expression(Self::P2_STATE_NO_ARG),
+        })
+    }
+
+    const DAMAGE_ARG: &str = "damage";
+    const HIT_FLAG_ARG: &str = "hitflag";
+    const GUARD_FLAG_ARG: &str = "guardflag";
+    const CHAIN_ID_ARG: &str = "chainid";
+    const NO_CHAIN_ID_ARG: &str = "nochainid";
+    const HIT_ONCE_ARG: &str = "hitonce";
+    const GUARD_KILL_ARG: &str = "guard.kill";
+    const NUM_HITS_ARG: &str = "numhits";
+    const GUARD_PAUSE_TIME_ARG: &str = "guard.pausetime";
+    const ANIM_TYPE_ARG: &str = "animtype";
+    const AIR_ANIM_TYPE_ARG: &str = "air.animtype";
+    const FALL_ANIM_TYPE_ARG: &str = "fall.animtype";
+    const GROUND_TYPE_ARG: &str = "ground.type";
+    const AIR_TYPE_ARG: &str = "air.type";
+    const GROUND_SLIDE_TIME_ARG: &str = "ground.slidetime";
+    const GUARD_SLIDE_TIME_ARG: &str = "guard.slidetime";
+    const GROUND_HIT_TIME_ARG: &str = "ground.hittime";
+    const GUARD_HIT_TIME_ARG: &str = "guard.hittime";
+    const AIR_HIT_TIME_ARG: &str = "air.hittime";
+    const GUARD_CTRL_TIME_ARG: &str = "guard.ctrltime";
+    const AIR_GUARD_CTRL_TIME_ARG: &str = "airguard.ctrltime";
+    const Y_ACCEL_ARG: &str = "yaccel";
+    const GROUND_VELOCITY_ARG: &str = "ground.velocity";
+    const GUARD_VELOCITY_ARG: &str = "guard.velocity";
+    const AIR_VELOCITY_ARG: &str = "air.velocity";
+    const AIR_GUARD_VELOCITY_ARG: &str = "airguard.velocity";
+    const DOWN_VELOCITY_ARG: &str = "down.velocity";
+    const DOWN_HIT_TIME_ARG: &str = "down.hittime";
+    const DOWN_BOUNCE_ARG: &str = "down.bounce";
+    const AIR_JUGGLE_ARG: &str = "air.juggle";
+    const P2_GET_P1_STATE_ARG: &str = "p2getp1state";
+    const FORCE_STAND_ARG: &str = "forcestand";
+    const FALL_ARG: &str = "fall";
+    const FALL_X_VELOCITY_ARG: &str = "fall.xvelocity";
+    const FALL_Y_VELOCITY_ARG: &str = "fall.yvelocity";
+    const FALL_RECOVER_ARG: &str = "fall.recover";
+    const FALL_RECOVER_TIME_ARG: &str = "fall.recovertime";
+    const FALL_DAMAGE_ARG: &str = "fall.damage";
+    const FALL_KILL_ARG: &str = "fall.kill";
+    const FALL_ENV_SHAKE_ARG: &str = "fall.envshake.";
+    const AIR_FALL_ARG: &str = "air.fall";
+    const FORCE_NO_FALL_ARG: &str = "forcenofall";
+    // What a HitDef can have that nothing here does anything with yet:
+    // sparks, sounds, guarding distance, corner pushes, priorities, teams,
+    // power, facings, snapping and the on-hit effects.
+    const HIT_DEF_UNSUPPORTED_ARGS: [&str; 30] = [
+        "affectteam",
+        "priority",
+        "sparkno",
+        "guard.sparkno",
+        "sparkxy",
+        "hitsound",
+        "guardsound",
+        "guard.dist",
+        "ground.cornerpush.veloff",
+        "air.cornerpush.veloff",
+        "down.cornerpush.veloff",
+        "guard.cornerpush.veloff",
+        "airguard.cornerpush.veloff",
+        "mindist",
+        "maxdist",
+        "snap",
+        "p1sprpriority",
+        "p2sprpriority",
+        "p1facing",
+        "p1getp2facing",
+        "p2facing",
+        "getpower",
+        "givepower",
+        "palfx.time",
+        "palfx.mul",
+        "palfx.add",
+        "envshake.time",
+        "envshake.freq",
+        "envshake.ampl",
+        "envshake.phase",
+    ];
+    // MUGEN's defaults. Some of them are other parameters', those are left
+    // out here and filled in when the HitDef runs.
+    fn hit_def_args(ini: &IniSection) -> Self {
+        let attr: AttackAttr = match Self::attr_arg(HIT_DEF_SCTRL, ini, Self::ATTR_ARG) {
+            Some(attr) => attr,
+            None => return Self::Null,
+        };
+        for key in Self::HIT_DEF_UNSUPPORTED_ARGS {
+            if ini.get::<String>(key).is_some() {
+                eprintln!("{}: {} isn't supported yet", HIT_DEF_SCTRL, key);
+            }
+        }
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        let optional = |key: &str| ini.get::<String>(key).map(|expn| Expression::new(&expn));
+        let optional_pair = |key: &str| {
+            optional(key).map(|_| {
+                let [x, y] = Self::components(ini, key, ["0", "0"]);
+                (x, y)
+            })
+        };
+        let flag = |key: &str, default: &str| -> HitFlags {
+            Self::parsed_arg(ini, key).unwrap_or_else(|| HitFlags::from_str(default).unwrap())
+        };
+        let [damage, guard_damage] = Self::components(ini, Self::DAMAGE_ARG, ["0", "0"]);
+        let [p1_pause, p2_pause] = Self::components(ini, Self::PAUSE_TIME_ARG, ["0", "0"]);
+        let [no_chain_1, no_chain_2] = Self::components(ini, Self::NO_CHAIN_ID_ARG, ["-1", "-1"]);
+        let [ground_x, ground_y] = Self::components(ini, Self::GROUND_VELOCITY_ARG, ["0", "0"]);
+        let [air_x, air_y] = Self::components(ini, Self::AIR_VELOCITY_ARG, ["0", "0"]);
+        let anim_type = Self::parsed_arg(ini, Self::ANIM_TYPE_ARG).unwrap_or_default();
+        let air_anim_type = Self::parsed_arg(ini, Self::AIR_ANIM_TYPE_ARG).unwrap_or(anim_type);
+        let ground_type = Self::parsed_arg(ini, Self::GROUND_TYPE_ARG).unwrap_or_default();
+        let hit_once = if attr.kind == AttackKind::T { "1" } else { "0" };
+        Self::HitDef(HitDefArgs {
+            attr,
+            hit_flag: flag(Self::HIT_FLAG_ARG, "MAF"),
+            guard_flag: flag(Self::GUARD_FLAG_ARG, ""),
+            id: expression(Self::ID_ARG, "0"),
+            chain_id: expression(Self::CHAIN_ID_ARG, "-1"),
+            no_chain_id: [no_chain_1, no_chain_2],
+            hit_once: expression(Self::HIT_ONCE_ARG, hit_once),
+            damage,
+            guard_damage,
+            kill: expression(Self::KILL_ARG, "1"),
+            guard_kill: expression(Self::GUARD_KILL_ARG, "1"),
+            num_hits: expression(Self::NUM_HITS_ARG, "1"),
+            pause_time: (p1_pause, p2_pause),
+            guard_pause_time: optional_pair(Self::GUARD_PAUSE_TIME_ARG),
+            anim_type,
+            air_anim_type,
+            // Up and DiagUp hits fall that way, the rest fall back
+            fall_anim_type: Self::parsed_arg(ini, Self::FALL_ANIM_TYPE_ARG).unwrap_or(
+                match air_anim_type {
+                    AnimType::Up | AnimType::DiagUp => air_anim_type,
+                    _ => AnimType::Back,
+                },
+            ),
+            ground_type,
+            air_type: Self::parsed_arg(ini, Self::AIR_TYPE_ARG).unwrap_or(ground_type),
+            ground_slide_time: expression(Self::GROUND_SLIDE_TIME_ARG, "0"),
+            guard_slide_time: optional(Self::GUARD_SLIDE_TIME_ARG),
+            ground_hit_time: expression(Self::GROUND_HIT_TIME_ARG, "0"),
+            guard_hit_time: optional(Self::GUARD_HIT_TIME_ARG),
+            air_hit_time: expression(Self::AIR_HIT_TIME_ARG, "20"),
+            guard_ctrl_time: optional(Self::GUARD_CTRL_TIME_ARG),
+            air_guard_ctrl_time: optional(Self::AIR_GUARD_CTRL_TIME_ARG),
+            y_accel: expression(Self::Y_ACCEL_ARG, ".35"),
+            ground_velocity: (ground_x, ground_y),
+            guard_velocity: optional(Self::GUARD_VELOCITY_ARG),
+            air_velocity: (air_x, air_y),
+            air_guard_velocity: optional_pair(Self::AIR_GUARD_VELOCITY_ARG),
+            down_velocity: optional_pair(Self::DOWN_VELOCITY_ARG),
+            down_hit_time: expression(Self::DOWN_HIT_TIME_ARG, "0"),
+            down_bounce: expression(Self::DOWN_BOUNCE_ARG, "0"),
+            air_juggle: optional(Self::AIR_JUGGLE_ARG),
+            p1_state: optional(Self::P1_STATE_NO_ARG),
+            p2_state: optional(Self::P2_STATE_NO_ARG),
+            p2_get_p1_state: expression(Self::P2_GET_P1_STATE_ARG, "1"),
+            force_stand: optional(Self::FORCE_STAND_ARG),
+            fall: expression(Self::FALL_ARG, "0"),
+            fall_x_vel: optional(Self::FALL_X_VELOCITY_ARG),
+            fall_y_vel: expression(Self::FALL_Y_VELOCITY_ARG, "-4.5"),
+            fall_recover: expression(Self::FALL_RECOVER_ARG, "1"),
+            fall_recover_time: expression(Self::FALL_RECOVER_TIME_ARG, "4"),
+            fall_damage: expression(Self::FALL_DAMAGE_ARG, "0"),
+            fall_kill: expression(Self::FALL_KILL_ARG, "1"),
+            fall_env_shake: Self::env_shake_params(ini, Self::FALL_ENV_SHAKE_ARG),
+            air_fall: optional(Self::AIR_FALL_ARG),
+            force_no_fall: expression(Self::FORCE_NO_FALL_ARG, "0"),
+        })
+    }
+
+    // A parameter that's parsed when the states load, like attr_arg, but
+    // that can be left out.
+    fn parsed_arg<T: FromStr<Err = &'static str>>(ini: &IniSection, key: &str) -> Option<T> {
+        let value: String = ini.get(key)?;
+        match T::from_str(&value) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                eprintln!("{}: {}, in {}", key, err, value);
+                None
+            }
+        }
+    }
+
+    // A parameter with several components, e.g. `add = 0, 0, 0`. Components
+    // that are left out get their defaults.
+    fn components<const N: usize>(
+        ini: &IniSection,
+        key: &str,
+        default: [&str; N],
+    ) -> [Expression; N] {
+        let value: String = ini.get(key).unwrap_or_default();
+        let components = split_components(&value);
+        std::array::from_fn(|i| match components.get(i) {
+            Some(component) if !component.is_empty() => Expression::new(component),
+            _ => Expression::new(default[i]),
+        })
+    }
+
+    const ADD_ARG: &str = "add";
+    const MUL_ARG: &str = "mul";
+    const SIN_ADD_ARG: &str = "sinadd";
+    const INVERT_ALL_ARG: &str = "invertall";
+    const COLOR_ARG: &str = "color";
+    fn pal_fx_args(ini: &IniSection) -> Self {
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        Self::PalFx(PalFxArgs {
+            time: expression(Self::TIME_ARG, "0"),
+            add: Self::components(ini, Self::ADD_ARG, ["0", "0", "0"]),
+            mul: Self::components(ini, Self::MUL_ARG, ["256", "256", "256"]),
+            sin_add: Self::components(ini, Self::SIN_ADD_ARG, ["0", "0", "0", "0"]),
+            invert: expression(Self::INVERT_ALL_ARG, "0"),
+            color: expression(Self::COLOR_ARG, "256"),
+        })
+    }
+
+    const LENGTH_ARG: &str = "length";
+    const PAL_COLOR_ARG: &str = "palcolor";
+    const PAL_INVERT_ALL_ARG: &str = "palinvertall";
+    const PAL_BRIGHT_ARG: &str = "palbright";
+    const PAL_CONTRAST_ARG: &str = "palcontrast";
+    const PAL_POST_BRIGHT_ARG: &str = "palpostbright";
+    const PAL_ADD_ARG: &str = "paladd";
+    const PAL_MUL_ARG: &str = "palmul";
+    const TIME_GAP_ARG: &str = "timegap";
+    const FRAME_GAP_ARG: &str = "framegap";
+    const TRANS_ARG: &str = "trans";
+    fn after_image_args(ini: &IniSection) -> Self {
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        Self::AfterImage(AfterImageArgs {
+            time: expression(Self::TIME_ARG, "1"),
+            length: expression(Self::LENGTH_ARG, "20"),
+            time_gap: expression(Self::TIME_GAP_ARG, "1"),
+            frame_gap: expression(Self::FRAME_GAP_ARG, "4"),
+            color: expression(Self::PAL_COLOR_ARG, "256"),
+            invert: expression(Self::PAL_INVERT_ALL_ARG, "0"),
+            bright: Self::components(ini, Self::PAL_BRIGHT_ARG, ["30", "30", "30"]),
+            contrast: Self::components(ini, Self::PAL_CONTRAST_ARG, ["120", "120", "220"]),
+            post_bright: Self::components(ini, Self::PAL_POST_BRIGHT_ARG, ["0", "0", "0"]),
+            add: Self::components(ini, Self::PAL_ADD_ARG, ["10", "10", "25"]),
+            mul: Self::components(ini, Self::PAL_MUL_ARG, [".65", ".65", ".75"]),
+            trans: Self::trans_arg(AFTER_IMAGE_SCTRL, ini).unwrap_or(Trans::None),
+        })
+    }
+
+    fn after_image_time_args(ini: &IniSection) -> Self {
+        let time = ini
+            .get::<String>(Self::TIME_ARG)
+            .or_else(|| ini.get::<String>(Self::VALUE_ARG));
+        match time {
+            Some(time) => Self::AfterImageTime(Expression::new(&time)),
+            None => {
+                eprintln!("{} is missing time", AFTER_IMAGE_TIME_SCTRL);
+                Self::Null
+            }
+        }
+    }
+
+    const FREQ_ARG: &str = "freq";
+    const AMPL_ARG: &str = "ampl";
+    const PHASE_ARG: &str = "phase";
+    fn env_shake_args(ini: &IniSection) -> Self {
+        Self::EnvShake(Self::env_shake_params(ini, ""))
+    }
+
+    // EnvShake's parameters, or a HitDef's fall.envshake.* with that prefix.
+    fn env_shake_params(ini: &IniSection, prefix: &str) -> EnvShakeArgs {
+        let key = |key: &str| format!("{}{}", prefix, key);
+        let expression = |key: String, default: &str| {
+            Expression::new(&ini.get::<String>(&key).unwrap_or_else(|| default.to_string()))
+        };
+        EnvShakeArgs {
+            time: expression(key(Self::TIME_ARG), "0"),
+            freq: expression(key(Self::FREQ_ARG), "60"),
+            ampl: expression(key(Self::AMPL_ARG), "-4"),
+            phase: ini
+                .get::<String>(&key(Self::PHASE_ARG))
+                .map(|phase| Expression::new(&phase)),
+        }
+    }
+
+    const UNDER_ARG: &str = "under";
+    fn env_color_args(ini: &IniSection) -> Self {
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        Self::EnvColor(EnvColorArgs {
+            color: Self::components(ini, Self::VALUE_ARG, ["255", "255", "255"]),
+            time: expression(Self::TIME_ARG, "1"),
+            under: expression(Self::UNDER_ARG, "0"),
+        })
+    }
+
+    // Trans isn't an expression, it's read when the states load.
+    fn trans_arg(name: &str, ini: &IniSection) -> Option<Trans> {
+        let trans: String = ini.get(Self::TRANS_ARG)?;
+        match Trans::from_str(&trans) {
+            Ok(trans) => Some(trans),
+            Err(err) => {
+                eprintln!("{}: {}", name, err);
+                None
+            }
+        }
+    }
+
+    const ALPHA_ARG: &str = "alpha";
+    fn trans_args(ini: &IniSection) -> Self {
+        match Self::trans_arg(TRANS_SCTRL, ini) {
+            Some(trans) => {
+                let alpha = match trans {
+                    Trans::AddAlpha(..) => {
+                        let [source, dest] = Self::components(ini, Self::ALPHA_ARG, ["256", "0"]);
+                        Some((source, dest))
+                    }
+                    _ => None,
+                };
+                Self::Trans(trans, alpha)
+            }
+            None => Self::Null,
+        }
+    }
+
+    const SCALE_ARG: &str = "scale";
+    fn angle_draw_args(ini: &IniSection) -> Self {
+        let angle = ini
+            .get::<String>(Self::VALUE_ARG)
+            .map(|value| Expression::new(&value));
+        let scale = ini.get::<String>(Self::SCALE_ARG).map(|_| {
+            let [x, y] = Self::components(ini, Self::SCALE_ARG, ["1", "1"]);
+            (x, y)
+        });
+        Self::AngleDraw(angle, scale)
+    }
+
+    fn bind_to_target_args(ini: &IniSection) -> Self {
+        let pos: String = ini.get(Self::POS_ARG).unwrap_or_default();
+        if let Some(pos_type) = split_components(&pos).get(2) {
+            if !pos_type.eq_ignore_ascii_case("foot") {
+                eprintln!("{}: only postype foot is supported", BIND_TO_TARGET_SCTRL);
+            }
+        }
+        let expression = |key: &str, default: &str| {
+            Expression::new(&ini.get::<String>(key).unwrap_or_else(|| default.to_string()))
+        };
+        Self::BindToTarget(BindToTargetArgs {
+            id: expression(Self::ID_ARG, "-1"),
+            time: expression(Self::TIME_ARG, "1"),
+            pos: Self::pos_arg(ini),
+        })
     }
 }
 // Null.
-const NULL_SCTRL: (&'static str) = ("null");
-fn null(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) { /* No OP */
+pub const NULL_SCTRL: (&'static str) = ("null");
+fn null(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) { /* No OP */
 }
 
 // Change State
 #[derive(Clone)]
 pub struct ChangeStateArgs {
-    pub state_no: i32,
-    pub ctrl_flag: Option<i32>,
-    pub anim_no: Option<i32>,
+    pub state_no: Expression,
+    pub ctrl_flag: Option<Expression>,
+    pub anim_no: Option<Expression>,
 }
 
 impl TryFrom<StateArgs> for ChangeStateArgs {
     type Error = &'static str;
     fn try_from(args: StateArgs) -> Result<Self, Self::Error> {
         match args {
-            StateArgs::ChangeState(c) => Ok(c),
+            StateArgs::ChangeState(c) | StateArgs::SelfState(c) => Ok(c),
             _ => Err(INVALID_TYPE_FOR_ARGS_ERR),
         }
     }
@@ -190,14 +972,15 @@ impl From<ChangeStateArgs> for StateArgs {
 }
 
 const CHANGE_STATE_SCTRL: (&'static str) = ("changestate");
-fn change_state(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: ChangeStateArgs = args.try_into().unwrap();
-    char.set_state(args.state_no);
-    if args.anim_no.is_some() {
-        char.set_animation_no(args.anim_no.unwrap());
-    }
-    if args.ctrl_flag.is_some() {
-        char.set_ctrl_flag(args.ctrl_flag.unwrap());
+fn change_state(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = ChangeStateArgs::try_from(args) {
+        char.set_state(args.state_no.evaluate_int(ctx));
+        if let Some(anim_no) = args.anim_no {
+            char.set_animation_no(anim_no.evaluate_int(ctx));
+        }
+        if let Some(ctrl_flag) = args.ctrl_flag {
+            char.set_ctrl_flag(ctrl_flag.evaluate_boolean(ctx) as i32);
+        }
     }
 }
 
@@ -225,36 +1008,38 @@ impl From<VelArgs> for StateArgs {
 }
 
 pub const VEL_SET_SCTRL: (&'static str) = ("velset");
-fn vel_set(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: VelArgs = args.try_into().unwrap();
-    if args.x.is_some() {
-        char.velocity.x = args.x.unwrap().evaluate_float(ctx); 
-    } 
-
-    if args.y.is_some() {
-        char.velocity.y = args.y.unwrap().evaluate_float(ctx); 
-    } 
+fn vel_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = VelArgs::try_from(args) {
+        if let Some(x) = args.x {
+            char.velocity.x = x.evaluate_float(ctx);
+        }
+        if let Some(y) = args.y {
+            char.velocity.y = y.evaluate_float(ctx);
+        }
+    }
 }
 
 pub const VEL_MUL_SCTRL: (&'static str) = ("velmul");
-fn vel_mul(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: VelArgs = args.try_into().unwrap();
-    if args.x.is_some() {
-        char.velocity.x *= args.x.unwrap().evaluate_float(ctx); 
-    } 
-    if args.y.is_some() {
-        char.velocity.y *= args.y.unwrap().evaluate_float(ctx); 
-    } 
+fn vel_mul(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = VelArgs::try_from(args) {
+        if let Some(x) = args.x {
+            char.velocity.x *= x.evaluate_float(ctx);
+        }
+        if let Some(y) = args.y {
+            char.velocity.y *= y.evaluate_float(ctx);
+        }
+    }
 }
 
 pub const VEL_ADD_SCTRL: (&'static str) = ("veladd");
-fn vel_add(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: VelArgs = args.try_into().unwrap();
-    if args.x.is_some() {
-        char.velocity.x += args.x.unwrap().evaluate_float(ctx); 
-    } 
-    if args.y.is_some() {
-        char.velocity.y += args.y.unwrap().evaluate_float(ctx); 
+fn vel_add(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = VelArgs::try_from(args) {
+        if let Some(x) = args.x {
+            char.velocity.x += x.evaluate_float(ctx);
+        }
+        if let Some(y) = args.y {
+            char.velocity.y += y.evaluate_float(ctx);
+        }
     }
 }
 
@@ -282,32 +1067,34 @@ impl From<PosArgs> for StateArgs {
 }
 
 pub const POS_SET_SCTRL: (&'static str) = ("posset");
-fn pos_set(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: PosArgs = args.try_into().unwrap();
-    if args.x.is_some() {
-        char.position.x = args.x.unwrap().evaluate_float(ctx); 
-    } 
-    if args.y.is_some() {
-        char.position.y = args.y.unwrap().evaluate_float(ctx); 
-    } 
+fn pos_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = PosArgs::try_from(args) {
+        if let Some(x) = args.x {
+            char.position.x = x.evaluate_float(ctx);
+        }
+        if let Some(y) = args.y {
+            char.position.y = y.evaluate_float(ctx);
+        }
+    }
 }
 
 pub const POS_ADD_SCTRL: (&'static str) = ("posadd");
-fn pos_add(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: PosArgs = args.try_into().unwrap();
-    if args.x.is_some() {
-        char.position.x += args.x.unwrap().evaluate_float(ctx); 
-    }
-    if args.y.is_some() {
-        char.position.y += args.y.unwrap().evaluate_float(ctx); 
+fn pos_add(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = PosArgs::try_from(args) {
+        if let Some(x) = args.x {
+            char.position.x += x.evaluate_float(ctx) * char.facing;
+        }
+        if let Some(y) = args.y {
+            char.position.y += y.evaluate_float(ctx);
+        }
     }
 }
 
 // Ctrl
 pub const CTRL_SET_SCTRL: (&'static str) = ("ctrlset");
-fn ctrl_set(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
+fn ctrl_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
     char.ctrl_flag = match args {
-        StateArgs::CtrlSet(flag) => flag,
+        StateArgs::CtrlSet(flag) => flag.evaluate_boolean(ctx) as i32,
         _ => char.ctrl_flag,
     };
     dbg!(char.ctrl_flag);
@@ -324,12 +1111,19 @@ impl TryFrom<StateArgs> for ChangeAnimArgs {
     type Error = &'static str;
     fn try_from(args: StateArgs) -> Result<Self, Self::Error> {
         match args {
-            StateArgs::ChangeAnim(args) => Ok(args),
+            StateArgs::ChangeAnim(args) | StateArgs::ChangeAnim2(args) => Ok(args),
             _ => Err(INVALID_TYPE_FOR_ARGS_ERR),
         }
     }
 }
 
+impl ChangeAnimArgs {
+    // The action when it's a plain number, for checking the AIR has it.
+    pub fn constant_anim_no(&self) -> Option<i32> {
+        self.anim_no.constant_int()
+    }
+}
+
 impl From<ChangeAnimArgs> for StateArgs {
     fn from(args: ChangeAnimArgs) -> StateArgs {
         StateArgs::ChangeAnim(args)
@@ -337,13 +1131,12 @@ impl From<ChangeAnimArgs> for StateArgs {
 }
 
 pub const CHANGE_ANIM_SCTRL: (&'static str) = ("changeanim");
-fn change_anim(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: ChangeAnimArgs = args.try_into().unwrap();
-    let anim_no = args.anim_no.evaluate_int(ctx);
-    char.set_animation_no(anim_no);
-    if args.elem.is_some() {
-        let elem = args.elem.unwrap().evaluate_int(ctx);
-        char.set_animation_element(elem);
+fn change_anim(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = ChangeAnimArgs::try_from(args) {
+        char.set_animation_no(args.anim_no.evaluate_int(ctx));
+        if let Some(elem) = args.elem {
+            char.set_animation_element(elem.evaluate_int(ctx));
+        }
     }
 }
 
@@ -353,28 +1146,770 @@ pub enum VarSetArgs {
     Int(usize, Expression),
     Float(usize, Expression),
     System(usize, Expression),
+    SystemFloat(usize, Expression),
 }
 
 impl TryFrom<StateArgs> for VarSetArgs {
     type Error = &'static str;
     fn try_from(args: StateArgs) -> Result<Self, Self::Error> {
         match args {
-            StateArgs::VarSet(args) => Ok(args),
+            StateArgs::VarSet(args) | StateArgs::VarAdd(args) => Ok(args),
             _ => Err(INVALID_TYPE_FOR_ARGS_ERR),
         }
     }
 }
 
 pub const VAR_SET_SCTRL: &'static str = "varset";
-fn var_set(char: &mut CharState, args: StateArgs, ctx: &ExpressionContext) {
-    let args: VarSetArgs = args.try_into().unwrap();
-    match args {
-        VarSetArgs::Int(idx, val) => {
-            char.set_int_var(idx, val.evaluate_int(ctx))
+fn var_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    match VarSetArgs::try_from(args) {
+        Ok(VarSetArgs::Int(idx, val)) => char.set_int_var(idx, val.evaluate_int(ctx)),
+        Ok(VarSetArgs::Float(idx, fval)) => char.set_float_var(idx, fval.evaluate_float(ctx)),
+        Ok(VarSetArgs::System(idx, val)) => char.set_sys_var(idx, val.evaluate_int(ctx)),
+        Ok(VarSetArgs::SystemFloat(idx, fval)) => {
+            char.set_sys_fvar(idx, fval.evaluate_float(ctx))
+        }
+        Err(_) => {}
+    }
+}
+
+// VarAdd
+pub const VAR_ADD_SCTRL: &'static str = "varadd";
+fn var_add(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    match VarSetArgs::try_from(args) {
+        Ok(VarSetArgs::Int(idx, val)) => {
+            char.set_int_var(idx, char.get_int_var(idx) + val.evaluate_int(ctx))
+        }
+        Ok(VarSetArgs::Float(idx, fval)) => {
+            char.set_float_var(idx, char.get_flaot_var(idx) + fval.evaluate_float(ctx))
+        }
+        Ok(VarSetArgs::System(idx, val)) => {
+            char.set_sys_var(idx, char.sys_var(idx) + val.evaluate_int(ctx))
+        }
+        Ok(VarSetArgs::SystemFloat(idx, fval)) => {
+            char.set_sys_fvar(idx, char.sys_fvar(idx) + fval.evaluate_float(ctx))
+        }
+        Err(_) => {}
+    }
+}
+
+// VarRangeSet
+#[derive(Clone)]
+pub struct VarRangeSetArgs {
+    first: usize,
+    last: usize,
+    value: Expression,
+    float: bool, // fvalue, sets fvars
+}
+
+pub const VAR_RANGE_SET_SCTRL: &'static str = "varrangeset";
+fn var_range_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::VarRangeSet(args) = args {
+        if args.float {
+            let value = args.value.evaluate_float(ctx);
+            for idx in args.first..=args.last {
+                char.set_float_var(idx, value);
+            }
+        } else {
+            let value = args.value.evaluate_int(ctx);
+            for idx in args.first..=args.last {
+                char.set_int_var(idx, value);
+            }
+        }
+    }
+}
+
+// ParentVarSet, ParentVarAdd
+pub const PARENT_VAR_SET_SCTRL: &'static str = "parentvarset";
+pub const PARENT_VAR_ADD_SCTRL: &'static str = "parentvaradd";
+
+// VarRandom
+#[derive(Clone)]
+pub struct VarRandomArgs {
+    v: usize,
+    min: Expression,
+    max: Expression,
+}
+
+impl TryFrom<StateArgs> for VarRandomArgs {
+    type Error = &'static str;
+    fn try_from(args: StateArgs) -> Result<Self, Self::Error> {
+        match args {
+            StateArgs::VarRandom(args) => Ok(args),
+            _ => Err(INVALID_TYPE_FOR_ARGS_ERR),
+        }
+    }
+}
+
+pub const VAR_RANDOM_SCTRL: &'static str = "varrandom";
+fn var_random(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = VarRandomArgs::try_from(args) {
+        let value = ctx.random(args.min.evaluate_int(ctx), args.max.evaluate_int(ctx));
+        char.set_int_var(args.v, value);
+    }
+}
+
+// Gravity
+pub const GRAVITY_SCTRL: &'static str = "gravity";
+fn gravity(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Some(yaccel) = ctx.get_float("movement.yaccel") {
+        char.velocity.y += yaccel;
+    }
+}
+
+// PosFreeze
+pub const POS_FREEZE_SCTRL: &'static str = "posfreeze";
+fn pos_freeze(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::PosFreeze(value) = args {
+        char.pos_freeze = value.evaluate_int(ctx) != 0;
+    }
+}
+
+// Width
+#[derive(Clone)]
+pub struct WidthArgs {
+    edge: Option<(Expression, Expression)>,
+    player: Option<(Expression, Expression)>,
+}
+
+pub const WIDTH_SCTRL: &'static str = "width";
+fn width(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Width(args) = args {
+        if let Some((front, back)) = args.edge {
+            char.edge_width = (front.evaluate_float(ctx), back.evaluate_float(ctx));
+        }
+        if let Some((front, back)) = args.player {
+            char.player_width = Some((front.evaluate_float(ctx), back.evaluate_float(ctx)));
         }
-        VarSetArgs::Float(idx, fval) => char.set_float_var(idx, fval.evaluate_float(ctx)),
-        VarSetArgs::System(idx, val) => {
-            char.set_sys_var(idx, val.evaluate_int(ctx))
+    }
+}
+
+// PlayerPush
+pub const PLAYER_PUSH_SCTRL: &'static str = "playerpush";
+fn player_push(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::PlayerPush(value) = args {
+        char.player_push = value.evaluate_int(ctx) != 0;
+    }
+}
+
+// SelfState, ChangeState back into the char's own states
+pub const SELF_STATE_SCTRL: &'static str = "selfstate";
+fn self_state(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    char.state_owner = None;
+    change_state(char, args, ctx);
+}
+
+// ChangeAnim2, an anim of the state owner's
+pub const CHANGE_ANIM_2_SCTRL: &'static str = "changeanim2";
+fn change_anim2(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let Ok(args) = ChangeAnimArgs::try_from(args) {
+        char.set_owner_animation_no(args.anim_no.evaluate_int(ctx));
+        if let Some(elem) = args.elem {
+            char.set_animation_element(elem.evaluate_int(ctx));
         }
     }
 }
+
+// TargetState, TargetBind, TargetLifeAdd, TargetVelSet, TargetVelAdd,
+// TargetPowerAdd, TargetFacing and TargetDrop. They're applied to the targets
+// once the char's states have run, see target::apply_effects.
+#[derive(Clone)]
+pub enum TargetArgs {
+    State(Expression),
+    Bind {
+        time: Expression,
+        pos: (Expression, Expression),
+    },
+    LifeAdd {
+        value: Expression,
+        kill: Expression,
+    },
+    VelSet(VelArgs),
+    VelAdd(VelArgs),
+    PowerAdd(Expression),
+    Facing(Expression),
+    Drop {
+        exclude_id: Expression,
+        keep_one: Expression,
+    },
+}
+
+pub const TARGET_STATE_SCTRL: &'static str = "targetstate";
+pub const TARGET_BIND_SCTRL: &'static str = "targetbind";
+pub const TARGET_LIFE_ADD_SCTRL: &'static str = "targetlifeadd";
+pub const TARGET_VEL_SET_SCTRL: &'static str = "targetvelset";
+pub const TARGET_VEL_ADD_SCTRL: &'static str = "targetveladd";
+pub const TARGET_POWER_ADD_SCTRL: &'static str = "targetpoweradd";
+pub const TARGET_FACING_SCTRL: &'static str = "targetfacing";
+pub const TARGET_DROP_SCTRL: &'static str = "targetdrop";
+const TARGET_SCTRLS: [&'static str; 8] = [
+    TARGET_STATE_SCTRL,
+    TARGET_BIND_SCTRL,
+    TARGET_LIFE_ADD_SCTRL,
+    TARGET_VEL_SET_SCTRL,
+    TARGET_VEL_ADD_SCTRL,
+    TARGET_POWER_ADD_SCTRL,
+    TARGET_FACING_SCTRL,
+    TARGET_DROP_SCTRL,
+];
+
+fn target(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Target(id, args) = args {
+        let optional = |expn: Option<Expression>| expn.map(|expn| expn.evaluate_float(ctx));
+        let effect = match args {
+            TargetArgs::State(value) => TargetEffect::State(value.evaluate_int(ctx)),
+            TargetArgs::Bind { time, pos: (x, y) } => TargetEffect::Bind {
+                time: time.evaluate_int(ctx),
+                offset: Vec2::new(x.evaluate_float(ctx), y.evaluate_float(ctx)),
+            },
+            TargetArgs::LifeAdd { value, kill } => TargetEffect::LifeAdd {
+                value: value.evaluate_int(ctx),
+                kill: kill.evaluate_boolean(ctx),
+            },
+            TargetArgs::VelSet(VelArgs { x, y }) => TargetEffect::VelSet(optional(x), optional(y)),
+            TargetArgs::VelAdd(VelArgs { x, y }) => TargetEffect::VelAdd(optional(x), optional(y)),
+            TargetArgs::PowerAdd(value) => TargetEffect::PowerAdd(value.evaluate_int(ctx)),
+            TargetArgs::Facing(value) => TargetEffect::Facing(value.evaluate_int(ctx)),
+            TargetArgs::Drop {
+                exclude_id,
+                keep_one,
+            } => TargetEffect::Drop {
+                exclude_id: exclude_id.evaluate_int(ctx),
+                keep_one: keep_one.evaluate_boolean(ctx),
+            },
+        };
+        char.queue_target_effect(id.evaluate_int(ctx), effect);
+    }
+}
+
+// BindToTarget, the other way around from TargetBind
+#[derive(Clone)]
+pub struct BindToTargetArgs {
+    id: Expression,
+    time: Expression,
+    pos: (Expression, Expression),
+}
+
+pub const BIND_TO_TARGET_SCTRL: &'static str = "bindtotarget";
+fn bind_to_target(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::BindToTarget(args) = args {
+        let id = args.id.evaluate_int(ctx);
+        let target = char
+            .targets
+            .iter()
+            .find(|target| id == -1 || target.id == id)
+            .copied();
+        if let Some(target) = target {
+            let (x, y) = args.pos;
+            char.bind = Some(Bind {
+                to: target.player,
+                time: args.time.evaluate_int(ctx),
+                offset: Vec2::new(x.evaluate_float(ctx), y.evaluate_float(ctx)),
+            });
+        }
+    }
+}
+
+// Pause, SuperPause
+#[derive(Clone)]
+pub struct PauseArgs {
+    super_pause: bool,
+    time: Expression,
+    move_time: Expression,
+    darken: Expression,
+    p2_def_mul: Expression,
+    power_add: Expression,
+    unhittable: Expression,
+}
+
+pub const PAUSE_SCTRL: &'static str = "pause";
+pub const SUPER_PAUSE_SCTRL: &'static str = "superpause";
+fn pause(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Pause(args) = args {
+        let max_power = ctx.get_float("data.power").unwrap_or(3000.0) as i32;
+        char.add_power(args.power_add.evaluate_int(ctx), max_power);
+        char.pause_request = Some(Pause {
+            time: args.time.evaluate_int(ctx),
+            move_time: args.move_time.evaluate_int(ctx),
+            super_pause: args.super_pause,
+            darken: args.darken.evaluate_boolean(ctx),
+            p2_def_mul: args.p2_def_mul.evaluate_float(ctx),
+            unhittable: args.unhittable.evaluate_boolean(ctx),
+        });
+    }
+}
+
+// HitFallDamage, the fall damage of the HitDef that hit the char, once it
+// lands
+pub const HIT_FALL_DAMAGE_SCTRL: &'static str = "hitfalldamage";
+fn hit_fall_damage(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    let fall = char.get_hit.fall;
+    char.add_life(-fall.damage, fall.kill);
+    char.get_hit.fall.damage = 0;
+}
+
+// HitFallVel, the velocity a falling char bounces off the floor with
+pub const HIT_FALL_VEL_SCTRL: &'static str = "hitfallvel";
+fn hit_fall_vel(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    let fall = char.get_hit.fall;
+    if let Some(x) = fall.x_vel {
+        char.velocity.x = x;
+    }
+    char.velocity.y = fall.y_vel;
+}
+
+// HitFallSet. value -1 leaves whether the char falls alone, 0 and 1 set it.
+#[derive(Clone)]
+pub struct HitFallSetArgs {
+    value: Expression,
+    x_vel: Option<Expression>,
+    y_vel: Option<Expression>,
+}
+
+pub const HIT_FALL_SET_SCTRL: &'static str = "hitfallset";
+fn hit_fall_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::HitFallSet(args) = args {
+        let fall = &mut char.get_hit.fall;
+        match args.value.evaluate_int(ctx) {
+            -1 => {}
+            value => fall.fall = value != 0,
+        }
+        if let Some(x) = args.x_vel {
+            fall.x_vel = Some(x.evaluate_float(ctx));
+        }
+        if let Some(y) = args.y_vel {
+            fall.y_vel = y.evaluate_float(ctx);
+        }
+    }
+}
+
+// FallEnvShake, the screen shake of the HitDef that hit the char. It only
+// shakes once.
+pub const FALL_ENV_SHAKE_SCTRL: &'static str = "fallenvshake";
+fn fall_env_shake(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    let env_shake = char.get_hit.fall.env_shake;
+    if env_shake.time > 0 {
+        char.screen_effects.push(ScreenEffect::EnvShake(env_shake));
+        char.get_hit.fall.env_shake.time = 0;
+    }
+}
+
+// HitBy, NotHitBy
+#[derive(Clone)]
+pub struct HitByArgs {
+    slot: usize,
+    attr: AttrSet,
+    time: Expression,
+    only: bool,
+}
+
+pub const HIT_BY_SCTRL: &'static str = "hitby";
+pub const NOT_HIT_BY_SCTRL: &'static str = "nothitby";
+fn hit_by(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::HitBy(args) = args {
+        char.hit_by[args.slot] = Some(HitBy {
+            attr: args.attr,
+            time: args.time.evaluate_int(ctx),
+            only: args.only,
+        });
+    }
+}
+
+// HitOverride
+#[derive(Clone)]
+pub struct HitOverrideArgs {
+    attr: AttrSet,
+    state_no: Expression,
+    slot: Expression,
+    time: Expression,
+    force_air: Expression,
+}
+
+pub const HIT_OVERRIDE_SCTRL: &'static str = "hitoverride";
+fn hit_override(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::HitOverride(args) = args {
+        let slot = args.slot.evaluate_int(ctx);
+        if slot < 0 || slot as usize >= HIT_OVERRIDE_SLOTS {
+            eprintln!(
+                "{}: slot {} is out of range, there are {}",
+                HIT_OVERRIDE_SCTRL, slot, HIT_OVERRIDE_SLOTS
+            );
+            return;
+        }
+        char.hit_overrides[slot as usize] = Some(HitOverride {
+            attr: args.attr,
+            state_no: args.state_no.evaluate_int(ctx),
+            time: args.time.evaluate_int(ctx),
+            force_air: args.force_air.evaluate_boolean(ctx),
+        });
+    }
+}
+
+// ReversalDef, see hit::apply_reversal
+#[derive(Clone)]
+pub struct ReversalDefArgs {
+    attr: AttrSet,
+    pause_time: (Expression, Expression),
+    p1_state: Option<Expression>,
+    p2_state: Option<Expression>,
+}
+
+pub const REVERSAL_DEF_SCTRL: &'static str = "reversaldef";
+fn reversal_def(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::ReversalDef(args) = args {
+        let (p1, p2) = args.pause_time;
+        char.reversal_def = Some(ReversalDef {
+            attr: args.attr,
+            pause_time: (p1.evaluate_int(ctx), p2.evaluate_int(ctx)),
+            p1_state: args.p1_state.map(|state| state.evaluate_int(ctx)),
+            p2_state: args.p2_state.map(|state| state.evaluate_int(ctx)),
+        });
+    }
+}
+
+// HitDef, the attack is resolved against the other chars' boxes once every
+// char has moved, see hit::resolve_hits. The parameters that are None
+// default to others'.
+#[derive(Clone)]
+pub struct HitDefArgs {
+    attr: AttackAttr,
+    hit_flag: HitFlags,
+    guard_flag: HitFlags,
+    id: Expression,
+    chain_id: Expression,
+    no_chain_id: [Expression; 2],
+    hit_once: Expression,
+    damage: Expression,
+    guard_damage: Expression,
+    kill: Expression,
+    guard_kill: Expression,
+    num_hits: Expression,
+    pause_time: (Expression, Expression),
+    guard_pause_time: Option<(Expression, Expression)>, // pausetime
+    anim_type: AnimType,
+    air_anim_type: AnimType,
+    fall_anim_type: AnimType,
+    ground_type: HitType,
+    air_type: HitType,
+    ground_slide_time: Expression,
+    guard_slide_time: Option<Expression>, // ground.slidetime
+    ground_hit_time: Expression,
+    guard_hit_time: Option<Expression>, // guard.slidetime
+    air_hit_time: Expression,
+    guard_ctrl_time: Option<Expression>, // guard.slidetime
+    air_guard_ctrl_time: Option<Expression>, // guard.ctrltime
+    y_accel: Expression,
+    ground_velocity: (Expression, Expression),
+    guard_velocity: Option<Expression>, // ground.velocity's x
+    air_velocity: (Expression, Expression),
+    air_guard_velocity: Option<(Expression, Expression)>, // air.velocity * (1.5, .5)
+    down_velocity: Option<(Expression, Expression)>,      // air.velocity
+    down_hit_time: Expression,
+    down_bounce: Expression,
+    air_juggle: Option<Expression>,
+    p1_state: Option<Expression>,
+    p2_state: Option<Expression>,
+    p2_get_p1_state: Expression,
+    force_stand: Option<Expression>, // whether ground.velocity has a y
+    fall: Expression,
+    fall_x_vel: Option<Expression>,
+    fall_y_vel: Expression,
+    fall_recover: Expression,
+    fall_recover_time: Expression,
+    fall_damage: Expression,
+    fall_kill: Expression,
+    fall_env_shake: EnvShakeArgs,
+    air_fall: Option<Expression>, // fall
+    force_no_fall: Expression,
+}
+
+pub const HIT_DEF_SCTRL: &'static str = "hitdef";
+fn hit_def(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::HitDef(args) = args {
+        let vec2 = |(x, y): &(Expression, Expression)| {
+            Vec2::new(x.evaluate_float(ctx), y.evaluate_float(ctx))
+        };
+        let ints = |(p1, p2): &(Expression, Expression)| {
+            (p1.evaluate_int(ctx), p2.evaluate_int(ctx))
+        };
+        let or_int = |expn: &Option<Expression>, default: i32| {
+            expn.as_ref().map_or(default, |expn| expn.evaluate_int(ctx))
+        };
+        let pause_time = ints(&args.pause_time);
+        let ground_slide_time = args.ground_slide_time.evaluate_int(ctx);
+        let guard_slide_time = or_int(&args.guard_slide_time, ground_slide_time);
+        let guard_ctrl_time = or_int(&args.guard_ctrl_time, guard_slide_time);
+        let ground_velocity = vec2(&args.ground_velocity);
+        let air_velocity = vec2(&args.air_velocity);
+        let fall = args.fall.evaluate_boolean(ctx);
+        char.hit_def = Some(HitDef {
+            attr: args.attr,
+            hit_flag: args.hit_flag,
+            guard_flag: args.guard_flag,
+            id: args.id.evaluate_int(ctx),
+            chain_id: args.chain_id.evaluate_int(ctx),
+            no_chain_id: [0, 1].map(|i| args.no_chain_id[i].evaluate_int(ctx)),
+            hit_once: args.hit_once.evaluate_boolean(ctx),
+            damage: args.damage.evaluate_int(ctx),
+            guard_damage: args.guard_damage.evaluate_int(ctx),
+            kill: args.kill.evaluate_boolean(ctx),
+            guard_kill: args.guard_kill.evaluate_boolean(ctx),
+            num_hits: args.num_hits.evaluate_int(ctx),
+            pause_time,
+            guard_pause_time: args.guard_pause_time.as_ref().map_or(pause_time, ints),
+            anim_type: args.anim_type,
+            air_anim_type: args.air_anim_type,
+            fall_anim_type: args.fall_anim_type,
+            ground_type: args.ground_type,
+            air_type: args.air_type,
+            ground_slide_time,
+            guard_slide_time,
+            ground_hit_time: args.ground_hit_time.evaluate_int(ctx),
+            guard_hit_time: or_int(&args.guard_hit_time, guard_slide_time),
+            air_hit_time: args.air_hit_time.evaluate_int(ctx),
+            guard_ctrl_time,
+            air_guard_ctrl_time: or_int(&args.air_guard_ctrl_time, guard_ctrl_time),
+            y_accel: args.y_accel.evaluate_float(ctx),
+            ground_velocity,
+            guard_velocity: args
+                .guard_velocity
+                .as_ref()
+                .map_or(ground_velocity.x, |x| x.evaluate_float(ctx)),
+            air_velocity,
+            air_guard_velocity: args
+                .air_guard_velocity
+                .as_ref()
+                .map_or(air_velocity * Vec2::new(1.5, 0.5), vec2),
+            down_velocity: args.down_velocity.as_ref().map_or(air_velocity, vec2),
+            down_hit_time: args.down_hit_time.evaluate_int(ctx),
+            down_bounce: args.down_bounce.evaluate_boolean(ctx),
+            air_juggle: args.air_juggle.map(|juggle| juggle.evaluate_int(ctx)),
+            p1_state: args.p1_state.map(|state| state.evaluate_int(ctx)),
+            p2_state: args.p2_state.map(|state| state.evaluate_int(ctx)),
+            p2_get_p1_state: args.p2_get_p1_state.evaluate_boolean(ctx),
+            force_stand: args
+                .force_stand
+                .map_or(ground_velocity.y != 0.0, |stand| stand.evaluate_boolean(ctx)),
+            fall: Fall {
+                fall,
+                damage: args.fall_damage.evaluate_int(ctx),
+                x_vel: args.fall_x_vel.map(|x| x.evaluate_float(ctx)),
+                y_vel: args.fall_y_vel.evaluate_float(ctx),
+                recover: args.fall_recover.evaluate_boolean(ctx),
+                recover_time: args.fall_recover_time.evaluate_int(ctx),
+                kill: args.fall_kill.evaluate_boolean(ctx),
+                env_shake: args.fall_env_shake.evaluate(ctx),
+                ..Fall::default()
+            },
+            air_fall: args.air_fall.map_or(fall, |fall| fall.evaluate_boolean(ctx)),
+            force_no_fall: args.force_no_fall.evaluate_boolean(ctx),
+        });
+    }
+}
+
+// PalFX, BGPalFX, AllPalFX
+#[derive(Clone)]
+pub struct PalFxArgs {
+    time: Expression,
+    add: [Expression; 3],
+    mul: [Expression; 3],
+    sin_add: [Expression; 4], // r, g, b, period
+    invert: Expression,
+    color: Expression,
+}
+
+impl PalFxArgs {
+    fn evaluate(&self, ctx: &TriggerContext) -> PalFx {
+        let ints = |expressions: &[Expression; 3]| {
+            [0, 1, 2].map(|i| expressions[i].evaluate_int(ctx))
+        };
+        let [r, g, b, period] = &self.sin_add;
+        PalFx {
+            time: self.time.evaluate_int(ctx),
+            add: ints(&self.add),
+            mul: ints(&self.mul),
+            sin_add: [r.evaluate_int(ctx), g.evaluate_int(ctx), b.evaluate_int(ctx)],
+            sin_period: period.evaluate_int(ctx),
+            invert: self.invert.evaluate_boolean(ctx),
+            color: self.color.evaluate_int(ctx).clamp(0, 256),
+            elapsed: 0,
+        }
+    }
+}
+
+pub const PAL_FX_SCTRL: &'static str = "palfx";
+fn pal_fx(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::PalFx(args) = args {
+        char.pal_fx = Some(args.evaluate(ctx));
+    }
+}
+
+pub const BG_PAL_FX_SCTRL: &'static str = "bgpalfx";
+fn bg_pal_fx(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::PalFx(args) = args {
+        char.screen_effects.push(ScreenEffect::BgPalFx(args.evaluate(ctx)));
+    }
+}
+
+pub const ALL_PAL_FX_SCTRL: &'static str = "allpalfx";
+fn all_pal_fx(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::PalFx(args) = args {
+        char.screen_effects.push(ScreenEffect::AllPalFx(args.evaluate(ctx)));
+    }
+}
+
+// AfterImage, AfterImageTime
+#[derive(Clone)]
+pub struct AfterImageArgs {
+    time: Expression,
+    length: Expression,
+    time_gap: Expression,
+    frame_gap: Expression,
+    color: Expression,
+    invert: Expression,
+    bright: [Expression; 3],
+    contrast: [Expression; 3],
+    post_bright: [Expression; 3],
+    add: [Expression; 3],
+    mul: [Expression; 3],
+    trans: Trans,
+}
+
+pub const AFTER_IMAGE_SCTRL: &'static str = "afterimage";
+fn after_image(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::AfterImage(args) = args {
+        let ints = |expressions: &[Expression; 3]| {
+            [0, 1, 2].map(|i| expressions[i].evaluate_int(ctx))
+        };
+        char.after_image = Some(AfterImage {
+            time: args.time.evaluate_int(ctx),
+            length: args.length.evaluate_int(ctx).max(0) as usize,
+            time_gap: args.time_gap.evaluate_int(ctx),
+            frame_gap: args.frame_gap.evaluate_int(ctx).max(1) as usize,
+            color: args.color.evaluate_int(ctx).clamp(0, 256),
+            invert: args.invert.evaluate_boolean(ctx),
+            bright: ints(&args.bright),
+            contrast: ints(&args.contrast),
+            post_bright: ints(&args.post_bright),
+            add: ints(&args.add),
+            mul: [0, 1, 2].map(|i| args.mul[i].evaluate_float(ctx)),
+            trans: args.trans,
+            ..AfterImage::default()
+        });
+    }
+}
+
+// 0 turns the after images off
+pub const AFTER_IMAGE_TIME_SCTRL: &'static str = "afterimagetime";
+fn after_image_time(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::AfterImageTime(time) = args {
+        let time = time.evaluate_int(ctx);
+        char.after_image = match char.after_image.take() {
+            Some(_) if time == 0 => None,
+            Some(after_image) => Some(AfterImage { time, ..after_image }),
+            None => None,
+        };
+    }
+}
+
+// EnvShake
+#[derive(Clone)]
+pub struct EnvShakeArgs {
+    time: Expression,
+    freq: Expression,
+    ampl: Expression,
+    phase: Option<Expression>, // 90 for freqs from 90 up, 0 below
+}
+
+impl EnvShakeArgs {
+    fn evaluate(&self, ctx: &TriggerContext) -> EnvShake {
+        let freq = self.freq.evaluate_float(ctx).clamp(0.0, 180.0);
+        let phase = match &self.phase {
+            Some(phase) => phase.evaluate_float(ctx),
+            None if freq >= 90.0 => 90.0,
+            None => 0.0,
+        };
+        EnvShake {
+            time: self.time.evaluate_int(ctx),
+            freq,
+            ampl: self.ampl.evaluate_float(ctx),
+            phase,
+        }
+    }
+}
+
+pub const ENV_SHAKE_SCTRL: &'static str = "envshake";
+fn env_shake(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::EnvShake(args) = args {
+        char.screen_effects.push(ScreenEffect::EnvShake(args.evaluate(ctx)));
+    }
+}
+
+// EnvColor
+#[derive(Clone)]
+pub struct EnvColorArgs {
+    color: [Expression; 3],
+    time: Expression,
+    under: Expression,
+}
+
+pub const ENV_COLOR_SCTRL: &'static str = "envcolor";
+fn env_color(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::EnvColor(args) = args {
+        let color = [0, 1, 2].map(|i| args.color[i].evaluate_int(ctx).clamp(0, 255) as u8);
+        char.screen_effects.push(ScreenEffect::EnvColor(EnvColor {
+            color,
+            time: args.time.evaluate_int(ctx),
+            under: args.under.evaluate_boolean(ctx),
+        }));
+    }
+}
+
+// Trans, only for the tick it's in
+pub const TRANS_SCTRL: &'static str = "trans";
+fn trans(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Trans(trans, alpha) = args {
+        char.trans = match (trans, alpha) {
+            (Trans::AddAlpha(..), Some((source, dest))) => Trans::AddAlpha(
+                source.evaluate_int(ctx).clamp(0, 256),
+                dest.evaluate_int(ctx).clamp(0, 256),
+            ),
+            (trans, _) => trans,
+        };
+    }
+}
+
+// AngleDraw draws the char rotated by its angle this tick, AngleSet,
+// AngleAdd and AngleMul change the angle.
+pub const ANGLE_DRAW_SCTRL: &'static str = "angledraw";
+fn angle_draw(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::AngleDraw(angle, scale) = args {
+        if let Some(angle) = angle {
+            char.angle = angle.evaluate_float(ctx);
+        }
+        char.angle_draw = Some(match scale {
+            Some((x, y)) => Vec2::new(x.evaluate_float(ctx), y.evaluate_float(ctx)),
+            None => Vec2::ONE,
+        });
+    }
+}
+
+pub const ANGLE_SET_SCTRL: &'static str = "angleset";
+fn angle_set(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Angle(value) = args {
+        char.angle = value.evaluate_float(ctx);
+    }
+}
+
+pub const ANGLE_ADD_SCTRL: &'static str = "angleadd";
+fn angle_add(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Angle(value) = args {
+        char.angle += value.evaluate_float(ctx);
+    }
+}
+
+pub const ANGLE_MUL_SCTRL: &'static str = "anglemul";
+fn angle_mul(char: &mut CharState, args: StateArgs, ctx: &TriggerContext) {
+    if let StateArgs::Angle(value) = args {
+        char.angle *= value.evaluate_float(ctx);
+    }
+}
@@ -1,9 +1,11 @@
 use crate::utils::ini::*;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 // None, different entities may have their own .defs.
 pub struct CharDef {
     ini_file: Ini,
+    dir: PathBuf, // file names in [Files] are relative to the .def
 }
 
 pub struct CharInfo {
@@ -31,7 +33,15 @@ pub struct CharFiles {
 impl CharDef {
     pub fn new(def_file_path: &str) -> Self {
         let ini_file = load_ini(def_file_path);
-        CharDef { ini_file }
+        let dir = Path::new(def_file_path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        CharDef { ini_file, dir }
+    }
+
+    pub fn path(&self, file_name: &str) -> String {
+        self.dir.join(file_name).to_string_lossy().to_string()
     }
 
     const INFO_KEY: &str = "info"; // [Info]  Player information
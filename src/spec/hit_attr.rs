@@ -0,0 +1,268 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::state::StateType;
+
+// Normal, special or hyper.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AttackClass {
+    N,
+    S,
+    H,
+}
+
+// Attack, projectile or throw.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AttackKind {
+    A,
+    P,
+    T,
+}
+
+// A HitDef's attr, e.g. `S, NA`: the state type the attacker is in and what
+// kind of attack it is.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AttackAttr {
+    pub state_type: StateType,
+    pub class: AttackClass,
+    pub kind: AttackKind,
+}
+
+impl FromStr for AttackAttr {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let set = AttrSet::from_str(s)?;
+        match (set.state_types.as_slice(), set.attacks.as_slice()) {
+            ([state_type], [(Some(class), kind)]) => Ok(AttackAttr {
+                state_type: *state_type,
+                class: *class,
+                kind: *kind,
+            }),
+            _ => Err("a HitDef attr is one state type and one attack"),
+        }
+    }
+}
+
+// The attrs HitBy, NotHitBy, HitOverride and ReversalDef take, e.g.
+// `SCA, AA, AP`: the state types, then the attacks. A class of A (AA, AP, AT)
+// is every class. With only state types, every attack from them.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct AttrSet {
+    state_types: Vec<StateType>,
+    attacks: Vec<(Option<AttackClass>, AttackKind)>, // None is every class
+}
+
+impl AttrSet {
+    pub fn contains(&self, attr: &AttackAttr) -> bool {
+        self.state_types.contains(&attr.state_type)
+            && (self.attacks.is_empty()
+                || self.attacks.iter().any(|(class, kind)| {
+                    *kind == attr.kind && class.map_or(true, |class| class == attr.class)
+                }))
+    }
+}
+
+impl FromStr for AttrSet {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        let mut parts = lowercase.split(',').map(str::trim);
+        let state_types = parts
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| match c {
+                's' | 'c' | 'a' => StateType::from_str(&c.to_string()),
+                _ => Err("state types are S, C and A"),
+            })
+            .collect::<Result<Vec<StateType>, _>>()?;
+        if state_types.is_empty() {
+            return Err("no state types in attr");
+        }
+
+        let attacks = parts
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let mut chars = part.chars();
+                let class = match chars.next() {
+                    Some('n') => Some(AttackClass::N),
+                    Some('s') => Some(AttackClass::S),
+                    Some('h') => Some(AttackClass::H),
+                    Some('a') => None,
+                    _ => return Err("attack classes are N, S, H and A"),
+                };
+                let kind = match (chars.next(), chars.next()) {
+                    (Some('a'), None) => AttackKind::A,
+                    (Some('p'), None) => AttackKind::P,
+                    (Some('t'), None) => AttackKind::T,
+                    _ => return Err("attack kinds are A, P and T"),
+                };
+                Ok((class, kind))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(AttrSet {
+            state_types,
+            attacks,
+        })
+    }
+}
+
+// A HitDef's hitflag or guardflag, which states of the defender it reaches.
+// H is standing, L crouching, M both, A in the air, F falling and D lying
+// down. A + only reaches chars in a get-hit state, a - only the others.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct HitFlags {
+    pub high: bool,
+    pub low: bool,
+    pub air: bool,
+    pub fall: bool,
+    pub down: bool,
+    pub get_hit: Option<bool>,
+}
+
+impl HitFlags {
+    // Whether a defender in `state_type` reaches, `get_hit` if it's being
+    // hit already and `falling` if that hit made it fall.
+    pub fn reaches(&self, state_type: StateType, get_hit: bool, falling: bool) -> bool {
+        if self.get_hit.is_some_and(|only| only != get_hit) {
+            return false;
+        }
+        match state_type {
+            StateType::S => self.high,
+            StateType::C => self.low,
+            StateType::A if get_hit && falling => self.fall,
+            StateType::A => self.air,
+            StateType::L => self.down,
+            StateType::U => false,
+        }
+    }
+}
+
+impl FromStr for HitFlags {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = HitFlags::default();
+        for c in s.trim().to_lowercase().chars() {
+            match c {
+                'h' => flags.high = true,
+                'l' => flags.low = true,
+                'm' => (flags.high, flags.low) = (true, true),
+                'a' => flags.air = true,
+                'f' => flags.fall = true,
+                'd' => flags.down = true,
+                '+' => flags.get_hit = Some(true),
+                '-' => flags.get_hit = Some(false),
+                _ => return Err("hit flags are H, L, M, A, F, D, + and -"),
+            }
+        }
+        Ok(flags)
+    }
+}
+
+// How the defender reels from a hit, GetHitVar(animtype) is the number.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum AnimType {
+    #[default]
+    Light = 0,
+    Medium = 1,
+    Hard = 2,
+    Back = 3,
+    Up = 4,
+    DiagUp = 5,
+}
+
+impl FromStr for AnimType {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "light" | "l" => Ok(AnimType::Light),
+            "medium" | "med" | "m" => Ok(AnimType::Medium),
+            "hard" | "heavy" | "h" => Ok(AnimType::Hard),
+            "back" | "b" => Ok(AnimType::Back),
+            "up" | "u" => Ok(AnimType::Up),
+            "diagup" | "d" => Ok(AnimType::DiagUp),
+            _ => Err("anim types are Light, Medium, Hard, Back, Up and DiagUp"),
+        }
+    }
+}
+
+// Where a hit lands, GetHitVar(groundtype) and (airtype) are the number.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum HitType {
+    None = 0,
+    #[default]
+    High = 1,
+    Low = 2,
+    Trip = 3,
+}
+
+impl FromStr for HitType {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" | "n" => Ok(HitType::None),
+            "high" | "h" => Ok(HitType::High),
+            "low" | "l" => Ok(HitType::Low),
+            "trip" | "t" => Ok(HitType::Trip),
+            _ => Err("hit types are None, High, Low and Trip"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(s: &str) -> AttackAttr {
+        AttackAttr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_attr_set() {
+        let set = AttrSet::from_str("SCA, AA, AP").unwrap();
+        assert!(set.contains(&attr("S, NA")));
+        assert!(set.contains(&attr("A, HP")));
+        assert!(!set.contains(&attr("C, ST")));
+
+        let set = AttrSet::from_str("sc, na").unwrap();
+        assert!(set.contains(&attr("C, NA")));
+        assert!(!set.contains(&attr("C, SA")));
+        assert!(!set.contains(&attr("A, NA")));
+
+        // no attacks is every attack
+        assert!(AttrSet::from_str("A").unwrap().contains(&attr("A, HT")));
+    }
+
+    #[test]
+    fn test_bad_attrs() {
+        assert!(AttrSet::from_str("SX, NA").is_err());
+        assert!(AttrSet::from_str("S, NX").is_err());
+        assert!(AttrSet::from_str("S, NAP").is_err());
+        assert!(AttrSet::from_str(", NA").is_err());
+        assert!(AttackAttr::from_str("SC, NA").is_err());
+        assert!(AttackAttr::from_str("S, AA").is_err());
+    }
+
+    #[test]
+    fn test_hit_flags() {
+        let flags = HitFlags::from_str("MAF").unwrap();
+        assert!(flags.reaches(StateType::S, false, false));
+        assert!(flags.reaches(StateType::C, true, false));
+        assert!(flags.reaches(StateType::A, true, true));
+        assert!(!flags.reaches(StateType::L, true, false));
+
+        // only the air, and no one falling in it
+        let flags = HitFlags::from_str("a").unwrap();
+        assert!(flags.reaches(StateType::A, false, false));
+        assert!(!flags.reaches(StateType::A, true, true));
+        assert!(!flags.reaches(StateType::S, false, false));
+
+        let flags = HitFlags::from_str("MA+").unwrap();
+        assert!(!flags.reaches(StateType::S, false, false));
+        assert!(flags.reaches(StateType::S, true, false));
+        assert!(HitFlags::from_str("MX").is_err());
+        assert_eq!(HitFlags::from_str("").unwrap(), HitFlags::default());
+    }
+}
@@ -1,10 +1,13 @@
 use std::fs;
 
+pub mod air;
 pub mod cmd;
 pub mod cns;
+pub mod config;
 pub mod constants;
 pub mod controllers;
 pub(crate) mod def;
+pub mod hit_attr;
 pub mod state;
 pub mod triggers;
 
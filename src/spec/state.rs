@@ -1,36 +1,39 @@
 use std::str::FromStr;
 
+use serde::{Deserialize, Serialize};
+
 use super::controllers::StateArgs;
-use super::triggers::{Condition, ExpressionContext};
+use super::triggers::{Condition, Expression, TriggerContext};
 use crate::game::char::CharState;
 
 pub struct StateDef {
     pub state_type: StateType,
     pub move_type: MoveType,
     pub physics: Physics,
-    pub anim: Option<i32>,
-    pub velset: Option<(i32, i32)>,
-    pub ctrl: Option<bool>,
-    pub power_add: Option<i32>,
-    pub juggle: Option<i32>,
-    pub face_p2: bool,
-    pub hit_def_persist: bool,
-    pub move_hit_persist: bool,
-    pub hit_count_persist: bool,
-    pub spr_priority: Option<u8>,
+    // evaluated when the state is entered
+    pub anim: Option<Expression>,
+    pub velset: Option<(Expression, Expression)>,
+    pub ctrl: Option<Expression>,
+    pub power_add: Option<Expression>,
+    pub juggle: Option<Expression>,
+    pub face_p2: Option<Expression>,
+    pub hit_def_persist: Option<Expression>,
+    pub move_hit_persist: Option<Expression>,
+    pub hit_count_persist: Option<Expression>,
+    pub spr_priority: Option<Expression>,
     pub states: Vec<State>,
 }
 
 pub struct State {
     pub label: String,
-    pub controller: Box<dyn Fn(&mut CharState, StateArgs, &ExpressionContext)>,
+    pub controller: Box<dyn Fn(&mut CharState, StateArgs, &TriggerContext)>,
     pub args: StateArgs,
     pub ignore_hit_pause: bool,
     pub persistency: i32,
     pub trigger_handler: TriggerHandler,
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum StateType {
     S,
     C,
@@ -71,6 +74,7 @@ impl Default for StateType {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum MoveType {
     A,
     I,
@@ -78,6 +82,17 @@ pub enum MoveType {
     U,
 }
 
+impl ToString for MoveType {
+    fn to_string(&self) -> String {
+        match self {
+            MoveType::A => "a".to_string(),
+            MoveType::I => "i".to_string(),
+            MoveType::H => "h".to_string(),
+            MoveType::U => "u".to_string(),
+        }
+    }
+}
+
 impl FromStr for MoveType {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -97,7 +112,7 @@ impl Default for MoveType {
     }
 }
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum Physics {
     S,
     C,
@@ -132,7 +147,7 @@ pub struct TriggerHandler {
 }
 
 impl TriggerHandler {
-    pub fn evaluate(&self, ctx: &ExpressionContext) -> bool {
+    pub fn evaluate(&self, ctx: &TriggerContext) -> bool {
         // Check all TriggerAll conditions
         if self.triggerall.is_some() {
             if !self.triggerall.as_ref().unwrap().evaluate(ctx) {
@@ -150,7 +165,7 @@ pub struct Trigger {
 }
 
 impl Trigger {
-    fn evaluate(&self, ctx: &ExpressionContext) -> bool {
+    fn evaluate(&self, ctx: &TriggerContext) -> bool {
         for condition in &self.conditions {
             if !condition.evaluate(ctx) {
                 return false;
@@ -173,7 +188,7 @@ pub struct TriggerSet {
 }
 
 impl TriggerSet {
-    fn evaluate(&self, ctx: &ExpressionContext) -> bool {
+    fn evaluate(&self, ctx: &TriggerContext) -> bool {
         // OR logic between triggers, AND logic within a trigger (same as before)
         for trigger in &self.triggers {
             if trigger.evaluate(ctx) {
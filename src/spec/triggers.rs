@@ -1,16 +1,117 @@
+use std::cell::Cell;
 use std::collections::HashSet;
 use std::ops::Index;
+use std::sync::{Arc, Mutex};
 
-use super::state::StateType;
-use crate::game::char::{CharState, CharSystem};
+use super::state::{MoveType, StateType};
+use crate::game::animation::Animator;
+use crate::game::char::{CharState, CharSystem, NUM_FVARS, NUM_SYS_VARS, NUM_VARS};
+use crate::game::get_hit::GET_HIT_VAR_NAMES;
+use crate::game::random::Rng;
 use crate::spec::{cmd::CommandList, constants::char_constants::*};
+use crate::utils::warn_once;
 use evalexpr::*;
-use ggez::Context;
 use regex::Regex;
 
 pub struct ExpressionContext {
     pub context: HashMapContext,
     pub screen_width: f32,
+    assignments: Arc<Mutex<Vec<Assignment>>>,
+    targets: HashMapContext, // see update_targets
+}
+
+// A `var(n) := value` from an expression. The expression can't get at the
+// char, so these wait here until apply_assignments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assignment {
+    Var(usize, i32),
+    FVar(usize, f32),
+    SysVar(usize, i32),
+    SysFVar(usize, f32),
+}
+
+// An ExpressionContext together with the battle's rng, which stays in the
+// BattleState and is only lent out for the evaluation (see
+// StateManager::update).
+pub struct TriggerContext<'a> {
+    values: &'a ExpressionContext,
+    rng: &'a Cell<Rng>,
+}
+
+impl TriggerContext<'_> {
+    // For controllers that roll, e.g. VarRandom.
+    pub fn random(&self, min: i32, max: i32) -> i32 {
+        let mut rng = self.rng.get();
+        let value = rng.range(min, max);
+        self.rng.set(rng);
+        value
+    }
+
+    pub fn get_float(&self, identifier: &str) -> Option<f32> {
+        self.values.get_float(identifier)
+    }
+}
+
+impl Context for TriggerContext<'_> {
+    fn get_value(&self, identifier: &str) -> Option<&Value> {
+        if identifier.starts_with(TARGET_TRIGGER) {
+            return self.values.targets.get_value(identifier);
+        }
+        self.values.context.get_value(identifier)
+    }
+
+    fn call_function(&self, identifier: &str, argument: &Value) -> EvalexprResult<Value> {
+        match identifier {
+            // replaces evalexpr's own random(), which isn't deterministic
+            RANDOM_TRIGGER => Ok(Value::Int(self.random(0, 999) as i64)),
+            _ if identifier.starts_with(TARGET_TRIGGER) => {
+                self.values.targets.call_function(identifier, argument)
+            }
+            _ => self.values.context.call_function(identifier, argument),
+        }
+    }
+
+    fn are_builtin_functions_disabled(&self) -> bool {
+        self.values.context.are_builtin_functions_disabled()
+    }
+
+    fn set_builtin_functions_disabled(&mut self, _disabled: bool) -> EvalexprResult<()> {
+        Err(EvalexprError::ContextNotMutable)
+    }
+}
+
+// A trigger that reads the animator as it was when the context was updated.
+// Asking for an element the action doesn't have is an error, which the
+// expression reports.
+fn anim_function(animator: Animator, trigger: fn(&Animator, i64) -> Option<i64>) -> Function {
+    Function::new(move |argument| {
+        let argument = argument.as_int()?;
+        match trigger(&animator, argument) {
+            Some(value) => Ok(Value::Int(value)),
+            None => Err(EvalexprError::CustomMessage(format!(
+                "action {} has no element for {}",
+                animator.get_anim_no(),
+                argument
+            ))),
+        }
+    })
+}
+
+fn assign_function(
+    assignments: Arc<Mutex<Vec<Assignment>>>,
+    assignment: fn(usize, f64) -> Assignment,
+) -> Function {
+    Function::new(move |argument| {
+        let arguments = argument.as_fixed_len_tuple(2)?;
+        let idx = arguments[0].as_int()?;
+        let value = arguments[1].as_number()?;
+        // a negative index wraps to something that's reported out of range
+        assignments
+            .lock()
+            .unwrap()
+            .push(assignment(idx as usize, value));
+        Ok(arguments[1].clone())
+    })
 }
 
 impl ExpressionContext {
@@ -22,7 +123,7 @@ impl ExpressionContext {
             Function::new(|args| {
                 let arguments = args.as_tuple()?;
 
-                if let Ok(condition_true) = arguments[0].as_boolean() {
+                if let Some(condition_true) = truthy(&arguments[0]) {
                     if condition_true {
                         Ok(arguments[1].clone())
                     } else {
@@ -34,6 +135,15 @@ impl ExpressionContext {
             }),
         );
 
+        // `!` for ints too, see convert_not_syntax
+        context.set_function(
+            "not".to_string(),
+            Function::new(|argument| match truthy(argument) {
+                Some(val) => Ok(Value::Boolean(!val)),
+                None => Err(EvalexprError::expected_boolean(argument.clone())),
+            }),
+        );
+
         // Todo, take user's configuration and actually do a proper conversion for this
         context.set_function(
             "720p".to_string(),
@@ -61,10 +171,55 @@ impl ExpressionContext {
             }),
         );
 
+        let assignments = Arc::new(Mutex::new(Vec::new()));
+        let assign_functions: [(&str, fn(usize, f64) -> Assignment); 4] = [
+            ("var_assign", |idx, val| Assignment::Var(idx, val as i32)),
+            ("fvar_assign", |idx, val| Assignment::FVar(idx, val as f32)),
+            ("sysvar_assign", |idx, val| Assignment::SysVar(idx, val as i32)),
+            ("sysfvar_assign", |idx, val| Assignment::SysFVar(idx, val as f32)),
+        ];
+        for (name, assignment) in assign_functions {
+            context.set_function(
+                name.to_string(),
+                assign_function(assignments.clone(), assignment),
+            );
+        }
+
         Self {
             context,
             screen_width,
+            assignments,
+            targets: HashMapContext::new(),
+        }
+    }
+
+    // Writes what the expressions evaluated since the last call assigned with
+    // `:=` to the char.
+    pub fn apply_assignments(&mut self, char: &mut CharState) {
+        let assignments: Vec<Assignment> = self.assignments.lock().unwrap().drain(..).collect();
+        if assignments.is_empty() {
+            return;
+        }
+        for assignment in assignments {
+            match assignment {
+                Assignment::Var(idx, val) => char.set_int_var(idx, val),
+                Assignment::FVar(idx, val) => char.set_float_var(idx, val),
+                Assignment::SysVar(idx, val) => char.set_sys_var(idx, val),
+                Assignment::SysFVar(idx, val) => char.set_sys_fvar(idx, val),
+            }
         }
+        self.update_vars(char);
+    }
+
+    // What expressions are evaluated against, rolling from `rng`.
+    pub fn with_rng<'a>(&'a self, rng: &'a Cell<Rng>) -> TriggerContext<'a> {
+        TriggerContext { values: self, rng }
+    }
+
+    // Reads back a value, e.g. a char constant put in by set_char_constants.
+    pub fn get_float(&self, identifier: &str) -> Option<f32> {
+        let value = self.context.get_value(identifier)?;
+        value.as_number().ok().map(|f| f as f32)
     }
 
     pub fn insert_float(&mut self, identifier: &str, value: f64) {
@@ -94,62 +249,13 @@ impl ExpressionContext {
                 ConstantValue::Float(f) => Value::Float(f as f64),
                 ConstantValue::Int(i) => Value::Int(i as i64),
             };
-            let mut new_name: String = format!("{}", key).replace("\\", "").replace("\"", "");
-            self.context.set_value(new_name, value);
+            self.context.set_value(key.clone(), value);
         }
         dbg!(&self.context);
     }
 
     fn update_values(&mut self, char: &CharState) {
-        self.context
-            .set_value(ANIM_TRIGGER.to_string(), Value::Int(anim(char) as i64));
-        self.context
-            .set_value(ANIM_TIME_TRIGGER.to_string(), Value::Int(anim_time(char)));
-
-        self.context
-            .set_value(TIME_TRIGGER.to_string(), Value::Int(time(char) as i64));
-
-        self.context
-            .set_value(VEL_X_TRIGGER.to_string(), Value::Float(vel_x(char) as f64));
-        self.context
-            .set_value(VEL_Y_TRIGGER.to_string(), Value::Float(vel_y(char) as f64));
-
-        self.context
-            .set_value(POS_X_TRIGGER.to_string(), Value::Float(pos_x(char) as f64));
-        self.context
-            .set_value(POS_Y_TRIGGER.to_string(), Value::Float(pos_y(char) as f64));
-
-        self.context
-            .set_value(ALIVE_TRIGGER.to_string(), Value::Boolean(alive(char) != 0));
-
-        self.context.set_value(
-            STATENO_TRIGGER.to_string(),
-            Value::Int(stateno(char) as i64),
-        );
-        self.context.set_value(
-            STATE_TYPE_TRIGGER.to_string(),
-            Value::String(state_type(char).to_string()),
-        );
-
-        self.context.set_value(
-            IN_GAURD_DIST_TRIGGER.to_string(),
-            Value::Boolean(in_gaurd_dist(char)),
-        );
-
-        self.context
-            .set_value(CTRL_TRIGGER.to_string(), Value::Boolean(ctrl(char) != 0));
-
-        self.context.set_value(
-            PREV_STATE_NO_TRIGGER.to_string(),
-            Value::Int(prev_state_no(char) as i64),
-        );
-
-        self.context.set_value(
-            MOVE_CONTACT_TRIGGER.to_string(),
-            Value::Boolean(move_contact(char) != 0),
-        );
-
-        self.context.set_value(ANIM_ELEM_TRIGGER.to_string(), Value::Int(anim_elem(char) as i64)); 
+        set_values(&mut self.context, "", char);
     }
 
     fn update_commands(&mut self, char: &CharState, command_list: &CommandList) {
@@ -161,24 +267,26 @@ impl ExpressionContext {
     }
 
     fn update_vars(&mut self, char: &CharState) {
-        for i in 0..6 {
-            self.context.set_value(
-                format!("sysvar_{:?}", i as usize),
-                Value::Int(char.sys_var(i as usize) as i64),
-            );
-        }
-        for i in 0..60 {
-            self.context.set_value(
-                format!("var_{}", i as usize),
-                Value::Int(char.get_int_var(i as usize) as i64),
-            );
+        set_vars(&mut self.context, "", char);
+    }
 
-            self.context.set_value(
-                format!("fvar_{}", i as usize),
-                Value::Float(char.get_flaot_var(i as usize) as f64),
-            );
+    // What `target, Life` and `target(id), StateNo` read: the first of the
+    // char's `targets`, and the first with each id. They're kept apart from
+    // the char's own values so a target that's gone doesn't leave any behind.
+    pub fn update_targets(&mut self, targets: &[(i32, &CharState)]) {
+        self.targets = HashMapContext::new();
+        for (i, &(id, target)) in targets.iter().enumerate().rev() {
+            let mut prefixes = vec![format!("{}{}_", TARGET_TRIGGER, id)];
+            if i == 0 {
+                prefixes.push(format!("{}_", TARGET_TRIGGER));
+            }
+            for prefix in prefixes {
+                set_values(&mut self.targets, &prefix, target);
+                set_vars(&mut self.targets, &prefix, target);
+            }
         }
     }
+
     pub fn mid_frame_update(&mut self, char: &CharState) {
         self.update_values(char);
         self.update_vars(char);
@@ -191,6 +299,124 @@ impl ExpressionContext {
     }
 }
 
+// The values the triggers read off `char`, named with `prefix` for a
+// redirection, e.g. target_life for `target, Life`.
+fn set_values(context: &mut HashMapContext, prefix: &str, char: &CharState) {
+    let name = |trigger: &str| format!("{}{}", prefix, trigger);
+    context.set_value(name(ANIM_TRIGGER), Value::Int(anim(char) as i64));
+    context.set_value(name(ANIM_TIME_TRIGGER), Value::Int(anim_time(char)));
+
+    context.set_value(name(TIME_TRIGGER), Value::Int(time(char) as i64));
+
+    context.set_value(name(VEL_X_TRIGGER), Value::Float(vel_x(char) as f64));
+    context.set_value(name(VEL_Y_TRIGGER), Value::Float(vel_y(char) as f64));
+
+    context.set_value(name(POS_X_TRIGGER), Value::Float(pos_x(char) as f64));
+    context.set_value(name(POS_Y_TRIGGER), Value::Float(pos_y(char) as f64));
+
+    context.set_value(name(ALIVE_TRIGGER), Value::Boolean(alive(char) != 0));
+    context.set_value(name(LIFE_TRIGGER), Value::Int(life(char) as i64));
+
+    context.set_value(name(STATENO_TRIGGER), Value::Int(stateno(char) as i64));
+    context.set_value(
+        name(STATE_TYPE_TRIGGER),
+        Value::String(state_type(char).to_string()),
+    );
+
+    context.set_value(
+        name(MOVE_TYPE_TRIGGER),
+        Value::String(move_type(char).to_string()),
+    );
+    context.set_value(name(POWER_TRIGGER), Value::Int(power(char) as i64));
+
+    context.set_value(
+        name(IN_GAURD_DIST_TRIGGER),
+        Value::Boolean(in_gaurd_dist(char)),
+    );
+
+    context.set_value(name(CTRL_TRIGGER), Value::Boolean(ctrl(char) != 0));
+
+    context.set_value(
+        name(PREV_STATE_NO_TRIGGER),
+        Value::Int(prev_state_no(char) as i64),
+    );
+
+    context.set_value(
+        name(MOVE_CONTACT_TRIGGER),
+        Value::Boolean(move_contact(char) != 0),
+    );
+
+    context.set_value(name(MOVE_HIT_TRIGGER), Value::Int(char.move_hit as i64));
+    context.set_value(
+        name(MOVE_GUARDED_TRIGGER),
+        Value::Int(char.move_guarded as i64),
+    );
+    context.set_value(name(HIT_COUNT_TRIGGER), Value::Int(char.hit_count as i64));
+    context.set_value(
+        name(HIT_PAUSE_TIME_TRIGGER),
+        Value::Int(char.hit_pause as i64),
+    );
+    context.set_value(
+        name(NUM_TARGET_TRIGGER),
+        Value::Int(char.targets.len() as i64),
+    );
+
+    context.set_value(
+        name(CAN_RECOVER_TRIGGER),
+        Value::Boolean(char.can_recover()),
+    );
+    context.set_value(
+        name(HIT_FALL_TRIGGER),
+        Value::Boolean(char.get_hit.fall.fall),
+    );
+    context.set_value(
+        name(HIT_OVER_TRIGGER),
+        Value::Boolean(char.get_hit.hit_time <= 0),
+    );
+    context.set_value(
+        name(HIT_SHAKE_OVER_TRIGGER),
+        Value::Boolean(!char.in_hit_pause()),
+    );
+    for var in GET_HIT_VAR_NAMES {
+        let value = match char.get_hit.get(var) {
+            Some(ConstantValue::Int(i)) => Value::Int(i as i64),
+            Some(ConstantValue::Float(f)) => Value::Float(f as f64),
+            None => continue,
+        };
+        context.set_value(name(&get_hit_var_name(var)), value);
+    }
+
+    context.set_function(
+        name(ANIM_ELEM_TIME_TRIGGER),
+        anim_function(char.animator.clone(), |animator, element_no| {
+            animator.get_anim_elem_time(element_no as i32)
+        }),
+    );
+    context.set_function(
+        name(ANIM_ELEM_NO_TRIGGER),
+        anim_function(char.animator.clone(), |animator, offset| {
+            animator.get_anim_elem_no(offset).map(i64::from)
+        }),
+    );
+}
+
+fn set_vars(context: &mut HashMapContext, prefix: &str, char: &CharState) {
+    for i in 0..NUM_SYS_VARS {
+        let sys_var = Value::Int(char.sys_var(i) as i64);
+        context.set_value(format!("{}sysvar_{}", prefix, i), sys_var);
+        let sys_fvar = Value::Float(char.sys_fvar(i) as f64);
+        context.set_value(format!("{}sysfvar_{}", prefix, i), sys_fvar);
+    }
+    for i in 0..NUM_VARS {
+        let var = Value::Int(char.get_int_var(i) as i64);
+        context.set_value(format!("{}var_{}", prefix, i), var);
+    }
+    for i in 0..NUM_FVARS {
+        let fvar = Value::Float(char.get_flaot_var(i) as f64);
+        context.set_value(format!("{}fvar_{}", prefix, i), fvar);
+    }
+}
+
 const ANIM_TRIGGER: &str = "anim";
 pub fn anim(char: &CharState) -> u64 {
     char.get_anim_no()
@@ -242,14 +468,19 @@ pub fn alive(char: &CharState) -> i32 {
     char.get_alive()
 }
 
+const LIFE_TRIGGER: &str = "life";
+pub fn life(char: &CharState) -> i32 {
+    char.get_life()
+}
+
 const STATENO_TRIGGER: &str = "stateno";
 pub fn stateno(char: &CharState) -> i32 {
     char.get_state_no()
 }
 
 const CONST_TRIGGER: &str = "const";
-pub fn constant(name: &str, char: &CharSystem) -> Option<ConstantValue> {
-    char.constant(name)
+pub fn constant(name: &str, constants: &CharConstants) -> Option<ConstantValue> {
+    constants.get(name)
 }
 
 const IN_GAURD_DIST_TRIGGER: &str = "ingaurddist";
@@ -262,6 +493,16 @@ pub fn state_type(char: &CharState) -> StateType {
     char.get_state_type()
 }
 
+const MOVE_TYPE_TRIGGER: &str = "movetype";
+pub fn move_type(char: &CharState) -> MoveType {
+    char.get_move_type()
+}
+
+const POWER_TRIGGER: &str = "power";
+pub fn power(char: &CharState) -> i32 {
+    char.power
+}
+
 const CTRL_TRIGGER: &str = "ctrl";
 pub fn ctrl(char: &CharState) -> i32 {
     char.get_ctrl()
@@ -287,11 +528,33 @@ pub fn move_contact(char: &CharState) -> i32 {
     char.get_move_contact()
 }
 
-const ANIM_ELEM_TRIGGER: &str = "animelem"; 
-pub fn anim_elem(char: &CharState) -> usize {
-    char.get_anim_element()
+const MOVE_HIT_TRIGGER: &str = "movehit";
+const MOVE_GUARDED_TRIGGER: &str = "moveguarded";
+const HIT_COUNT_TRIGGER: &str = "hitcount";
+const NUM_TARGET_TRIGGER: &str = "numtarget";
+const HIT_PAUSE_TIME_TRIGGER: &str = "hitpausetime";
+const CAN_RECOVER_TRIGGER: &str = "canrecover";
+const HIT_FALL_TRIGGER: &str = "hitfall";
+const HIT_OVER_TRIGGER: &str = "hitover";
+const HIT_SHAKE_OVER_TRIGGER: &str = "hitshakeover";
+
+// gethitvar(fall.yvel) is gethitvar_fall_yvel in the context
+const GET_HIT_VAR_TRIGGER: &str = "gethitvar";
+fn get_hit_var_name(name: &str) -> String {
+    format!("{}_{}", GET_HIT_VAR_TRIGGER, name.replace('.', "_"))
 }
 
+// Evaluated as a function so every reference rolls again.
+const RANDOM_TRIGGER: &str = "random";
+
+// `target, Life` is target_life, `target(2), Life` target2_life, see
+// convert_target_syntax
+const TARGET_TRIGGER: &str = "target";
+
+// AnimElem = n, op t is AnimElemTime(n) op t, see convert_anim_elem_syntax
+const ANIM_ELEM_TIME_TRIGGER: &str = "animelemtime";
+const ANIM_ELEM_NO_TRIGGER: &str = "animelemno";
+
 #[derive(Clone)]
 pub struct Expression {
     original_expression: String,
@@ -306,21 +569,56 @@ impl Expression {
         }
     }
 
-    pub fn evaluate_int(&self, ctx: &ExpressionContext) -> i32 {
-        self.expn.eval_int_with_context(&ctx.context).unwrap() as i32
+    // What a plain number like `ChangeAnim`'s `value = 200` comes to, without
+    // a context. None for anything that has to be evaluated.
+    pub fn constant_int(&self) -> Option<i32> {
+        self.original_expression.trim().parse().ok()
     }
 
-    // This guy doesn't like "0" otherwise.
-    pub fn evaluate_float(&self, ctx: &ExpressionContext) -> f32 {
-        self.expn.eval_number_with_context(&ctx.context).unwrap() as f32
+    // An expression that doesn't evaluate is reported (once) and counts as
+    // 0, like MUGEN's bottom value.
+    fn evaluate(&self, ctx: &TriggerContext) -> Value {
+        match self.expn.eval_with_context(ctx) {
+            Ok(value) => value,
+            Err(err) => {
+                warn_once(format!("can't evaluate {}: {}", self.original_expression, err));
+                Value::Int(0)
+            }
+        }
     }
 
-    pub fn evaluate_boolean(&self, ctx: &ExpressionContext) -> bool {
-        self.expn.eval_boolean_with_context(&ctx.context).unwrap()
+    // MUGEN's typing: floats truncate where an int is wanted, and booleans
+    // are 0 or 1.
+    pub fn evaluate_int(&self, ctx: &TriggerContext) -> i32 {
+        match self.evaluate(ctx) {
+            Value::Int(i) => i as i32,
+            Value::Float(f) => f as i32,
+            Value::Boolean(b) => b as i32,
+            _ => 0,
+        }
     }
 
-    pub fn evaluate_string(&self, ctx: &ExpressionContext) -> String {
-        self.expn.eval_string_with_context(&ctx.context).unwrap()
+    pub fn evaluate_float(&self, ctx: &TriggerContext) -> f32 {
+        match self.evaluate(ctx) {
+            Value::Int(i) => i as f32,
+            Value::Float(f) => f as f32,
+            Value::Boolean(b) => b as i32 as f32,
+            _ => 0.0,
+        }
+    }
+
+    pub fn evaluate_boolean(&self, ctx: &TriggerContext) -> bool {
+        truthy(&self.evaluate(ctx)).unwrap_or(false)
+    }
+
+    pub fn evaluate_string(&self, ctx: &TriggerContext) -> String {
+        match self.expn.eval_string_with_context(ctx) {
+            Ok(string) => string,
+            Err(err) => {
+                warn_once(format!("can't evaluate {}: {}", self.original_expression, err));
+                String::new()
+            }
+        }
     }
 }
 
@@ -348,6 +646,117 @@ fn convert_const_syntax(input: &str) -> String {
     input.replace("const", "")
 }
 
+const VAR_REGEX: &str = r"(?i)\b(sysfvar|sysvar|fvar|var)\s*\(\s*(\d+)\s*\)";
+
+fn var_count(name: &str) -> usize {
+    match name {
+        "var" => NUM_VARS,
+        "fvar" => NUM_FVARS,
+        _ => NUM_SYS_VARS,
+    }
+}
+
+// var(3) becomes var_3. Reading past the end is reported and reads 0, rather
+// than failing every time the expression is evaluated.
+fn convert_var_syntax(input: &str) -> String {
+    let re = Regex::new(VAR_REGEX).unwrap();
+    re.replace_all(input, |caps: &regex::Captures| {
+        let name = caps[1].to_lowercase();
+        let idx: usize = caps[2].parse().unwrap_or(usize::MAX);
+        if idx < var_count(&name) {
+            format!("{}_{}", name, idx)
+        } else {
+            eprintln!(
+                "{}({}) is out of range, there are {}",
+                name,
+                &caps[2],
+                var_count(&name)
+            );
+            "0".to_string()
+        }
+    })
+    .into_owned()
+}
+
+// gethitvar(fall.recover) becomes gethitvar_fall_recover. Names it doesn't
+// know are reported and read 0.
+fn convert_get_hit_var_syntax(input: &str) -> String {
+    let re = Regex::new(r"(?i)\bgethitvar\s*\(\s*([a-z.]+)\s*\)").unwrap();
+    re.replace_all(input, |caps: &regex::Captures| {
+        let name = caps[1].to_lowercase();
+        if GET_HIT_VAR_NAMES.contains(&name.as_str()) {
+            get_hit_var_name(&name)
+        } else {
+            eprintln!("unknown gethitvar({})", &caps[1]);
+            "0".to_string()
+        }
+    })
+    .into_owned()
+}
+
+// AnimElem = 2 becomes animelemtime(2) = 0, true on the tick element 2
+// starts, and AnimElem = 2, >= 4 becomes animelemtime(2) >= 4.
+fn convert_anim_elem_syntax(input: &str) -> String {
+    let re = Regex::new(r"(?i)\banimelem\s*=\s*(\d+)(?:\s*,\s*(>=|<=|!=|=|<|>)\s*(-?\d+))?")
+        .unwrap();
+    re.replace_all(input, |caps: &regex::Captures| match (caps.get(2), caps.get(3)) {
+        (Some(op), Some(time)) => format!(
+            "{}({}) {} {}",
+            ANIM_ELEM_TIME_TRIGGER,
+            &caps[1],
+            op.as_str(),
+            time.as_str()
+        ),
+        _ => format!("{}({}) = 0", ANIM_ELEM_TIME_TRIGGER, &caps[1]),
+    })
+    .into_owned()
+}
+
+// `target, Life` becomes target_life and `target(2), Life` target2_life,
+// the values update_targets puts in. It runs once the triggers after the
+// comma have their own names, e.g. `target, var(1)` is target_var_1.
+fn convert_target_syntax(input: &str) -> String {
+    let re = Regex::new(r"\btarget\s*(?:\(\s*(\d+)\s*\))?\s*,\s*").unwrap();
+    re.replace_all(input, |caps: &regex::Captures| match caps.get(1) {
+        Some(id) => format!("{}{}_", TARGET_TRIGGER, id.as_str()),
+        None => format!("{}_", TARGET_TRIGGER),
+    })
+    .into_owned()
+}
+
+// IKEMEN's `var(n) := value` becomes var_assign(n, value). := binds loosest,
+// so the value runs to the end of the enclosing parentheses or argument.
+fn convert_assignment_syntax(input: &str) -> String {
+    let re = Regex::new(&format!(r"{}\s*:=", VAR_REGEX)).unwrap();
+    let mut result = input.to_string();
+    while let Some(caps) = re.captures(&result) {
+        let whole = caps.get(0).unwrap();
+        let rest = &result[whole.end()..];
+        let mut depth = 0;
+        let mut end = rest.len();
+        for (i, c) in rest.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' | ',' if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        result = format!(
+            "{}{}_assign({}, {}){}",
+            &result[..whole.start()],
+            caps[1].to_lowercase(),
+            &caps[2],
+            rest[..end].trim(),
+            &rest[end..]
+        );
+    }
+    result
+}
+
 fn convert_command_negation_syntax(input: &str) -> String {
     let input: String = input.split_whitespace().collect();
     let mut result = input
@@ -363,6 +772,104 @@ fn convert_command_negation_syntax(input: &str) -> String {
     result //+ ")"
 }
 
+// Anything non zero is true.
+fn truthy(value: &Value) -> Option<bool> {
+    match value {
+        Value::Boolean(b) => Some(*b),
+        Value::Int(i) => Some(*i != 0),
+        Value::Float(f) => Some(*f != 0.0),
+        _ => None,
+    }
+}
+
+// Splits a parameter with several components, e.g. `velset = x, y`, on the
+// commas that aren't inside a function call.
+pub fn split_components(expn: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in expn.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                components.push(expn[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    components.push(expn[start..].trim());
+    components
+}
+
+// evalexpr's `!` only takes booleans, `!time`, `!(var_1 & 2)` and
+// `!animelemtime(2)` have to work as well.
+fn convert_not_syntax(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '!' || chars.get(i + 1) == Some(&'=') {
+            result.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let mut start = i + 1;
+        while chars.get(start).is_some_and(|c| c.is_whitespace()) {
+            start += 1;
+        }
+        // a name, a call or anything in parentheses
+        let mut end = start;
+        while chars
+            .get(end)
+            .is_some_and(|&c| c.is_alphanumeric() || c == '_' || c == '.')
+        {
+            end += 1;
+        }
+        let mut call = end;
+        while chars.get(call).is_some_and(|c| c.is_whitespace()) {
+            call += 1;
+        }
+        if chars.get(call) == Some(&'(') {
+            end = closing_paren(&chars, call);
+        }
+        if end == start {
+            result.push('!');
+            i += 1;
+            continue;
+        }
+        let operand: String = chars[start..end].iter().collect();
+        let operand = convert_not_syntax(&operand);
+        if operand.starts_with('(') {
+            result.push_str(&format!("not{}", operand));
+        } else {
+            result.push_str(&format!("not({})", operand));
+        }
+        i = end;
+    }
+    result
+}
+
+// Index just past the parenthesis matching the one at `open`, or the end of
+// the input if it's never closed.
+fn closing_paren(chars: &[char], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, &c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    chars.len()
+}
+
 pub fn sanitize_expression(exp: &str) -> String {
     let mut result = String::from(exp);
 
@@ -371,16 +878,10 @@ pub fn sanitize_expression(exp: &str) -> String {
         .replace_all(&result, "${1} > $2 && ${1} < $3")
         .to_string();
 
-    let patterns = vec![
-        ("sysvar\\((\\d+)\\)", "sysvar_$1"),
-        ("var\\((\\d+)\\)", "var_$1"),
-        ("fvar\\((\\d+)\\)", "fvar_$1"),
-    ];
-
-    for (pattern, replacement) in patterns {
-        let re = Regex::new(pattern).unwrap();
-        result = re.replace_all(&result, replacement).into_owned();
-    }
+    result = convert_assignment_syntax(&result);
+    result = convert_var_syntax(&result);
+    result = convert_get_hit_var_syntax(&result);
+    result = convert_anim_elem_syntax(&result);
 
     if result.contains("command =") {
         result = convert_command_syntax(&result);
@@ -395,6 +896,12 @@ pub fn sanitize_expression(exp: &str) -> String {
     if result.contains("const") {
         result = convert_const_syntax(&result);
     }
+    result = convert_target_syntax(&result);
+
+    let random_regex = Regex::new(r"\brandom\b(\s*\(\s*\))?").unwrap();
+    result = random_regex.replace_all(&result, "random()").to_string();
+
+    result = convert_not_syntax(&result);
 
     let mut new_val: String = result.split_whitespace().collect();
     if new_val.contains("=") {
@@ -419,6 +926,7 @@ pub fn sanitize_expression(exp: &str) -> String {
     }
     result = result.replace("!==", "!=");
     result = result.replace(">==", ">=");
+    result = result.replace("<==", "<=");
     dbg!(&result);
     result
 }
@@ -438,16 +946,16 @@ impl Condition {
         }
     }
 
-    pub fn evaluate(&self, ctx: &ExpressionContext) -> bool {
-        let result = self
-            .compiled_expression
-            .eval_with_context(&ctx.context)
-            .unwrap();
-        match result {
-            Value::Boolean(val) => return val,
-            Value::Float(val) => return val != 0.0,
-            Value::Int(val) => return val != 0,
-            _ => result.as_boolean().unwrap(), // just panic bruh, for now
+    // Like Expression::evaluate, a condition that doesn't evaluate (say,
+    // AnimElem on an element the action doesn't have) is reported once and
+    // is false.
+    pub fn evaluate(&self, ctx: &TriggerContext) -> bool {
+        match self.compiled_expression.eval_with_context(ctx) {
+            Ok(result) => truthy(&result).unwrap_or(false),
+            Err(err) => {
+                warn_once(format!("can't evaluate {}: {}", self.original_expression, err));
+                false
+            }
         }
 
         // if self.original_expression.contains("holddown") || self.original_expression.contains("statetype = C") || self.original_expression.contains("= \"x") || self.original_expression.contains("ctrl") || self.original_expression.contains("AnimTime = 0") {
@@ -461,6 +969,7 @@ impl Condition {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::game::test_utils::battle;
 
     // Expression evaluation
     #[test]
@@ -488,6 +997,208 @@ mod tests {
         assert_eq!(sanitize_expression(original), result.to_string())
     }
 
+    #[test]
+    fn test_var_out_of_range_reads_zero() {
+        assert_eq!(sanitize_expression("fvar(39) + fvar(40)"), "fvar_39 + 0");
+        assert_eq!(sanitize_expression("SysFVar(4)"), "sysfvar_4");
+    }
+
+    #[test]
+    fn test_assignment_sanitization() {
+        assert_eq!(
+            sanitize_expression("var(2) := var(1) + 1"),
+            "var_assign(2, var_1 + 1)"
+        );
+        assert_eq!(
+            sanitize_expression("(fvar(0) := 1.5) > 0 && var(3) > 0"),
+            "(fvar_assign(0, 1.5)) > 0 && var_3 > 0"
+        );
+    }
+
+    #[test]
+    fn test_assignment_is_queued() {
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        ctx.insert_int("var_1", 4);
+
+        let assign = Expression::new("var(2) := var(1) + 1");
+        assert_eq!(assign.evaluate_int(&ctx.with_rng(&Cell::new(Rng::new(0)))), 5);
+        assert_eq!(
+            *ctx.assignments.lock().unwrap(),
+            vec![Assignment::Var(2, 5)]
+        );
+    }
+
+    #[test]
+    fn test_not_sanitization() {
+        assert_eq!(sanitize_expression("!time"), "not(time)");
+        assert_eq!(sanitize_expression("stateno != 20"), "stateno != 20");
+        assert_eq!(sanitize_expression("!(var(1) > 0)"), "not(var_1 > 0)");
+        assert_eq!(
+            sanitize_expression("!(time && !(var(1) > 0)) || 1"),
+            "not(time && not(var_1 > 0)) || 1"
+        );
+        assert_eq!(sanitize_expression("! AnimElemNo(0)"), "not(animelemno(0))");
+    }
+
+    #[test]
+    fn test_split_components() {
+        assert_eq!(
+            split_components("const(velocity.run.fwd.x), 0"),
+            vec!["const(velocity.run.fwd.x)", "0"]
+        );
+        assert_eq!(
+            split_components("ifelse(var(1), 210, 200)"),
+            vec!["ifelse(var(1), 210, 200)"]
+        );
+    }
+
+    #[test]
+    fn test_mugen_typing() {
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        ctx.insert_int("var_1", 1);
+        ctx.insert_int("time", 0);
+
+        let rng = Cell::new(Rng::new(0));
+        let ctx = ctx.with_rng(&rng);
+
+        assert_eq!(Expression::new("ifelse(var(1), 210, 200)").evaluate_int(&ctx), 210);
+        assert_eq!(Expression::new("2.9").evaluate_int(&ctx), 2);
+        assert_eq!(Expression::new("!time").evaluate_int(&ctx), 1);
+        assert!(Expression::new("!time").evaluate_boolean(&ctx));
+        assert_eq!(Expression::new("1").evaluate_float(&ctx), 1.0);
+    }
+
+    #[test]
+    fn test_move_type_trigger() {
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        ctx.insert_string(MOVE_TYPE_TRIGGER, MoveType::A.to_string());
+
+        let rng = Cell::new(Rng::new(0));
+        let ctx = ctx.with_rng(&rng);
+
+        assert!(Expression::new("movetype = A").evaluate_boolean(&ctx));
+        assert!(!Expression::new("MoveType = H").evaluate_boolean(&ctx));
+    }
+
+    #[test]
+    fn test_get_hit_var_trigger() {
+        assert_eq!(
+            sanitize_expression("GetHitVar(fall.recover) && gethitvar( hittime ) > 0"),
+            "gethitvar_fall_recover && gethitvar_hittime > 0"
+        );
+        assert_eq!(sanitize_expression("gethitvar(nope)"), "0");
+
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        ctx.insert_float("gethitvar_fall_yvel", -4.5);
+        let rng = Cell::new(Rng::new(0));
+        let ctx = ctx.with_rng(&rng);
+
+        assert_eq!(Expression::new("gethitvar(fall.yvel)").evaluate_float(&ctx), -4.5);
+    }
+
+    #[test]
+    fn test_target_redirection() {
+        assert_eq!(sanitize_expression("target, Life"), "target_life");
+        assert_eq!(
+            sanitize_expression("Target(2) , StateNo"),
+            "target2_stateno"
+        );
+        assert_eq!(
+            sanitize_expression("target, var(1) > 0"),
+            "target_var_1 > 0"
+        );
+
+        let (_, mut state) = battle(3, 0);
+        state.chars[1].set_state(200);
+        state.chars[2].set_int_var(1, 7);
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        ctx.update_targets(&[(2, &state.chars[1]), (3, &state.chars[2])]);
+        let rng = Cell::new(Rng::new(0));
+        let triggers = ctx.with_rng(&rng);
+
+        let life = state.chars[1].get_life();
+        assert_eq!(
+            Expression::new("target, Life").evaluate_int(&triggers),
+            life
+        );
+        assert_eq!(
+            Expression::new("target(2), StateNo").evaluate_int(&triggers),
+            200
+        );
+        assert_eq!(
+            Expression::new("target(3), var(1)").evaluate_int(&triggers),
+            7
+        );
+        // a target that isn't there reads 0
+        assert_eq!(
+            Expression::new("target(4), StateNo").evaluate_int(&triggers),
+            0
+        );
+
+        ctx.update_targets(&[]);
+        let triggers = ctx.with_rng(&rng);
+        assert_eq!(Expression::new("target, Life").evaluate_int(&triggers), 0);
+    }
+
+    #[test]
+    fn test_anim_elem_sanitization() {
+        assert_eq!(sanitize_expression("AnimElem = 2"), "animelemtime(2) == 0");
+        assert_eq!(sanitize_expression("AnimElem = 3, >= 4"), "animelemtime(3) >= 4");
+        assert_eq!(sanitize_expression("animelem = 3, <= -1"), "animelemtime(3) <= -1");
+        assert_eq!(sanitize_expression("AnimElemNo(0) = 2"), "animelemno(0) == 2");
+    }
+
+    #[test]
+    fn test_anim_elem_trigger() {
+        let (_, mut state) = battle(1, 0);
+        let char = &mut state.chars[0];
+        char.set_animation_no(0);
+        for _ in 0..10 {
+            char.animator.update();
+        }
+        let mut ctx = ExpressionContext::new(1280.0, HashSet::new());
+        ctx.update(char);
+
+        let rng = Cell::new(Rng::new(0));
+        let ctx = ctx.with_rng(&rng);
+
+        assert!(Expression::new("AnimElem = 2").evaluate_boolean(&ctx));
+        assert!(!Expression::new("AnimElem = 1").evaluate_boolean(&ctx));
+        assert!(Expression::new("AnimElem = 1, >= 10").evaluate_boolean(&ctx));
+        assert_eq!(Expression::new("AnimElemTime(3)").evaluate_int(&ctx), -7);
+        assert_eq!(Expression::new("AnimElemNo(7)").evaluate_int(&ctx), 3);
+        // there's no 12th element, which counts as 0
+        assert_eq!(Expression::new("AnimElemTime(12)").evaluate_int(&ctx), 0);
+    }
+
+    #[test]
+    fn test_bad_string_expression_is_empty() {
+        let ctx = ExpressionContext::new(1280.0, HashSet::new());
+        let rng = Cell::new(Rng::new(0));
+        let ctx = ctx.with_rng(&rng);
+        assert_eq!(Expression::new("\"kfm\"").evaluate_string(&ctx), "kfm");
+        assert_eq!(Expression::new("1 +").evaluate_string(&ctx), "");
+        assert_eq!(Expression::new("1").evaluate_string(&ctx), "");
+    }
+
+    #[test]
+    fn test_random_sanitization() {
+        assert_eq!(sanitize_expression("Random < 500"), "random() < 500");
+    }
+
+    #[test]
+    fn test_random_trigger() {
+        let ctx = ExpressionContext::new(1280.0, HashSet::new());
+        let rng = Cell::new(Rng::new(42));
+        let ctx = ctx.with_rng(&rng);
+        let mut expected = Rng::new(42);
+        let random = Expression::new("Random");
+        assert_eq!(random.evaluate_int(&ctx), expected.range(0, 999));
+        assert_eq!(random.evaluate_int(&ctx), expected.range(0, 999));
+        // the rolls come out of the lent rng
+        assert_eq!(rng.get(), expected);
+    }
+
     #[test]
     fn test_command_eq_conversion() {
         let original = "command = \"holdfwd\"";
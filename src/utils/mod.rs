@@ -1,5 +1,8 @@
 use ini_core;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
 pub mod ini;
+pub mod serde_array;
 pub mod sprite_sheet;
 
 pub(crate) fn strip_comment_from_lines(value: &str) -> String {
@@ -17,3 +20,14 @@ pub(crate) fn strip_comment(value: &str) -> &str {
         None => value,
     }
 }
+
+// eprintln!s `message` the first time it comes up, for problems that would
+// otherwise be reported every tick.
+pub(crate) fn warn_once(message: String) {
+    static WARNED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    let mut warned = WARNED.get_or_init(Default::default).lock().unwrap();
+    if !warned.contains(&message) {
+        eprintln!("{}", message);
+        warned.insert(message);
+    }
+}
@@ -0,0 +1,24 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// serde only derives for arrays up to 32 long, the var arrays are 60.
+// Use with #[serde(with = "crate::utils::serde_array")].
+pub fn serialize<S, T, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize,
+{
+    serializer.collect_seq(array)
+}
+
+pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let values: Vec<T> = Vec::deserialize(deserializer)?;
+    let len = values.len();
+    values
+        .try_into()
+        .map_err(|_| D::Error::invalid_length(len, &"an array of the declared length"))
+}
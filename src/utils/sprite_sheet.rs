@@ -7,8 +7,6 @@ use std::{collections::HashMap, fs};
 pub struct Sprite {
     axis_x: u16,
     axis_y: u16,
-    width: u32,
-    height: u32,
     image: graphics::Image,
 }
 
@@ -38,8 +36,6 @@ impl SpriteSheet {
             );
             let sprite = Sprite {
                 image: ez_image,
-                width: image.width,
-                height: image.height,
                 axis_x: image.axis_x,
                 axis_y: image.axis_y,
             };
@@ -48,16 +44,24 @@ impl SpriteSheet {
         map
     }
 
-    pub fn get_axis(&self, group: u16, image: u16) -> (u16, u16) {
-        let sprite = self.map.get(&(group, image)).unwrap();
-        (sprite.axis_x, sprite.axis_y)
+    // None for a sprite the SFF doesn't have, which MUGEN draws as nothing.
+    pub fn get_axis(&self, group: u16, image: u16) -> Option<(u16, u16)> {
+        let sprite = self.map.get(&(group, image))?;
+        Some((sprite.axis_x, sprite.axis_y))
     }
-    pub fn get_size(&self, group: u16, image: u16) -> (u32, u32) {
-        let sprite = self.map.get(&(group, image)).unwrap();
-        (sprite.width, sprite.height)
+
+    pub fn get(&self, group: u16, image: u16) -> Option<&graphics::Image> {
+        Some(&self.map.get(&(group, image))?.image)
     }
 
-    pub fn get(&self, group: u16, image: u16) -> &graphics::Image {
-        &self.map.get(&(group, image)).unwrap().image
+    // The ones out of `sprites` the SFF doesn't have, in order.
+    pub fn missing(&self, sprites: impl IntoIterator<Item = (u16, u16)>) -> Vec<(u16, u16)> {
+        let mut missing: Vec<(u16, u16)> = sprites
+            .into_iter()
+            .filter(|sprite| !self.map.contains_key(sprite))
+            .collect();
+        missing.sort();
+        missing.dedup();
+        missing
     }
 }